@@ -0,0 +1,87 @@
+//! Упаковывает каталог `rootfs/` в USTAR-архив, который ядро встраивает как initrd.
+
+use std::{env, fs, io, path::Path};
+
+const BLOCK: usize = 512;
+
+fn main() {
+    let root = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("rootfs");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("initrd.tar");
+    println!("cargo:rerun-if-changed=rootfs");
+
+    let mut archive = Vec::new();
+    if root.is_dir() {
+        pack_dir(&root, "", &mut archive).expect("cannot pack rootfs");
+    }
+    // Конец архива — два нулевых блока
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    fs::write(&out, archive).expect("cannot write initrd.tar");
+}
+
+// Каталог пишется раньше своего содержимого, записи отсортированы по имени
+fn pack_dir(dir: &Path, prefix: &str, archive: &mut Vec<u8>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().into_string().expect("non-UTF-8 file name in rootfs");
+        let path = format!("{}{}", prefix, name);
+        // rerun-if-changed на каталоге не видит правок внутри вложенных файлов
+        println!("cargo:rerun-if-changed={}", entry.path().display());
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            archive.extend_from_slice(&header(&format!("{}/", path), mode(&meta), 0, b'5'));
+            pack_dir(&entry.path(), &format!("{}/", path), archive)?;
+        } else if meta.is_file() {
+            let data = fs::read(entry.path())?;
+            archive.extend_from_slice(&header(&path, mode(&meta), data.len() as u64, b'0'));
+            archive.extend_from_slice(&data);
+            archive.resize(archive.len().div_ceil(BLOCK) * BLOCK, 0);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(meta: &fs::Metadata) -> u32 {
+    if meta.is_dir() { 0o755 } else { 0o644 }
+}
+
+fn header(path: &str, mode: u32, size: u64, type_flag: u8) -> [u8; BLOCK] {
+    let mut h = [0u8; BLOCK];
+    // Длинные пути делятся на prefix (до 155 байт) и name (до 100 байт) по '/'
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        let split = path[..path.len() - 1].rfind('/').filter(|&i| i <= 155 && path.len() - i - 1 <= 100)
+            .unwrap_or_else(|| panic!("path too long for ustar: {}", path));
+        (&path[..split], &path[split + 1..])
+    };
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut h[100..108], mode as u64);
+    octal(&mut h[108..116], 0);
+    octal(&mut h[116..124], 0);
+    octal(&mut h[124..136], size);
+    octal(&mut h[136..148], 0);
+    h[156] = type_flag;
+    h[257..263].copy_from_slice(b"ustar\0");
+    h[263..265].copy_from_slice(b"00");
+    h[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    // Контрольная сумма считается с полем суммы, заполненным пробелами
+    h[148..156].copy_from_slice(b"        ");
+    let sum: u32 = h.iter().map(|&b| b as u32).sum();
+    octal(&mut h[148..155], sum as u64);
+    h
+}
+
+// Восьмеричное число с ведущими нулями и завершающим NUL
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
@@ -0,0 +1,40 @@
+use super::Sdt;
+
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const BYTE_PREFIX: u8 = 0x0A;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+
+/// Ищет в AML-коде DSDT объект `Name(_S5_, Package() {SLP_TYPa, SLP_TYPb, ...})`
+/// и возвращает (SLP_TYPa, SLP_TYPb). Полноценный интерпретатор AML не нужен:
+/// у всех известных прошивок пакет _S5 объявлен статически.
+pub fn s5_sleep_types(dsdt: &Sdt) -> Option<(u8, u8)> {
+    let aml = dsdt.data();
+    let pos = aml.windows(4).position(|w| w == b"_S5_")?;
+    // Перед именем стоит NameOp, возможно с корневым префиксом '\'
+    let named = (pos >= 1 && aml[pos - 1] == NAME_OP)
+        || (pos >= 2 && aml[pos - 2] == NAME_OP && aml[pos - 1] == b'\\');
+    if !named || aml.get(pos + 4) != Some(&PACKAGE_OP) {
+        return None;
+    }
+    // PkgLength: старшие 2 бита первого байта — число дополнительных байт длины
+    let pkg_length_bytes = ((*aml.get(pos + 5)? >> 6) & 0b11) as usize + 1;
+    let mut i = pos + 5 + pkg_length_bytes + 1; // + NumElements
+    let mut read_value = || -> Option<u8> {
+        let value = match *aml.get(i)? {
+            BYTE_PREFIX => {
+                i += 1;
+                *aml.get(i)?
+            }
+            ZERO_OP => 0,
+            ONE_OP => 1,
+            other => other,
+        };
+        i += 1;
+        Some(value)
+    };
+    let slp_typ_a = read_value()?;
+    let slp_typ_b = read_value()?;
+    Some((slp_typ_a, slp_typ_b))
+}
@@ -0,0 +1,69 @@
+use super::{read_u16, read_u32, read_u64, read_u8, GenericAddress, Sdt};
+
+const RESET_REG_SUP: u32 = 1 << 10;
+// Длина FADT из ACPI 1.0; все поля до `flags` включительно есть в любой таблице
+const ACPI1_LENGTH: usize = 116;
+
+/// Нужные ядру поля FADT ("FACP"). Для старых таблиц (ACPI 1.0)
+/// 64-битных X_-полей нет, тогда используются 32-битные.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub revision: u8,
+    pub dsdt: u64,
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm_timer_block: u32,
+    pub pm_timer_length: u8,
+    pub century: u8,
+    pub boot_arch_flags: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    /// None, если таблица короче, чем в ACPI 1.0.
+    pub fn parse(sdt: Sdt) -> Option<Fadt> {
+        let b = sdt.bytes();
+        let len = b.len();
+        if len < ACPI1_LENGTH {
+            return None;
+        }
+        let mut fadt = Fadt {
+            revision: sdt.header.revision,
+            dsdt: read_u32(b, 40) as u64,
+            sci_interrupt: read_u16(b, 46),
+            smi_command: read_u32(b, 48),
+            acpi_enable: read_u8(b, 52),
+            acpi_disable: read_u8(b, 53),
+            pm1a_event_block: read_u32(b, 56),
+            pm1b_event_block: read_u32(b, 60),
+            pm1a_control_block: read_u32(b, 64),
+            pm1b_control_block: read_u32(b, 68),
+            pm_timer_block: read_u32(b, 76),
+            pm_timer_length: read_u8(b, 91),
+            century: read_u8(b, 108),
+            boot_arch_flags: read_u16(b, 109),
+            flags: read_u32(b, 112),
+            reset_register: None,
+            reset_value: 0,
+        };
+        if len >= 129 && fadt.flags & RESET_REG_SUP != 0 {
+            fadt.reset_register = Some(GenericAddress::parse(&b[116..128]));
+            fadt.reset_value = read_u8(b, 128);
+        }
+        if len >= 148 {
+            let x_dsdt = read_u64(b, 140);
+            if x_dsdt != 0 {
+                fadt.dsdt = x_dsdt;
+            }
+        }
+        Some(fadt)
+    }
+}
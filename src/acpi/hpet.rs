@@ -0,0 +1,33 @@
+use super::{read_u16, read_u32, read_u8, GenericAddress, Sdt};
+
+#[derive(Debug, Clone, Copy)]
+pub struct HpetInfo {
+    pub hardware_revision: u8,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement: bool,
+    pub vendor_id: u16,
+    pub address: GenericAddress,
+    pub number: u8,
+    pub minimum_tick: u16,
+}
+
+impl HpetInfo {
+    pub fn parse(sdt: Sdt) -> Option<HpetInfo> {
+        let b = sdt.bytes();
+        if b.len() < 56 {
+            return None;
+        }
+        let id = read_u32(b, 36);
+        Some(HpetInfo {
+            hardware_revision: id as u8,
+            comparators: ((id >> 8) & 0x1F) as u8 + 1,
+            counter_64bit: id & (1 << 13) != 0,
+            legacy_replacement: id & (1 << 15) != 0,
+            vendor_id: (id >> 16) as u16,
+            address: GenericAddress::parse(&b[40..52]),
+            number: read_u8(b, 52),
+            minimum_tick: read_u16(b, 53),
+        })
+    }
+}
@@ -0,0 +1,114 @@
+use alloc::vec::Vec;
+use super::{read_u16, read_u32, read_u64, Sdt};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApic {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// Переназначение ISA IRQ на другой GSI и/или с другой полярностью.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+impl InterruptOverride {
+    // Биты 0-1: 01 — активный высокий, 11 — активный низкий, 00 — как у шины (для ISA — высокий)
+    pub fn active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    // Биты 2-3: 01 — по фронту, 11 — по уровню, 00 — как у шины (для ISA — по фронту)
+    pub fn level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    pub flags: u32,
+    pub local_apics: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+const PCAT_COMPAT: u32 = 1;
+
+impl Madt {
+    pub fn parse(sdt: Sdt) -> Result<Madt, &'static str> {
+        let data = sdt.data();
+        if data.len() < 8 {
+            return Err("MADT too short");
+        }
+        let mut madt = Madt {
+            local_apic_address: read_u32(data, 0) as u64,
+            flags: read_u32(data, 4),
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+        let mut entries = &data[8..];
+        while entries.len() >= 2 {
+            let (kind, len) = (entries[0], entries[1] as usize);
+            if len < 2 || len > entries.len() {
+                return Err("bad MADT entry length");
+            }
+            let e = &entries[..len];
+            // Короче, чем требует тип: поля записи вышли бы за её пределы
+            let min_len = match kind {
+                0 => 8,
+                1 => 12,
+                2 => 10,
+                5 => 12,
+                _ => 2,
+            };
+            if len < min_len {
+                return Err("bad MADT entry length");
+            }
+            match kind {
+                0 => madt.local_apics.push(LocalApic {
+                    processor_id: e[2],
+                    apic_id: e[3],
+                    enabled: read_u32(e, 4) & 1 != 0,
+                }),
+                1 => madt.io_apics.push(IoApic {
+                    id: e[2],
+                    address: read_u32(e, 4),
+                    gsi_base: read_u32(e, 8),
+                }),
+                2 => madt.overrides.push(InterruptOverride {
+                    bus: e[2],
+                    source: e[3],
+                    gsi: read_u32(e, 4),
+                    flags: read_u16(e, 8),
+                }),
+                5 => madt.local_apic_address = read_u64(e, 4),
+                _ => {}
+            }
+            entries = &entries[len..];
+        }
+        Ok(madt)
+    }
+
+    /// Есть ли в системе и 8259, который надо замаскировать.
+    pub fn has_legacy_pic(&self) -> bool {
+        self.flags & PCAT_COMPAT != 0
+    }
+
+    pub fn isa_override(&self, irq: u8) -> Option<&InterruptOverride> {
+        self.overrides.iter().find(|o| o.bus == 0 && o.source == irq)
+    }
+}
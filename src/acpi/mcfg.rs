@@ -0,0 +1,32 @@
+use alloc::vec::Vec;
+use super::{read_u16, read_u64, read_u8, Sdt};
+
+/// Окно ECAM: конфигурационное пространство шин `bus_start..=bus_end` сегмента, по 1 МиБ на шину.
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    pub base: u64,
+    pub segment: u16,
+    pub bus_start: u8,
+    pub bus_end: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    pub fn parse(sdt: Sdt) -> Mcfg {
+        let b = sdt.bytes();
+        // После заголовка 8 зарезервированных байт, затем записи по 16 байт
+        let entries = b.get(44..).unwrap_or_default().chunks_exact(16)
+            .map(|e| McfgEntry {
+                base: read_u64(e, 0),
+                segment: read_u16(e, 8),
+                bus_start: read_u8(e, 10),
+                bus_end: read_u8(e, 11),
+            })
+            .collect();
+        Mcfg { entries }
+    }
+}
@@ -0,0 +1,325 @@
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{convert::TryInto, fmt, mem, ptr, slice, str};
+use x86_64::PhysAddr;
+use crate::{memory::phys_to_virt, println};
+
+pub mod madt;
+pub mod fadt;
+pub mod hpet;
+pub mod dsdt;
+pub mod mcfg;
+
+pub use madt::Madt;
+pub use fadt::Fadt;
+pub use hpet::HpetInfo;
+pub use mcfg::Mcfg;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // Поля ниже есть только при revision >= 2
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+const RSDP_V1_LENGTH: usize = 20;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// Таблица ACPI в физической памяти (через отображение bootloader'а).
+#[derive(Debug, Clone, Copy)]
+pub struct Sdt {
+    pub phys: PhysAddr,
+    pub header: SdtHeader,
+}
+
+impl Sdt {
+    unsafe fn at(phys: PhysAddr) -> Sdt {
+        let header = ptr::read_unaligned(phys_to_virt(phys).as_ptr::<SdtHeader>());
+        Sdt { phys, header }
+    }
+
+    /// Вся таблица вместе с заголовком.
+    pub fn bytes(&self) -> &'static [u8] {
+        unsafe { slice::from_raw_parts(phys_to_virt(self.phys).as_ptr(), self.header.length as usize) }
+    }
+
+    /// Содержимое после заголовка.
+    pub fn data(&self) -> &'static [u8] {
+        &self.bytes()[mem::size_of::<SdtHeader>()..]
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.header.length as usize >= mem::size_of::<SdtHeader>() && checksum(self.bytes()) == 0
+    }
+
+    pub fn signature(&self) -> &str {
+        str::from_utf8(&self.header.signature).unwrap_or("????")
+    }
+}
+
+/// Generic Address Structure: регистр в памяти или в портах ввода-вывода.
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+    pub const PCI_CONFIG: u8 = 2;
+
+    fn parse(b: &[u8]) -> GenericAddress {
+        GenericAddress {
+            space: b[0],
+            bit_width: b[1],
+            bit_offset: b[2],
+            access_size: b[3],
+            address: read_u64(b, 4),
+        }
+    }
+
+    pub fn space_name(&self) -> &'static str {
+        match self.space {
+            Self::SYSTEM_MEMORY => "mem",
+            Self::SYSTEM_IO => "io",
+            Self::PCI_CONFIG => "pci",
+            _ => "other",
+        }
+    }
+}
+
+impl fmt::Display for GenericAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:#x}", self.space_name(), self.address)?;
+        // Нули означают «любая ширина/смещение/размер доступа»
+        if self.bit_width != 0 {
+            write!(f, " ({} bits at bit {}, access size {})", self.bit_width, self.bit_offset, self.access_size)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn read_u8(b: &[u8], off: usize) -> u8 {
+    b[off]
+}
+
+pub(crate) fn read_u16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off..off + 2].try_into().unwrap())
+}
+
+pub(crate) fn read_u32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_u64(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap())
+}
+
+// Сумма всех байт структуры ACPI должна быть 0 по модулю 256
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+pub struct Acpi {
+    pub revision: u8,
+    pub rsdp: PhysAddr,
+    pub oem_id: [u8; 6],
+    pub root: Sdt,
+    pub tables: Vec<Sdt>,
+    // Таблицы с неверной контрольной суммой: не используются, но показываются в `acpi`
+    pub invalid: Vec<Sdt>,
+}
+
+impl Acpi {
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&Sdt> {
+        self.tables.iter().find(|t| &t.header.signature == signature)
+    }
+}
+
+static ACPI: OnceCell<Acpi> = OnceCell::uninit();
+
+pub fn init() -> Result<(), &'static str> {
+    let rsdp_addr = find_rsdp().ok_or("RSDP not found")?;
+    let rsdp = unsafe { ptr::read_unaligned(phys_to_virt(rsdp_addr).as_ptr::<Rsdp>()) };
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        // v2-структура не короче 36 байт, а длиннее страницы её не бывает: иначе это мусор
+        if !(mem::size_of::<Rsdp>()..=4096).contains(&(rsdp.length as usize)) {
+            return Err("bad extended RSDP length");
+        }
+        let bytes = unsafe {
+            slice::from_raw_parts(phys_to_virt(rsdp_addr).as_ptr::<u8>(), rsdp.length as usize)
+        };
+        if checksum(bytes) != 0 {
+            return Err("bad extended RSDP checksum");
+        }
+        (PhysAddr::new(rsdp.xsdt_address), 8)
+    } else {
+        (PhysAddr::new(rsdp.rsdt_address as u64), 4)
+    };
+    let root = unsafe { Sdt::at(root) };
+    if !root.is_valid() {
+        return Err("bad RSDT/XSDT checksum");
+    }
+    let (tables, invalid): (Vec<Sdt>, Vec<Sdt>) = root.data()
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let addr = match entry_size {
+                8 => read_u64(entry, 0),
+                _ => read_u32(entry, 0) as u64,
+            };
+            unsafe { Sdt::at(PhysAddr::new(addr)) }
+        })
+        .partition(|sdt| sdt.is_valid());
+    let acpi = Acpi { revision: rsdp.revision, rsdp: rsdp_addr, oem_id: rsdp.oem_id, root, tables, invalid };
+    ACPI.try_init_once(|| acpi).map_err(|_| "ACPI already initialized")
+}
+
+pub fn get() -> Option<&'static Acpi> {
+    ACPI.get()
+}
+
+pub fn madt() -> Result<Madt, &'static str> {
+    let sdt = get().and_then(|acpi| acpi.find(b"APIC")).ok_or("no MADT")?;
+    Madt::parse(*sdt)
+}
+
+pub fn fadt() -> Option<Fadt> {
+    get()?.find(b"FACP").and_then(|sdt| Fadt::parse(*sdt))
+}
+
+pub fn dsdt() -> Option<Sdt> {
+    let dsdt = unsafe { Sdt::at(PhysAddr::new(fadt()?.dsdt)) };
+    if dsdt.is_valid() && &dsdt.header.signature == b"DSDT" {
+        Some(dsdt)
+    } else {
+        None
+    }
+}
+
+pub fn hpet() -> Option<HpetInfo> {
+    get()?.find(b"HPET").and_then(|sdt| HpetInfo::parse(*sdt))
+}
+
+pub fn mcfg() -> Option<Mcfg> {
+    get()?.find(b"MCFG").map(|sdt| Mcfg::parse(*sdt))
+}
+
+// RSDP лежит в первом КБ EBDA или в области BIOS 0xE0000..0xFFFFF, выровненный на 16 байт
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda = unsafe { ptr::read(phys_to_virt(PhysAddr::new(0x40E)).as_ptr::<u16>()) } as u64 * 16;
+    let areas = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+    areas.iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .map(PhysAddr::new)
+        .find(|&addr| {
+            let bytes = unsafe { slice::from_raw_parts(phys_to_virt(addr).as_ptr::<u8>(), RSDP_V1_LENGTH) };
+            &bytes[..8] == b"RSD PTR " && checksum(bytes) == 0
+        })
+}
+
+fn text(bytes: &[u8]) -> &str {
+    str::from_utf8(bytes).unwrap_or("?").trim_end_matches([' ', '\0'])
+}
+
+/// Вывод для команды `acpi [madt|fadt|hpet|mcfg]`.
+pub fn dump(what: &str) {
+    let acpi = match get() {
+        Some(acpi) => acpi,
+        None => return println!("ACPI is not available"),
+    };
+    match what {
+        "" => {
+            println!("RSDP at {:#x}, revision {}, OEM '{}', root {} at {:#x}",
+                acpi.rsdp.as_u64(), acpi.revision, text(&acpi.oem_id),
+                acpi.root.signature(), acpi.root.phys.as_u64());
+            for sdt in &acpi.tables {
+                let h = sdt.header;
+                let length = h.length;
+                println!("  {}  {:#010x}  len {:>5}  rev {}  {} {}",
+                    sdt.signature(), sdt.phys.as_u64(), length, h.revision,
+                    text(&h.oem_id), text(&h.oem_table_id));
+            }
+            for sdt in &acpi.invalid {
+                println!("  {}  {:#010x}  BAD CHECKSUM (ignored)", sdt.signature(), sdt.phys.as_u64());
+            }
+        }
+        "madt" => match madt() {
+            Ok(madt) => {
+                println!("Local APIC at {:#x}, flags {:#x}", madt.local_apic_address, madt.flags);
+                for cpu in &madt.local_apics {
+                    println!("  CPU {}: APIC id {} {}", cpu.processor_id, cpu.apic_id,
+                        if cpu.enabled { "enabled" } else { "disabled" });
+                }
+                for io in &madt.io_apics {
+                    println!("  IOAPIC {}: {:#x}, GSI base {}", io.id, io.address, io.gsi_base);
+                }
+                for o in &madt.overrides {
+                    println!("  IRQ {} -> GSI {} ({}, {})", o.source, o.gsi,
+                        if o.active_low() { "low" } else { "high" },
+                        if o.level_triggered() { "level" } else { "edge" });
+                }
+            }
+            Err(e) => println!("MADT: {}", e),
+        },
+        "fadt" => match fadt() {
+            Some(f) => {
+                println!("FADT rev {}, DSDT at {:#x}, SCI IRQ {}", f.revision, f.dsdt, f.sci_interrupt);
+                println!("  SMI cmd {:#x} (enable {:#x}, disable {:#x})", f.smi_command, f.acpi_enable, f.acpi_disable);
+                println!("  PM1a evt {:#x} cnt {:#x}, PM1b evt {:#x} cnt {:#x}",
+                    f.pm1a_event_block, f.pm1a_control_block, f.pm1b_event_block, f.pm1b_control_block);
+                println!("  PM timer {:#x} ({} bytes), century reg {}, boot arch {:#x}, flags {:#x}",
+                    f.pm_timer_block, f.pm_timer_length, f.century, f.boot_arch_flags, f.flags);
+                match f.reset_register {
+                    Some(r) => println!("  Reset register: {} <- {:#x}", r, f.reset_value),
+                    None => println!("  Reset register: not supported"),
+                }
+            }
+            None => println!("No FADT"),
+        },
+        "hpet" => match hpet() {
+            Some(h) => {
+                println!("HPET #{} at {}, rev {}, vendor {:#06x}", h.number,
+                    h.address, h.hardware_revision, h.vendor_id);
+                println!("  {} comparators, {}-bit counter, legacy replacement {}, min tick {}",
+                    h.comparators, if h.counter_64bit { 64 } else { 32 },
+                    if h.legacy_replacement { "yes" } else { "no" }, h.minimum_tick);
+            }
+            None => println!("No HPET"),
+        },
+        "mcfg" => match mcfg() {
+            Some(m) => {
+                for e in &m.entries {
+                    println!("  ECAM {:#x}: segment {}, buses {}..{}", e.base, e.segment, e.bus_start, e.bus_end);
+                }
+            }
+            None => println!("No MCFG"),
+        },
+        _ => println!("Usage: acpi [madt|fadt|hpet|mcfg]"),
+    }
+}
@@ -4,13 +4,32 @@ use x86_64::{
     },
     VirtAddr,
 };
-use linked_list_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
+use crate::lock::IrqSafeMutex;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024;
+pub const HEAP_SIZE: usize = 1024 * 1024;
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Allocator = Allocator(IrqSafeMutex::new("heap", Heap::empty()));
+
+// Свой аналог LockedHeap: замок кучи держится с выключенными прерываниями,
+// поэтому прерывание не застанет кучу занятой прерванным потоком.
+struct Allocator(IrqSafeMutex<Heap>);
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock()
+            .allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -37,8 +56,14 @@ pub fn init_heap(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.0.lock().init(HEAP_START, HEAP_SIZE);
     }
 
     Ok(())
+}
+
+/// (занято, свободно) в байтах.
+pub fn heap_usage() -> (usize, usize) {
+    let heap = ALLOCATOR.0.lock();
+    (heap.used(), heap.free())
 }
\ No newline at end of file
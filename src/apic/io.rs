@@ -0,0 +1,76 @@
+use core::ptr;
+use x86_64::VirtAddr;
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+
+const ACTIVE_LOW: u64 = 1 << 13;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+
+pub struct IoApic {
+    base: VirtAddr,
+    pub gsi_base: u32,
+    pub entries: u32,
+}
+
+impl IoApic {
+    pub unsafe fn new(base: VirtAddr, gsi_base: u32) -> Self {
+        let mut ioapic = IoApic { base, gsi_base, entries: 0 };
+        ioapic.entries = ((ioapic.read(REG_VERSION) >> 16) & 0xFF) + 1;
+        ioapic
+    }
+
+    unsafe fn read(&self, reg: u32) -> u32 {
+        ptr::write_volatile((self.base.as_u64() + IOREGSEL) as *mut u32, reg);
+        ptr::read_volatile((self.base.as_u64() + IOWIN) as *const u32)
+    }
+
+    unsafe fn write(&self, reg: u32, value: u32) {
+        ptr::write_volatile((self.base.as_u64() + IOREGSEL) as *mut u32, reg);
+        ptr::write_volatile((self.base.as_u64() + IOWIN) as *mut u32, value);
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+
+    fn write_entry(&self, gsi: u32, entry: u64) {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        unsafe {
+            self.write(reg, entry as u32);
+            self.write(reg + 1, (entry >> 32) as u32);
+        }
+    }
+
+    fn read_entry(&self, gsi: u32) -> u64 {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        unsafe { self.read(reg) as u64 | (self.read(reg + 1) as u64) << 32 }
+    }
+
+    pub fn mask_all(&self) {
+        for i in 0..self.entries {
+            self.write_entry(self.gsi_base + i, MASKED);
+        }
+    }
+
+    /// Фиксированная доставка в физическом режиме на процессор `apic_id`.
+    pub fn route(&self, gsi: u32, vector: u8, apic_id: u8, active_low: bool, level: bool) {
+        let mut entry = vector as u64 | (apic_id as u64) << 56;
+        if active_low {
+            entry |= ACTIVE_LOW;
+        }
+        if level {
+            entry |= LEVEL_TRIGGERED;
+        }
+        self.write_entry(gsi, entry);
+    }
+
+    pub fn set_masked(&self, gsi: u32, masked: bool) {
+        let entry = self.read_entry(gsi);
+        self.write_entry(gsi, if masked { entry | MASKED } else { entry & !MASKED });
+    }
+}
@@ -0,0 +1,122 @@
+use core::ptr;
+use x86_64::{instructions::interrupts, VirtAddr};
+
+const ID: u32 = 0x20;
+const TPR: u32 = 0x80;
+const EOI: u32 = 0xB0;
+const SVR: u32 = 0xF0;
+const LVT_TIMER: u32 = 0x320;
+const LVT_LINT0: u32 = 0x350;
+const LVT_LINT1: u32 = 0x360;
+const LVT_ERROR: u32 = 0x370;
+const ICR_LOW: u32 = 0x300;
+const ICR_HIGH: u32 = 0x310;
+const TIMER_INITIAL: u32 = 0x380;
+const TIMER_CURRENT: u32 = 0x390;
+const TIMER_DIVIDE: u32 = 0x3E0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const DIVIDE_BY_16: u32 = 0b0011;
+
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_LEVEL: u32 = 1 << 15;
+
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    pub const fn new(base: VirtAddr) -> Self {
+        LocalApic { base }
+    }
+
+    unsafe fn read(&self, reg: u32) -> u32 {
+        ptr::read_volatile((self.base.as_u64() + reg as u64) as *const u32)
+    }
+
+    unsafe fn write(&self, reg: u32, value: u32) {
+        ptr::write_volatile((self.base.as_u64() + reg as u64) as *mut u32, value);
+    }
+
+    pub unsafe fn enable(&self, spurious_vector: u8) {
+        self.write(TPR, 0);
+        self.write(LVT_LINT0, LVT_MASKED);
+        self.write(LVT_LINT1, LVT_MASKED);
+        self.write(LVT_ERROR, LVT_MASKED);
+        self.write(SVR, SVR_ENABLE | spurious_vector as u32);
+    }
+
+    pub fn id(&self) -> u8 {
+        unsafe { (self.read(ID) >> 24) as u8 }
+    }
+
+    pub fn eoi(&self) {
+        unsafe { self.write(EOI, 0) };
+    }
+
+    // ICR — пара регистров: прерывание между записями, само шлющее IPI,
+    // подменило бы адресата в ICR_HIGH
+    unsafe fn send_ipi(&self, apic_id: u8, command: u32) {
+        interrupts::without_interrupts(|| {
+            self.write(ICR_HIGH, (apic_id as u32) << 24);
+            self.write(ICR_LOW, command);
+            while self.read(ICR_LOW) & ICR_PENDING != 0 {
+                core::hint::spin_loop();
+            }
+        });
+    }
+
+    /// Обычное (fixed) прерывание `vector` процессору `apic_id`.
+    pub unsafe fn send_fixed(&self, apic_id: u8, vector: u8) {
+        self.send_ipi(apic_id, ICR_ASSERT | vector as u32);
+    }
+
+    /// INIT: процессор `apic_id` сбрасывается и ждёт SIPI.
+    pub unsafe fn send_init(&self, apic_id: u8) {
+        self.send_ipi(apic_id, ICR_INIT | ICR_LEVEL | ICR_ASSERT);
+        self.send_ipi(apic_id, ICR_INIT | ICR_LEVEL);
+    }
+
+    /// SIPI: процессор начнёт в real mode с адреса `vector << 12`.
+    pub unsafe fn send_startup(&self, apic_id: u8, vector: u8) {
+        self.send_ipi(apic_id, ICR_STARTUP | vector as u32);
+    }
+
+    /// Запускает таймер на максимальный отсчёт без прерываний — для калибровки.
+    pub unsafe fn start_counting(&self) {
+        self.write(TIMER_DIVIDE, DIVIDE_BY_16);
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(TIMER_INITIAL, u32::MAX);
+    }
+
+    /// Сколько отсчётов прошло с `start_counting`.
+    pub fn elapsed_counts(&self) -> u32 {
+        unsafe { u32::MAX - self.read(TIMER_CURRENT) }
+    }
+
+    pub unsafe fn start_periodic(&self, vector: u8, counts: u32) {
+        self.write(TIMER_DIVIDE, DIVIDE_BY_16);
+        self.write(LVT_TIMER, TIMER_PERIODIC | vector as u32);
+        self.write(TIMER_INITIAL, counts);
+    }
+
+    /// Одно прерывание через `counts` отсчётов (для tickless-простоя).
+    pub unsafe fn start_oneshot(&self, vector: u8, counts: u32) {
+        self.write(TIMER_DIVIDE, DIVIDE_BY_16);
+        self.write(LVT_TIMER, vector as u32);
+        self.write(TIMER_INITIAL, counts.max(1));
+    }
+
+    pub fn initial_count(&self) -> u32 {
+        unsafe { self.read(TIMER_INITIAL) }
+    }
+
+    pub fn current_count(&self) -> u32 {
+        unsafe { self.read(TIMER_CURRENT) }
+    }
+}
@@ -0,0 +1,192 @@
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use x86_64::{instructions::interrupts, PhysAddr};
+use crate::{acpi::{self, Madt}, cmdline, interrupts::{InterruptIndex, TICKS}, memory};
+
+pub mod io;
+pub mod local;
+
+use io::IoApic;
+use local::LocalApic;
+
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+/// IPI, которым будят простаивающий процессор.
+pub const WAKEUP_VECTOR: u8 = 0xF0;
+const CALIBRATION_MS: u64 = 20;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TIMER_COUNTS_PER_MS: AtomicU32 = AtomicU32::new(0);
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APICS: OnceCell<Vec<IoApic>> = OnceCell::uninit();
+static MADT: OnceCell<Madt> = OnceCell::uninit();
+
+/// Переводит систему с 8259 на local APIC + IOAPIC. Вызывается при уже
+/// работающем PIT через PIC: по его тикам калибруется таймер APIC.
+/// При ошибке (или флаге `noapic`) всё остаётся на PIC.
+pub fn init() -> Result<(), &'static str> {
+    if cmdline::has_flag("noapic") {
+        return Err("disabled by noapic");
+    }
+    if !cpu_has_apic() {
+        return Err("CPU has no local APIC");
+    }
+    let madt = acpi::madt()?;
+    if madt.io_apics.is_empty() {
+        return Err("no IOAPIC in MADT");
+    }
+
+    let base = memory::map_mmio(PhysAddr::new(madt.local_apic_address), 4096)
+        .map_err(|_| "cannot map local APIC")?;
+    let lapic = LocalApic::new(base);
+    unsafe { lapic.enable(SPURIOUS_VECTOR) };
+    let counts_per_ms = calibrate(&lapic);
+    TIMER_COUNTS_PER_MS.store(counts_per_ms, Ordering::Relaxed);
+
+    let mut io_apics = Vec::new();
+    for entry in &madt.io_apics {
+        let base = memory::map_mmio(PhysAddr::new(entry.address as u64), 4096)
+            .map_err(|_| "cannot map IOAPIC")?;
+        let ioapic = unsafe { IoApic::new(base, entry.gsi_base) };
+        ioapic.mask_all();
+        io_apics.push(ioapic);
+    }
+
+    let _ = LOCAL_APIC.try_init_once(|| lapic);
+    let _ = IO_APICS.try_init_once(|| io_apics);
+    let _ = MADT.try_init_once(|| madt);
+
+    interrupts::without_interrupts(|| {
+        // IRQ0 (PIT) не маршрутизируем: тики теперь даёт таймер local APIC
+        if MADT.get().is_none_or(|madt| madt.has_legacy_pic()) {
+            crate::interrupts::disable_pic();
+        }
+        ENABLED.store(true, Ordering::Release);
+        start_periodic_timer();
+    });
+    // Линии, открытые до перехода, теперь идут через IOAPIC
+    crate::irq::reroute();
+    Ok(())
+}
+
+fn cpu_has_apic() -> bool {
+    __cpuid(1).edx & (1 << 9) != 0
+}
+
+// Сколько отсчётов таймера APIC (делитель 16) приходится на 1 мс по PIT
+fn calibrate(lapic: &LocalApic) -> u32 {
+    let wait_tick = || {
+        let start = TICKS.load(Ordering::Relaxed);
+        while TICKS.load(Ordering::Relaxed) == start {
+            x86_64::instructions::hlt();
+        }
+    };
+    wait_tick();
+    unsafe { lapic.start_counting() };
+    for _ in 0..CALIBRATION_MS {
+        wait_tick();
+    }
+    (lapic.elapsed_counts() as u64 / CALIBRATION_MS) as u32
+}
+
+/// Тик 1 мс на векторе таймера — вместо PIT.
+pub fn start_periodic_timer() {
+    if let Some(lapic) = LOCAL_APIC.get() {
+        let counts = TIMER_COUNTS_PER_MS.load(Ordering::Relaxed);
+        unsafe { lapic.start_periodic(InterruptIndex::Timer as u8, counts) };
+    }
+}
+
+/// Останавливает периодический тик и заводит одно прерывание через `ms` мс.
+pub fn start_oneshot_timer(ms: u64) {
+    if let Some(lapic) = LOCAL_APIC.get() {
+        let counts = (TIMER_COUNTS_PER_MS.load(Ordering::Relaxed) as u64 * ms).min(u32::MAX as u64);
+        unsafe { lapic.start_oneshot(InterruptIndex::Timer as u8, counts as u32) };
+    }
+}
+
+/// Отсчёты таймера на 1 мс (делитель 16).
+pub fn timer_counts_per_ms() -> u32 {
+    TIMER_COUNTS_PER_MS.load(Ordering::Relaxed)
+}
+
+/// Сколько отсчётов прошло с начала текущего периода таймера.
+pub fn timer_elapsed_in_period() -> u32 {
+    LOCAL_APIC.get().map_or(0, |lapic| lapic.initial_count().saturating_sub(lapic.current_count()))
+}
+
+/// Включает local APIC на AP (адрес регистров у всех процессоров один).
+pub fn init_ap() {
+    if let Some(lapic) = LOCAL_APIC.get() {
+        unsafe { lapic.enable(SPURIOUS_VECTOR) };
+    }
+}
+
+pub fn send_ipi(apic_id: u8, vector: u8) {
+    if let Some(lapic) = LOCAL_APIC.get() {
+        unsafe { lapic.send_fixed(apic_id, vector) };
+    }
+}
+
+pub fn send_init(apic_id: u8) {
+    if let Some(lapic) = LOCAL_APIC.get() {
+        unsafe { lapic.send_init(apic_id) };
+    }
+}
+
+pub fn send_startup(apic_id: u8, vector: u8) {
+    if let Some(lapic) = LOCAL_APIC.get() {
+        unsafe { lapic.send_startup(apic_id, vector) };
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+pub fn eoi() {
+    if let Some(lapic) = LOCAL_APIC.get() {
+        lapic.eoi();
+    }
+}
+
+pub fn local_apic_id() -> Option<u8> {
+    LOCAL_APIC.get().map(|lapic| lapic.id())
+}
+
+fn io_apic_for(gsi: u32) -> Option<&'static IoApic> {
+    IO_APICS.get()?.iter().find(|ioapic| ioapic.handles(gsi))
+}
+
+/// IRQ -> GSI с учётом переназначений ISA-линий из MADT (например, IRQ0 -> GSI2).
+/// Линии выше 15 — PCI: уровень, активный низкий.
+fn irq_to_gsi(irq: u8) -> (u32, bool, bool) {
+    if irq >= 16 {
+        return (irq as u32, true, true);
+    }
+    match MADT.get().and_then(|madt| madt.isa_override(irq)) {
+        Some(o) => (o.gsi, o.active_low(), o.level_triggered()),
+        None => (irq as u32, false, false),
+    }
+}
+
+/// Направляет IRQ на `vector` загрузочного процессора.
+pub fn route_irq(irq: u8, vector: u8) -> bool {
+    let (gsi, active_low, level) = irq_to_gsi(irq);
+    let apic_id = crate::smp::boot_apic_id();
+    match io_apic_for(gsi) {
+        Some(ioapic) => {
+            ioapic.route(gsi, vector, apic_id, active_low, level);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn set_irq_masked(irq: u8, masked: bool) {
+    let (gsi, _, _) = irq_to_gsi(irq);
+    if let Some(ioapic) = io_apic_for(gsi) {
+        ioapic.set_masked(gsi, masked);
+    }
+}
@@ -0,0 +1,576 @@
+//! ATA (IDE) диски на двух стандартных каналах в режиме PIO, LBA28 и LBA48.
+//! Асинхронные запросы задач ведёт обработчик IRQ 14/15: он переносит сектора и
+//! будит задачу по завершении. Если прерывание потерялось, задача раз в
+//! `IRQ_TIMEOUT_MS` опрашивает канал сама. Синхронные запросы опрашивают регистр
+//! состояния, как и раньше.
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
+use core::future;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+use futures_util::task::AtomicWaker;
+use x86_64::instructions::port::Port;
+use super::{check_range, BlockDevice, BlockError, BlockFuture, BlockResult, SECTOR_SIZE};
+use crate::irq::{self, IrqReturn};
+use crate::lock::IrqSafeMutex;
+use crate::pci::{self, PciDevice, PciDriver, PciMatch};
+use crate::task::sync;
+use crate::time;
+
+// Смещения регистров от базового порта канала
+const REG_DATA: u16 = 0;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DEVICE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+// Регистр управления: запрет прерываний от канала
+const CONTROL_NIEN: u8 = 1 << 1;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_FLUSH_CACHE: u8 = 0xE7;
+const CMD_FLUSH_CACHE_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+// Сектора за одну команду: счётчик LBA28 — один байт
+const MAX_SECTORS_PER_COMMAND: u64 = 255;
+const LBA28_LIMIT: u64 = 1 << 28;
+const COMMAND_TIMEOUT_MS: u64 = 1000;
+const IRQ_TIMEOUT_MS: u64 = 10;
+
+// prog-if IDE-контроллера: канал в native-режиме и на стандартных портах не отвечает
+const PROG_IF_PRIMARY_NATIVE: u8 = 1 << 0;
+const PROG_IF_SECONDARY_NATIVE: u8 = 1 << 2;
+const IDE_CONTROLLER: PciMatch = PciMatch::Class { class: 0x01, subclass: 0x01 };
+
+pub static DRIVER: PciDriver = PciDriver {
+    name: "ata",
+    matches: &[IDE_CONTROLLER],
+    probe,
+};
+
+/// Асинхронный запрос, который ведёт обработчик прерывания. Данные лежат в своём
+/// буфере: задача может отбросить future, а запрос всё равно доработает до конца.
+struct Transfer {
+    slave: bool,
+    lba48: bool,
+    write: bool,
+    lba: u64,
+    data: Vec<u8>,
+    // Сколько секторов перенесено и сколько покрывают уже отданные команды
+    done: u64,
+    issued: u64,
+    deadline: u64,
+    result: Option<BlockResult<()>>,
+    // Future отброшен: результат никому не нужен
+    abandoned: bool,
+}
+
+impl Transfer {
+    fn sectors(&self) -> u64 {
+        (self.data.len() / SECTOR_SIZE) as u64
+    }
+
+    fn sector(&mut self, index: u64) -> &mut [u8] {
+        let start = index as usize * SECTOR_SIZE;
+        &mut self.data[start..start + SECTOR_SIZE]
+    }
+}
+
+struct ChannelState {
+    // Запрос на диске и завершённый, но ещё не забранный задачей
+    active: Option<Transfer>,
+    done: Option<Transfer>,
+    // Канал занят синхронным запросом
+    claimed: bool,
+}
+
+struct Channel {
+    io: u16,
+    control: u16,
+    irq: u8,
+    // Один канал — одна команда за раз, даже для двух дисков на нём
+    state: IrqSafeMutex<ChannelState>,
+    // Асинхронные запросы встают в очередь здесь, а не крутятся в ожидании канала
+    queue: sync::Mutex<()>,
+    // Задача, ждущая канал или завершения своего запроса (она одна: см. `queue`)
+    waker: AtomicWaker,
+    irq_enabled: AtomicBool,
+}
+
+/// Синхронный захват канала; отпускается при drop.
+struct Claim<'a> {
+    channel: &'a Channel,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.channel.state.lock().claimed = false;
+        self.channel.waker.wake();
+    }
+}
+
+impl Channel {
+    const fn new(io: u16, control: u16, irq: u8) -> Self {
+        Channel {
+            io,
+            control,
+            irq,
+            state: IrqSafeMutex::new("ata", ChannelState { active: None, done: None, claimed: false }),
+            queue: sync::Mutex::new(()),
+            waker: AtomicWaker::new(),
+            irq_enabled: AtomicBool::new(false),
+        }
+    }
+
+    /// Ждёт, пока канал освободится, и занимает его под синхронный запрос.
+    /// Асинхронный запрос на канале ждущий сам доводит до конца опросом: его
+    /// задача может стоять в очереди этого же CPU.
+    fn claim(&self) -> Claim<'_> {
+        loop {
+            {
+                let mut state = self.state.lock();
+                if !state.claimed && state.active.is_none() {
+                    state.claimed = true;
+                    return Claim { channel: self };
+                }
+                self.service(&mut state);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn read(&self, reg: u16) -> u8 {
+        unsafe { Port::new(self.io + reg).read() }
+    }
+
+    fn write(&self, reg: u16, value: u8) {
+        unsafe { Port::new(self.io + reg).write(value) }
+    }
+
+    // Альтернативный регистр состояния: чтение не сбрасывает прерывание
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::new(self.control).read() }
+    }
+
+    fn set_control(&self, value: u8) {
+        unsafe { Port::new(self.control).write(value) }
+    }
+
+    fn select(&self, slave: bool, lba_bits: u8) {
+        // Бит 6 — адресация LBA, биты 7 и 5 по стандарту всегда 1
+        self.write(REG_DEVICE, 0xE0 | (slave as u8) << 4 | (lba_bits & 0x0F));
+        // Диску нужно 400 нс, чтобы выставить состояние после выбора
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    fn wait_not_busy(&self) -> BlockResult<u8> {
+        let deadline = time::now() + COMMAND_TIMEOUT_MS * 1_000_000;
+        loop {
+            let status = self.alt_status();
+            if status & STATUS_BSY == 0 {
+                return Ok(status);
+            }
+            if time::now() > deadline {
+                return Err(BlockError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Ждёт, пока диск будет готов передать очередной сектор.
+    fn wait_data(&self) -> BlockResult<()> {
+        let status = self.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Io);
+        }
+        if status & STATUS_DRQ == 0 {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    fn read_sector(&self, buf: &mut [u8]) {
+        let mut data: Port<u16> = Port::new(self.io + REG_DATA);
+        for word in buf.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&self, buf: &[u8]) {
+        let mut data: Port<u16> = Port::new(self.io + REG_DATA);
+        for word in buf.chunks_exact(2) {
+            unsafe { data.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+
+    fn command(&self, slave: bool, lba48: bool, lba: u64, count: u64, write: bool) {
+        if lba48 {
+            self.select(slave, 0);
+            // Сначала старшие байты счётчика и адреса, затем младшие
+            self.write(REG_SECTOR_COUNT, (count >> 8) as u8);
+            self.write(REG_LBA_LOW, (lba >> 24) as u8);
+            self.write(REG_LBA_MID, (lba >> 32) as u8);
+            self.write(REG_LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            self.select(slave, (lba >> 24) as u8);
+        }
+        self.write(REG_SECTOR_COUNT, count as u8);
+        self.write(REG_LBA_LOW, lba as u8);
+        self.write(REG_LBA_MID, (lba >> 8) as u8);
+        self.write(REG_LBA_HIGH, (lba >> 16) as u8);
+        self.write(REG_COMMAND, match (write, lba48) {
+            (false, false) => CMD_READ_SECTORS,
+            (false, true) => CMD_READ_SECTORS_EXT,
+            (true, false) => CMD_WRITE_SECTORS,
+            (true, true) => CMD_WRITE_SECTORS_EXT,
+        });
+    }
+
+    // Отдаёт команду на следующий кусок запроса. При записи прерывания перед первым
+    // сектором нет, поэтому его ждём опросом и пишем сразу
+    fn start_next(&self, transfer: &mut Transfer) -> BlockResult<()> {
+        let count = (transfer.sectors() - transfer.done).min(MAX_SECTORS_PER_COMMAND);
+        self.command(transfer.slave, transfer.lba48, transfer.lba + transfer.done, count, transfer.write);
+        transfer.issued = transfer.done + count;
+        transfer.deadline = time::now() + COMMAND_TIMEOUT_MS * 1_000_000;
+        if transfer.write {
+            self.wait_data()?;
+            let index = transfer.done;
+            self.write_sector(transfer.sector(index));
+            transfer.done += 1;
+        }
+        Ok(())
+    }
+
+    /// Продвигает асинхронный запрос по состоянию канала. Зовётся из обработчика
+    /// IRQ и при опросе; чтение основного регистра состояния снимает прерывание.
+    fn service(&self, state: &mut ChannelState) {
+        let status = self.read(REG_STATUS);
+        let transfer = match state.active.as_mut() {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        let result = match self.advance(transfer, status) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return,
+        };
+        let mut transfer = state.active.take().expect("active ATA transfer");
+        transfer.result = Some(result);
+        if !transfer.abandoned {
+            state.done = Some(transfer);
+        }
+        self.waker.wake();
+    }
+
+    // Ready — запрос завершён целиком
+    fn advance(&self, transfer: &mut Transfer, status: u8) -> Poll<BlockResult<()>> {
+        if status & STATUS_BSY != 0 {
+            if time::now() <= transfer.deadline {
+                return Poll::Pending;
+            }
+            return Poll::Ready(Err(BlockError::Timeout));
+        }
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Poll::Ready(Err(BlockError::Io));
+        }
+        if transfer.done < transfer.issued {
+            // Диск ждёт или отдаёт очередной сектор
+            if status & STATUS_DRQ == 0 {
+                return Poll::Pending;
+            }
+            let index = transfer.done;
+            if transfer.write {
+                self.write_sector(transfer.sector(index));
+            } else {
+                self.read_sector(transfer.sector(index));
+            }
+            transfer.done += 1;
+            // Записанный сектор диск ещё подтвердит прерыванием, а прочитанный
+            // последний завершает команду
+            if transfer.write || transfer.done < transfer.issued {
+                return Poll::Pending;
+            }
+        }
+        if transfer.done == transfer.sectors() {
+            return Poll::Ready(Ok(()));
+        }
+        match self.start_next(transfer) {
+            Ok(()) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    /// IDENTIFY DEVICE; None, если на месте диска пусто или там ATAPI/SATA.
+    fn identify(&self, slave: bool) -> Option<[u16; 256]> {
+        self.select(slave, 0);
+        for reg in REG_SECTOR_COUNT..=REG_LBA_HIGH {
+            self.write(reg, 0);
+        }
+        self.write(REG_COMMAND, CMD_IDENTIFY);
+        let status = self.read(REG_STATUS);
+        if status == 0 || status == 0xFF {
+            return None;
+        }
+        self.wait_not_busy().ok()?;
+        // ATAPI и SATA отвечают сигнатурой в регистрах LBA
+        if self.read(REG_LBA_MID) != 0 || self.read(REG_LBA_HIGH) != 0 {
+            return None;
+        }
+        self.wait_data().ok()?;
+        let mut bytes = [0u8; SECTOR_SIZE];
+        self.read_sector(&mut bytes);
+        let mut words = [0u16; 256];
+        for (word, pair) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([pair[0], pair[1]]);
+        }
+        Some(words)
+    }
+}
+
+static CHANNELS: [Channel; 2] = [Channel::new(0x1F0, 0x3F6, 14), Channel::new(0x170, 0x376, 15)];
+
+pub struct AtaDrive {
+    name: String,
+    model: String,
+    channel: &'static Channel,
+    slave: bool,
+    lba48: bool,
+    sectors: u64,
+}
+
+impl AtaDrive {
+    fn from_identify(name: String, channel: &'static Channel, slave: bool, id: &[u16; 256]) -> Self {
+        // Слово 83, бит 10: поддержка 48-битной адресации
+        let lba48 = id[83] & (1 << 10) != 0;
+        let sectors = if lba48 {
+            id[100..104].iter().rev().fold(0u64, |acc, &w| acc << 16 | w as u64)
+        } else {
+            (id[61] as u64) << 16 | id[60] as u64
+        };
+        // Модель — слова 27..47, в каждом слове байты переставлены
+        let model: String = id[27..47].iter()
+            .flat_map(|w| w.to_be_bytes())
+            .map(|b| b as char)
+            .collect();
+        AtaDrive { name, model: String::from(model.trim()), channel, slave, lba48, sectors }
+    }
+
+    fn read_chunk(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        let ch = self.channel;
+        ch.command(self.slave, self.lba48, lba, (buf.len() / SECTOR_SIZE) as u64, false);
+        for sector in buf.chunks_exact_mut(SECTOR_SIZE) {
+            ch.wait_data()?;
+            ch.read_sector(sector);
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        let ch = self.channel;
+        ch.command(self.slave, self.lba48, lba, (buf.len() / SECTOR_SIZE) as u64, true);
+        for sector in buf.chunks_exact(SECTOR_SIZE) {
+            ch.wait_data()?;
+            ch.write_sector(sector);
+        }
+        let status = ch.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    /// Асинхронный запрос: ставит его на канал и ждёт завершения, не занимая CPU.
+    /// Возвращает буфер с данными (для чтения — прочитанными).
+    async fn transfer(&self, lba: u64, data: Vec<u8>, write: bool) -> BlockResult<Vec<u8>> {
+        let ch = self.channel;
+        let _queue = ch.queue.lock().await;
+        let mut request = Some(Transfer {
+            slave: self.slave,
+            lba48: self.lba48,
+            write,
+            lba,
+            data,
+            done: 0,
+            issued: 0,
+            deadline: 0,
+            result: None,
+            abandoned: false,
+        });
+        let _abandon = Abandon { channel: ch };
+        // Ждём, пока канал освободится
+        future::poll_fn(|cx| {
+            ch.waker.register(cx.waker());
+            let mut state = ch.state.lock();
+            if state.claimed || state.active.is_some() {
+                return Poll::Pending;
+            }
+            let mut transfer = request.take().expect("ATA transfer started twice");
+            match ch.start_next(&mut transfer) {
+                Ok(()) => state.active = Some(transfer),
+                Err(e) => {
+                    transfer.result = Some(Err(e));
+                    state.done = Some(transfer);
+                }
+            }
+            Poll::Ready(())
+        }).await;
+        loop {
+            let finished = future::poll_fn(|cx| {
+                ch.waker.register(cx.waker());
+                match ch.state.lock().done.take() {
+                    Some(transfer) => Poll::Ready(transfer),
+                    None => Poll::Pending,
+                }
+            });
+            match sync::select(finished, crate::task::sleep(IRQ_TIMEOUT_MS)).await {
+                sync::Either::Left(transfer) => {
+                    let result = transfer.result.unwrap_or(Err(BlockError::Io));
+                    return result.map(|()| transfer.data);
+                }
+                // Прерывание не пришло: опрашиваем канал сами
+                sync::Either::Right(()) => ch.service(&mut ch.state.lock()),
+            }
+        }
+    }
+}
+
+// Если future запроса отброшен, запрос на канале дорабатывает без получателя
+struct Abandon<'a> {
+    channel: &'a Channel,
+}
+
+impl Drop for Abandon<'_> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock();
+        if let Some(transfer) = state.active.as_mut() {
+            transfer.abandoned = true;
+        }
+        state.done = None;
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        let _claim = self.channel.claim();
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE).enumerate() {
+            self.read_chunk(lba + i as u64 * MAX_SECTORS_PER_COMMAND, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        let _claim = self.channel.claim();
+        for (i, chunk) in buf.chunks(MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE).enumerate() {
+            self.write_chunk(lba + i as u64 * MAX_SECTORS_PER_COMMAND, chunk)?;
+        }
+        Ok(())
+    }
+
+    // Без прерываний канала остаётся синхронный опрос из реализации по умолчанию
+    fn read_blocks_async<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        if !self.channel.irq_enabled.load(Ordering::Relaxed) {
+            return Box::pin(future::ready(self.read_blocks(lba, buf)));
+        }
+        Box::pin(async move {
+            check_range(self, lba, buf.len())?;
+            let data = self.transfer(lba, vec![0; buf.len()], false).await?;
+            buf.copy_from_slice(&data);
+            Ok(())
+        })
+    }
+
+    fn write_blocks_async<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        if !self.channel.irq_enabled.load(Ordering::Relaxed) {
+            return Box::pin(future::ready(self.write_blocks(lba, buf)));
+        }
+        Box::pin(async move {
+            check_range(self, lba, buf.len())?;
+            self.transfer(lba, buf.to_vec(), true).await.map(drop)
+        })
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        let ch = self.channel;
+        let _claim = ch.claim();
+        ch.select(self.slave, 0);
+        ch.write(REG_COMMAND, if self.lba48 { CMD_FLUSH_CACHE_EXT } else { CMD_FLUSH_CACHE });
+        let status = ch.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    fn description(&self) -> String {
+        format!("{} (ATA PIO, {})", self.model, if self.lba48 { "LBA48" } else { "LBA28" })
+    }
+}
+
+/// Опрашивает оба канала и регистрирует найденные диски как `hda`..`hdd`.
+pub fn init() {
+    let native = pci::devices().iter()
+        .filter(|dev| IDE_CONTROLLER.matches(dev))
+        .fold(0, |mask, dev| mask | dev.prog_if);
+    for (index, channel) in CHANNELS.iter().enumerate() {
+        let native_bit = if index == 0 { PROG_IF_PRIMARY_NATIVE } else { PROG_IF_SECONDARY_NATIVE };
+        // Пустой канал: шина "висит" в единицах
+        if native & native_bit != 0 || channel.alt_status() == 0xFF {
+            continue;
+        }
+        channel.set_control(CONTROL_NIEN);
+        let mut found = false;
+        for slave in [false, true] {
+            if let Some(id) = channel.identify(slave) {
+                let name = format!("hd{}", (b'a' + (index * 2 + slave as usize) as u8) as char);
+                let drive = AtaDrive::from_identify(name, channel, slave, &id);
+                if drive.sectors != 0 && (drive.lba48 || drive.sectors <= LBA28_LIMIT) {
+                    super::register(Arc::new(drive));
+                    found = true;
+                }
+            }
+        }
+        if !found {
+            continue;
+        }
+        let registered = irq::register_irq(channel.irq, "ata", move || {
+            channel.service(&mut channel.state.lock());
+            IrqReturn::Handled
+        });
+        if registered.is_ok() {
+            channel.irq_enabled.store(true, Ordering::Relaxed);
+            channel.set_control(0);
+        }
+    }
+}
+
+// Каналы в режиме совместимости уже опросил `init`; контроллер лишь закрепляем за драйвером
+fn probe(dev: &PciDevice) -> Result<(), &'static str> {
+    if dev.prog_if & PROG_IF_PRIMARY_NATIVE != 0 && dev.prog_if & PROG_IF_SECONDARY_NATIVE != 0 {
+        return Err("native-mode IDE is not supported");
+    }
+    Ok(())
+}
@@ -0,0 +1,415 @@
+//! Буферный кэш блоков с отложенной записью. Все диски регистрируются обёрнутыми в
+//! `CachedDevice`: чтение идёт через кэш, запись только помечает буфер грязным, а на
+//! диск его отправляет фоновая задача, `sync` или вытеснение.
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use super::{check_range, BlockDevice, BlockError, BlockFuture, BlockResult};
+use crate::println;
+use crate::task::sync::{self, mpsc, oneshot, Either, Notify};
+
+/// Столько блоков держим в памяти; при куче в 1 МиБ это 128 КиБ для 512-байтных секторов.
+const CAPACITY: usize = 256;
+const WRITEBACK_INTERVAL_MS: u64 = 5000;
+const SYNC_QUEUE: usize = 8;
+
+// (номер устройства в кэше, блок)
+type Key = (usize, u64);
+
+struct Buffer {
+    data: Vec<u8>,
+    dirty: bool,
+    // Сколько записей этого буфера сейчас в полёте; такой буфер не вытесняется
+    writers: u32,
+    stamp: u64,
+}
+
+struct Cache {
+    devices: BTreeMap<usize, Arc<dyn BlockDevice>>,
+    buffers: BTreeMap<Key, Buffer>,
+    // Порядок использования: самый старый штамп вытесняется первым
+    lru: BTreeMap<u64, Key>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    devices: BTreeMap::new(),
+    buffers: BTreeMap::new(),
+    lru: BTreeMap::new(),
+    clock: 0,
+    hits: 0,
+    misses: 0,
+});
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+// Кэш заполнен: фоновой задаче пора сбросить грязные буферы, не дожидаясь таймера
+static PRESSURE: Notify = Notify::new();
+// Очередная запись из кэша завершилась: `sync` ждёт так буферы, которые пишет не он
+static WRITE_DONE: Notify = Notify::new();
+
+// Ответ на запрос `sync`: число записанных блоков
+type SyncReply = oneshot::Sender<BlockResult<usize>>;
+
+lazy_static! {
+    // `sync` выполняет фоновая задача: так он не пересекается с её собственной записью
+    // и не вернётся раньше, чем допишутся уже забранные ею блоки.
+    // Получателя забирает `writeback_task` при запуске
+    static ref SYNC_REQUESTS: (mpsc::Sender<SyncReply>, Mutex<Option<mpsc::Receiver<SyncReply>>>) = {
+        let (sender, receiver) = mpsc::channel(SYNC_QUEUE);
+        (sender, Mutex::new(Some(receiver)))
+    };
+}
+
+impl Cache {
+    fn touch(&mut self, key: Key) -> Option<&mut Buffer> {
+        self.clock += 1;
+        let stamp = self.clock;
+        let buffer = self.buffers.get_mut(&key)?;
+        self.lru.remove(&buffer.stamp);
+        self.lru.insert(stamp, key);
+        buffer.stamp = stamp;
+        Some(buffer)
+    }
+
+    // Место под новый буфер освобождают только чистые жертвы: грязные пишет
+    // `make_room` без замка. Если таких нет, кэш временно растёт сверх лимита
+    fn insert(&mut self, key: Key, data: Vec<u8>, dirty: bool) {
+        while self.buffers.len() >= CAPACITY {
+            match self.victim(false) {
+                Some((stamp, victim)) => self.remove(stamp, victim),
+                None => break,
+            }
+        }
+        self.clock += 1;
+        self.lru.insert(self.clock, key);
+        self.buffers.insert(key, Buffer { data, dirty, writers: 0, stamp: self.clock });
+    }
+
+    /// Самый давно использованный буфер, который сейчас не пишется (и чистый, если не `dirty`).
+    fn victim(&self, dirty: bool) -> Option<(u64, Key)> {
+        self.lru.iter()
+            .map(|(&stamp, &key)| (stamp, key))
+            .find(|(_, key)| {
+                let buffer = &self.buffers[key];
+                buffer.writers == 0 && (dirty || !buffer.dirty)
+            })
+    }
+
+    fn remove(&mut self, stamp: u64, key: Key) {
+        self.lru.remove(&stamp);
+        self.buffers.remove(&key);
+    }
+
+    /// Копирует блоки из кэша, только если там есть все; иначе false.
+    fn read_cached(&mut self, id: usize, lba: u64, buf: &mut [u8]) -> bool {
+        let size = self.devices[&id].block_size();
+        let count = (buf.len() / size) as u64;
+        if !(lba..lba + count).all(|block| self.buffers.contains_key(&(id, block))) {
+            return false;
+        }
+        for (i, chunk) in buf.chunks_mut(size).enumerate() {
+            if let Some(buffer) = self.touch((id, lba + i as u64)) {
+                chunk.copy_from_slice(&buffer.data);
+            }
+        }
+        self.hits += count;
+        true
+    }
+
+    /// Кладёт прочитанные с диска блоки в кэш. Блоки, попавшие в кэш за время
+    /// чтения, новее диска: их содержимое копируется в `buf`.
+    fn fill(&mut self, id: usize, lba: u64, buf: &mut [u8]) {
+        let size = self.devices[&id].block_size();
+        for (i, chunk) in buf.chunks_mut(size).enumerate() {
+            let key = (id, lba + i as u64);
+            match self.touch(key) {
+                Some(buffer) => chunk.copy_from_slice(&buffer.data),
+                None => {
+                    self.misses += 1;
+                    self.insert(key, chunk.to_vec(), false);
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, id: usize, lba: u64, buf: &[u8]) {
+        let size = self.devices[&id].block_size();
+        for (i, block) in buf.chunks(size).enumerate() {
+            let key = (id, lba + i as u64);
+            match self.touch(key) {
+                Some(buffer) => {
+                    buffer.data.copy_from_slice(block);
+                    buffer.dirty = true;
+                }
+                None => {
+                    if self.buffers.len() >= CAPACITY {
+                        PRESSURE.notify_one();
+                    }
+                    self.insert(key, block.to_vec(), true);
+                }
+            }
+        }
+    }
+
+    /// Забирает грязные буферы (только устройства `only`, если задано), склеивая
+    /// соседние блоки в один запрос. Буферы помечаются чистыми и занятыми записью.
+    /// Буфер, который уже пишется, пропускается: две записи одного блока в полёте
+    /// могли бы завершиться в любом порядке, и на диске осталась бы старая версия.
+    fn take_dirty(&mut self, only: Option<usize>) -> Vec<Run> {
+        let mut runs: Vec<Run> = Vec::new();
+        for (&key, buffer) in self.buffers.iter_mut() {
+            if !buffer.dirty || buffer.writers > 0 || only.is_some_and(|id| id != key.0) {
+                continue;
+            }
+            buffer.dirty = false;
+            buffer.writers += 1;
+            match runs.last_mut() {
+                Some(run) if run.id == key.0 && run.lba + run.blocks == key.1 => {
+                    run.data.extend_from_slice(&buffer.data);
+                    run.blocks += 1;
+                }
+                _ => runs.push(Run {
+                    id: key.0,
+                    device: self.devices[&key.0].clone(),
+                    lba: key.1,
+                    blocks: 1,
+                    data: buffer.data.clone(),
+                }),
+            }
+        }
+        runs
+    }
+
+    fn writing(&self) -> bool {
+        self.buffers.values().any(|buffer| buffer.writers > 0)
+    }
+
+    fn finish(&mut self, run: &Run, ok: bool) {
+        for lba in run.lba..run.lba + run.blocks {
+            if let Some(buffer) = self.buffers.get_mut(&(run.id, lba)) {
+                buffer.writers -= 1;
+                // Неудачную запись повторим при следующем сбросе
+                if !ok {
+                    buffer.dirty = true;
+                }
+            }
+        }
+        WRITE_DONE.notify_one();
+    }
+}
+
+/// Освобождает место под `blocks` новых буферов. Грязную жертву помечает занятой
+/// записью, как `take_dirty`, и пишет на диск без замка кэша.
+fn make_room(blocks: usize) -> BlockResult<()> {
+    loop {
+        let run = {
+            let mut cache = CACHE.lock();
+            if cache.buffers.len() + blocks <= CAPACITY {
+                return Ok(());
+            }
+            let (stamp, key) = match cache.victim(true) {
+                Some(victim) => victim,
+                // Все буферы пишутся: кэш временно вырастет сверх лимита
+                None => return Ok(()),
+            };
+            let device = cache.devices[&key.0].clone();
+            let buffer = cache.buffers.get_mut(&key).unwrap();
+            if !buffer.dirty {
+                cache.remove(stamp, key);
+                continue;
+            }
+            buffer.dirty = false;
+            buffer.writers += 1;
+            Run { id: key.0, device, lba: key.1, blocks: 1, data: buffer.data.clone() }
+        };
+        let ok = run.device.write_blocks(run.lba, &run.data);
+        CACHE.lock().finish(&run, ok.is_ok());
+        ok?;
+    }
+}
+
+/// Непрерывный участок грязных блоков одного устройства.
+struct Run {
+    id: usize,
+    device: Arc<dyn BlockDevice>,
+    lba: u64,
+    blocks: u64,
+    data: Vec<u8>,
+}
+
+/// Устройство, читающее и пишущее через общий кэш.
+pub struct CachedDevice {
+    id: usize,
+    inner: Arc<dyn BlockDevice>,
+}
+
+impl CachedDevice {
+    pub fn new(inner: Arc<dyn BlockDevice>) -> CachedDevice {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        CACHE.lock().devices.insert(id, inner.clone());
+        CachedDevice { id, inner }
+    }
+
+    // Данные уже прочитаны: если место освободить не вышло, кэш просто подрастёт
+    fn fill(&self, lba: u64, buf: &mut [u8]) {
+        let _ = make_room(buf.len() / self.block_size());
+        CACHE.lock().fill(self.id, lba, buf);
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.inner.block_count()
+    }
+
+    // Промахи читаются с диска без замка кэша
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        if CACHE.lock().read_cached(self.id, lba, buf) {
+            return Ok(());
+        }
+        self.inner.read_blocks(lba, buf)?;
+        self.fill(lba, buf);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        make_room(buf.len() / self.block_size())?;
+        CACHE.lock().write(self.id, lba, buf);
+        Ok(())
+    }
+
+    fn read_blocks_async<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            check_range(self, lba, buf.len())?;
+            if CACHE.lock().read_cached(self.id, lba, buf) {
+                return Ok(());
+            }
+            self.inner.read_blocks_async(lba, buf).await?;
+            self.fill(lba, buf);
+            Ok(())
+        })
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        let runs = CACHE.lock().take_dirty(Some(self.id));
+        write_runs(&runs)?;
+        self.inner.flush()
+    }
+
+    fn description(&self) -> String {
+        self.inner.description()
+    }
+}
+
+// Пишет участки синхронно; ошибка не мешает записать остальные
+fn write_runs(runs: &[Run]) -> BlockResult<usize> {
+    let mut written = 0;
+    let mut result = Ok(());
+    for run in runs {
+        let ok = run.device.write_blocks(run.lba, &run.data);
+        CACHE.lock().finish(run, ok.is_ok());
+        match ok {
+            Ok(()) => written += run.blocks as usize,
+            Err(e) => result = Err(e),
+        }
+    }
+    result.map(|_| written)
+}
+
+/// Записывает все грязные блоки и сбрасывает кэш записи дисков; возвращает число блоков.
+pub async fn sync() -> BlockResult<usize> {
+    let (reply, result) = oneshot::channel();
+    if SYNC_REQUESTS.0.send(reply).await.is_err() {
+        return Err(BlockError::Io);
+    }
+    result.await.unwrap_or(Err(BlockError::Io))
+}
+
+async fn sync_all() -> BlockResult<usize> {
+    let mut written = 0;
+    let mut devices: Vec<(usize, Arc<dyn BlockDevice>)> = Vec::new();
+    loop {
+        let (runs, writing) = {
+            let mut cache = CACHE.lock();
+            (cache.take_dirty(None), cache.writing())
+        };
+        if runs.is_empty() {
+            // Буферы в полёте пишет кто-то другой (`flush` устройства, вытеснение): ждём его
+            if !writing {
+                break;
+            }
+            WRITE_DONE.notified().await;
+            continue;
+        }
+        written += write_runs_async(&runs).await?;
+        for run in &runs {
+            if !devices.iter().any(|(id, _)| *id == run.id) {
+                devices.push((run.id, run.device.clone()));
+            }
+        }
+    }
+    for (_, device) in &devices {
+        device.flush()?;
+    }
+    Ok(written)
+}
+
+// То же, что `write_runs`, но запросами, которые не занимают executor ожиданием
+async fn write_runs_async(runs: &[Run]) -> BlockResult<usize> {
+    let mut written = 0;
+    let mut result = Ok(());
+    for run in runs {
+        let ok = run.device.write_blocks_async(run.lba, &run.data).await;
+        CACHE.lock().finish(run, ok.is_ok());
+        match ok {
+            Ok(()) => written += run.blocks as usize,
+            Err(e) => {
+                println!("writeback: {}: block {}: {}", run.device.name(), run.lba, e);
+                result = Err(e);
+            }
+        }
+    }
+    result.map(|_| written)
+}
+
+/// Фоновая задача: раз в `WRITEBACK_INTERVAL_MS` (или раньше, если кэш заполнен)
+/// отправляет грязные блоки на диск и выполняет запросы `sync`.
+pub async fn writeback_task() {
+    let mut requests = SYNC_REQUESTS.1.lock().take().expect("writeback task started twice");
+    loop {
+        let timer = sync::select(crate::task::sleep(WRITEBACK_INTERVAL_MS), PRESSURE.notified());
+        match sync::select(timer, requests.recv()).await {
+            Either::Left(_) => {
+                let runs = CACHE.lock().take_dirty(None);
+                let _ = write_runs_async(&runs).await;
+            }
+            Either::Right(Some(reply)) => {
+                // Запросившая задача могла быть отменена, пока запрос ждал в очереди
+                if !reply.is_closed() {
+                    let _ = reply.send(sync_all().await);
+                }
+            }
+            Either::Right(None) => return,
+        }
+    }
+}
+
+pub fn print_stats() {
+    let cache = CACHE.lock();
+    let dirty = cache.buffers.values().filter(|b| b.dirty).count();
+    println!("Block cache: {}/{} buffers, {} dirty, {} hits, {} misses",
+        cache.buffers.len(), CAPACITY, dirty, cache.hits, cache.misses);
+}
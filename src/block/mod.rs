@@ -0,0 +1,155 @@
+//! Блочные устройства: общий интерфейс драйверов дисков и реестр устройств по имени.
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{fmt, future::Future, pin::Pin};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::println;
+
+pub mod ata;
+pub mod cache;
+pub mod partition;
+pub mod virtio;
+
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// Устройство сообщило об ошибке.
+    Io,
+    Timeout,
+    /// Блок за концом устройства или буфер не кратен размеру блока.
+    OutOfRange,
+    ReadOnly,
+    NoDevice,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BlockError::Io => "device error",
+            BlockError::Timeout => "device timeout",
+            BlockError::OutOfRange => "block out of range",
+            BlockError::ReadOnly => "read-only device",
+            BlockError::NoDevice => "no such device",
+        })
+    }
+}
+
+pub type BlockResult<T> = Result<T, BlockError>;
+pub type BlockFuture<'a> = Pin<Box<dyn Future<Output = BlockResult<()>> + Send + 'a>>;
+
+/// Диск, читаемый и записываемый целыми блоками.
+pub trait BlockDevice: Send + Sync {
+    /// Имя в стиле Linux: `hda`, `vdb`, ...
+    fn name(&self) -> &str;
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64;
+
+    /// Читает `buf.len() / block_size()` блоков начиная с `lba`.
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()>;
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()>;
+
+    /// Асинхронные варианты для задач executor'а. По умолчанию запрос
+    /// выполняется синхронно при первом опросе.
+    fn read_blocks_async<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(core::future::ready(self.read_blocks(lba, buf)))
+    }
+
+    fn write_blocks_async<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(core::future::ready(self.write_blocks(lba, buf)))
+    }
+
+    /// Сбрасывает кэш записи самого устройства.
+    fn flush(&self) -> BlockResult<()> {
+        Ok(())
+    }
+
+    /// Модель или другое описание для `disks`.
+    fn description(&self) -> String {
+        String::new()
+    }
+}
+
+/// Проверяет, что запрос целиком лежит на устройстве и состоит из целых блоков.
+pub fn check_range(device: &dyn BlockDevice, lba: u64, len: usize) -> BlockResult<u64> {
+    let size = device.block_size();
+    if !len.is_multiple_of(size) {
+        return Err(BlockError::OutOfRange);
+    }
+    let count = (len / size) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= device.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+/// Регистрирует диск; чтение и запись через реестр идут через буферный кэш.
+pub fn register(device: Arc<dyn BlockDevice>) {
+    add(Arc::new(cache::CachedDevice::new(device)));
+}
+
+// Разделы ложатся поверх уже кэшированного диска и второй раз не оборачиваются
+fn add(device: Arc<dyn BlockDevice>) {
+    interrupts::without_interrupts(|| DEVICES.lock().push(device));
+}
+
+pub fn get(name: &str) -> BlockResult<Arc<dyn BlockDevice>> {
+    interrupts::without_interrupts(|| {
+        DEVICES.lock().iter().find(|d| d.name() == name).cloned().ok_or(BlockError::NoDevice)
+    })
+}
+
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    interrupts::without_interrupts(|| DEVICES.lock().clone())
+}
+
+/// Ищет диски всех поддерживаемых типов и разделы на них; возвращает число дисков и разделов.
+pub fn init() -> (usize, usize) {
+    ata::init();
+    crate::pci::register_driver(&ata::DRIVER);
+    crate::pci::register_driver(&virtio::DRIVER);
+    let disks = devices();
+    let partitions = disks.iter().map(|disk| partition::scan(disk.clone())).sum();
+    (disks.len(), partitions)
+}
+
+pub fn list_devices() {
+    println!("NAME   SIZE(MiB)  MODEL");
+    for device in devices() {
+        let mib = device.block_count() * device.block_size() as u64 / (1024 * 1024);
+        println!("{:<6} {:>9}  {}", device.name(), mib, device.description());
+    }
+}
+
+/// Печатает блок `lba` устройства в шестнадцатеричном виде.
+pub async fn dump_block(name: &str, lba: u64) {
+    let result = match get(name) {
+        Ok(device) => {
+            let mut buf = alloc::vec![0u8; device.block_size()];
+            device.read_blocks_async(lba, &mut buf).await.map(|_| buf)
+        }
+        Err(e) => Err(e),
+    };
+    let buf = match result {
+        Ok(buf) => buf,
+        Err(e) => return println!("blkread: {}: {}", name, e),
+    };
+    for (i, row) in buf.chunks(16).enumerate() {
+        let mut line = String::new();
+        for byte in row {
+            line.push_str(&alloc::format!("{:02x} ", byte));
+        }
+        let text: String = row.iter()
+            .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' })
+            .collect();
+        println!("{:03x}: {} {}", i * 16, line, text);
+    }
+}
@@ -0,0 +1,361 @@
+//! Таблицы разделов MBR (с расширенными разделами) и GPT. Каждый найденный раздел
+//! регистрируется отдельным блочным устройством поверх диска: `hda1`, `vda2`, ...
+
+use alloc::{collections::BTreeSet, format, string::String, sync::Arc, vec, vec::Vec};
+use core::convert::TryInto;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use super::{check_range, BlockDevice, BlockFuture, BlockResult};
+use crate::println;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_TABLE: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+// Логические разделы в цепочке EBR нумеруются с 5, как в Linux
+const FIRST_LOGICAL: usize = 5;
+// Предел длины цепочки EBR, считая и записи без раздела; петли ловит множество пройденных EBR
+const MAX_EBR: usize = 64;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN: usize = 92;
+const GPT_ENTRY_MIN: usize = 128;
+const GPT_ENTRY_MAX: usize = 4096;
+const GPT_MAX_ENTRIES: u32 = 1024;
+// Массив записей читается в кучу целиком: обычно это 128 × 128 байт = 16 КиБ
+const GPT_ENTRIES_MAX_BYTES: usize = 64 * 1024;
+
+fn u32_at(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap())
+}
+
+/// CRC-32 (IEEE 802.3), которым GPT защищает заголовок и массив записей.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Раздел: окно `[start, start + count)` на диске.
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    start: u64,
+    count: u64,
+    scheme: &'static str,
+    kind: String,
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        self.disk.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        self.disk.write_blocks(self.start + lba, buf)
+    }
+
+    fn read_blocks_async<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        match check_range(self, lba, buf.len()) {
+            Ok(_) => self.disk.read_blocks_async(self.start + lba, buf),
+            Err(e) => alloc::boxed::Box::pin(core::future::ready(Err(e))),
+        }
+    }
+
+    fn write_blocks_async<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        match check_range(self, lba, buf.len()) {
+            Ok(_) => self.disk.write_blocks_async(self.start + lba, buf),
+            Err(e) => alloc::boxed::Box::pin(core::future::ready(Err(e))),
+        }
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        self.disk.flush()
+    }
+
+    fn description(&self) -> String {
+        format!("{} {}", self.scheme, self.kind)
+    }
+}
+
+// Найденные разделы, для `lsblk`
+static PARTITIONS: Mutex<Vec<Arc<Partition>>> = Mutex::new(Vec::new());
+
+// Сырая запись таблицы до того, как из неё сделано устройство
+struct Entry {
+    number: usize,
+    start: u64,
+    count: u64,
+    kind: String,
+}
+
+fn mbr_type_name(kind: u8) -> &'static str {
+    match kind {
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0E => "FAT16",
+        0x0B | 0x0C => "FAT32",
+        0x07 => "NTFS/exFAT",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0x8E => "Linux LVM",
+        0xEF => "EFI System",
+        _ => "",
+    }
+}
+
+fn is_extended(kind: u8) -> bool {
+    matches!(kind, 0x05 | 0x0F | 0x85)
+}
+
+// Четыре записи сектора MBR/EBR: (тип, начало, длина); None — это не таблица разделов
+fn mbr_entries(sector: &[u8]) -> Option<[(u8, u64, u64); 4]> {
+    if sector[510..512] != MBR_SIGNATURE {
+        return None;
+    }
+    let mut entries = [(0u8, 0u64, 0u64); 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &sector[MBR_TABLE + i * MBR_ENTRY_SIZE..MBR_TABLE + (i + 1) * MBR_ENTRY_SIZE];
+        // Загрузочный сектор ФС без таблицы разделов тоже кончается на 55 AA;
+        // у настоящих записей флаг активности — только 0x00 или 0x80
+        if raw[0] & 0x7F != 0 {
+            return None;
+        }
+        *entry = (raw[4], u32_at(raw, 8) as u64, u32_at(raw, 12) as u64);
+    }
+    Some(entries)
+}
+
+fn format_mbr_kind(kind: u8) -> String {
+    match mbr_type_name(kind) {
+        "" => format!("type {:02x}", kind),
+        name => format!("{} ({:02x})", name, kind),
+    }
+}
+
+fn parse_mbr(disk: &dyn BlockDevice, sector: &[u8]) -> Option<Vec<Entry>> {
+    let primary = mbr_entries(sector)?;
+    let total = disk.block_count();
+    let valid = |start: u64, count: u64| start > 0 && count > 0 && start + count <= total;
+    if primary.iter().any(|&(kind, start, count)| kind != 0 && !valid(start, count)) {
+        return None;
+    }
+    let mut found = Vec::new();
+    for (i, &(kind, start, count)) in primary.iter().enumerate() {
+        if kind == 0 {
+            continue;
+        }
+        // Сам расширенный раздел — лишь контейнер для логических, устройством его не делаем
+        if !is_extended(kind) {
+            found.push(Entry { number: i + 1, start, count, kind: format_mbr_kind(kind) });
+            continue;
+        }
+        // Цепочка EBR: первая запись — логический раздел относительно текущего EBR,
+        // вторая — следующий EBR относительно начала расширенного раздела
+        let mut ebr = start;
+        let mut number = FIRST_LOGICAL;
+        let mut visited = BTreeSet::new();
+        let mut buf = vec![0u8; disk.block_size()];
+        while visited.len() < MAX_EBR && visited.insert(ebr) && disk.read_blocks(ebr, &mut buf).is_ok() {
+            let entries = match mbr_entries(&buf) {
+                Some(entries) => entries,
+                None => break,
+            };
+            let (kind, rel, count) = entries[0];
+            if kind != 0 && valid(ebr + rel, count) {
+                found.push(Entry { number, start: ebr + rel, count, kind: format_mbr_kind(kind) });
+                number += 1;
+            }
+            let (next_kind, next, _) = entries[1];
+            if next_kind == 0 || next == 0 || !valid(start + next, 1) {
+                break;
+            }
+            ebr = start + next;
+        }
+    }
+    Some(found)
+}
+
+fn format_guid(g: &[u8]) -> String {
+    // Первые три поля GUID хранятся в little-endian
+    format!("{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32_at(g, 0), u16::from_le_bytes([g[4], g[5]]), u16::from_le_bytes([g[6], g[7]]),
+        g[8], g[9], g[10], g[11], g[12], g[13], g[14], g[15])
+}
+
+fn gpt_type_name(guid: &str) -> &'static str {
+    match guid {
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => "EFI System",
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux",
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap",
+        "E6D6D379-F507-44C2-A23C-238F2A3DF928" => "Linux LVM",
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Microsoft basic data",
+        "21686148-6449-6E6F-744E-656564454649" => "BIOS boot",
+        _ => "",
+    }
+}
+
+// Заголовок GPT в `lba` и массив его записей, если обе контрольные суммы сошлись
+fn read_gpt(disk: &dyn BlockDevice, lba: u64) -> Option<(Vec<u8>, Vec<u8>)> {
+    let size = disk.block_size();
+    let mut header = vec![0u8; size];
+    disk.read_blocks(lba, &mut header).ok()?;
+    if &header[..8] != GPT_SIGNATURE {
+        return None;
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if header_size < GPT_HEADER_MIN || header_size > size || u64_at(&header, 24) != lba {
+        return None;
+    }
+    let mut copy = header[..header_size].to_vec();
+    copy[16..20].fill(0);
+    if crc32(&copy) != u32_at(&header, 16) {
+        return None;
+    }
+    let entries_lba = u64_at(&header, 72);
+    let count = u32_at(&header, 80);
+    let entry_size = u32_at(&header, 84) as usize;
+    if count > GPT_MAX_ENTRIES || !(GPT_ENTRY_MIN..=GPT_ENTRY_MAX).contains(&entry_size)
+        || !entry_size.is_multiple_of(8) {
+        return None;
+    }
+    let bytes = count as usize * entry_size;
+    if bytes > GPT_ENTRIES_MAX_BYTES {
+        return None;
+    }
+    let mut entries = vec![0u8; bytes.div_ceil(size) * size];
+    disk.read_blocks(entries_lba, &mut entries).ok()?;
+    entries.truncate(bytes);
+    if crc32(&entries) != u32_at(&header, 88) {
+        return None;
+    }
+    Some((header, entries))
+}
+
+fn parse_gpt(disk: &dyn BlockDevice) -> Option<Vec<Entry>> {
+    // Основной заголовок в LBA 1, запасной — в последнем секторе
+    let (header, entries) = match read_gpt(disk, 1) {
+        Some(gpt) => gpt,
+        None => {
+            let backup = read_gpt(disk, disk.block_count() - 1)?;
+            println!("{}: primary GPT header is corrupt, using the backup", disk.name());
+            backup
+        }
+    };
+    let entry_size = u32_at(&header, 84) as usize;
+    let mut found = Vec::new();
+    for (i, raw) in entries.chunks_exact(entry_size).enumerate() {
+        if raw[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let (first, last) = (u64_at(raw, 32), u64_at(raw, 40));
+        if first == 0 || last < first || last >= disk.block_count() {
+            continue;
+        }
+        let guid = format_guid(&raw[..16]);
+        let label: String = core::char::decode_utf16(
+            raw[56..128].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).take_while(|&c| c != 0))
+            .map(|c| c.unwrap_or('?'))
+            .collect();
+        let mut kind = match gpt_type_name(&guid) {
+            "" => guid,
+            name => String::from(name),
+        };
+        if !label.is_empty() {
+            kind = format!("{} \"{}\"", kind, label);
+        }
+        found.push(Entry { number: i + 1, start: first, count: last - first + 1, kind });
+    }
+    Some(found)
+}
+
+// hda + 1 = hda1; имя, оканчивающееся цифрой, получает `p`: nvme0n1p1
+fn partition_name(disk: &str, number: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", disk, number)
+    } else {
+        format!("{}{}", disk, number)
+    }
+}
+
+/// Ищет таблицу разделов на диске и регистрирует разделы; возвращает их число.
+pub fn scan(disk: Arc<dyn BlockDevice>) -> usize {
+    let mut sector = vec![0u8; disk.block_size()];
+    if disk.read_blocks(0, &mut sector).is_err() {
+        return 0;
+    }
+    let protective = mbr_entries(&sector)
+        .is_some_and(|entries| entries.iter().any(|e| e.0 == MBR_GPT_PROTECTIVE));
+    let (scheme, entries) = if protective {
+        match parse_gpt(&*disk) {
+            Some(entries) => ("gpt", entries),
+            None => {
+                println!("{}: GPT header or entries failed CRC check", disk.name());
+                return 0;
+            }
+        }
+    } else {
+        match parse_mbr(&*disk, &sector) {
+            Some(entries) => ("mbr", entries),
+            None => return 0,
+        }
+    };
+    let count = entries.len();
+    for entry in entries {
+        let partition = Arc::new(Partition {
+            name: partition_name(disk.name(), entry.number),
+            disk: disk.clone(),
+            start: entry.start,
+            count: entry.count,
+            scheme,
+            kind: entry.kind,
+        });
+        interrupts::without_interrupts(|| PARTITIONS.lock().push(partition.clone()));
+        super::add(partition);
+    }
+    count
+}
+
+fn is_partition(name: &str) -> bool {
+    interrupts::without_interrupts(|| PARTITIONS.lock().iter().any(|p| p.name == name))
+}
+
+/// Диски и их разделы деревом, как `lsblk`.
+pub fn list_tree() {
+    println!("NAME        SIZE(MiB)  TYPE  START       INFO");
+    let partitions = interrupts::without_interrupts(|| PARTITIONS.lock().clone());
+    for disk in super::devices().into_iter().filter(|d| !is_partition(d.name())) {
+        let mib = |blocks: u64| blocks * disk.block_size() as u64 / (1024 * 1024);
+        println!("{:<11} {:>9}  disk              {}", disk.name(), mib(disk.block_count()), disk.description());
+        let children: Vec<&Arc<Partition>> = partitions.iter()
+            .filter(|p| p.disk.name() == disk.name())
+            .collect();
+        for (i, part) in children.iter().enumerate() {
+            let branch = if i + 1 == children.len() { "`-" } else { "|-" };
+            println!("{}{:<9} {:>9}  part  {:<10}  {}", branch, part.name, mib(part.count), part.start,
+                part.description());
+        }
+    }
+}
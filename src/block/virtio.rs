@@ -0,0 +1,478 @@
+//! virtio-blk через PCI: одна очередь запросов, данные идут через DMA-страницы
+//! из аллокатора фреймов, завершение приходит прерыванием (MSI-X или INTx)
+//! и будит задачу executor'а, ждущую запрос через `*_blocks_async`.
+//! Синхронные `read_blocks`/`write_blocks` (файловые системы, вытеснение из кэша)
+//! прерывания не ждут, а опрашивают used-кольцо до завершения запроса.
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use core::sync::atomic::{AtomicU8, Ordering};
+use futures_util::task::noop_waker_ref;
+use x86_64::PhysAddr;
+use super::{check_range, BlockDevice, BlockError, BlockFuture, BlockResult, SECTOR_SIZE};
+use crate::{irq::{self, IrqReturn}, lock::IrqSafeMutex, memory, pci::{MsixTable, PciDevice, PciDriver, PciMatch}};
+use crate::virtio::{self, Descriptor, Transport, VirtQueue, DESC_F_NEXT, DESC_F_WRITE, NO_VECTOR};
+
+const DEVICE_LEGACY: u16 = 0x1001;
+const DEVICE_MODERN: u16 = 0x1042;
+
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const REQ_FLUSH: u32 = 4;
+const STATUS_OK: u8 = 0;
+
+const QUEUE: u16 = 0;
+// На запрос — три дескриптора (заголовок, данные, статус) и одна DMA-страница данных
+const MAX_SLOTS: usize = 16;
+const SLOT_BYTES: usize = 4096;
+const HEADER_SIZE: u64 = 16;
+
+pub static DRIVER: PciDriver = PciDriver {
+    name: "virtio-blk",
+    matches: &[
+        PciMatch::Device { vendor: virtio::VENDOR_ID, device: DEVICE_LEGACY },
+        PciMatch::Device { vendor: virtio::VENDOR_ID, device: DEVICE_MODERN },
+    ],
+    probe,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlotState {
+    Free,
+    InFlight,
+    Done,
+    // Future отброшен, пока запрос был у устройства: слот освободится по завершении
+    Abandoned,
+}
+
+struct Slot {
+    state: SlotState,
+    status: u8,
+    waker: Option<Waker>,
+    data: PhysAddr,
+}
+
+struct Queue {
+    vq: VirtQueue,
+    slots: Vec<Slot>,
+}
+
+pub struct VirtioBlk {
+    name: String,
+    transport: Transport,
+    queue: IrqSafeMutex<Queue>,
+    // Заголовки запросов по 16 байт, за ними байты статуса
+    headers: PhysAddr,
+    sectors: u64,
+    read_only: bool,
+    can_flush: bool,
+    interrupt: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Read,
+    Write,
+    Flush,
+}
+
+impl VirtioBlk {
+    fn header(&self, slot: usize) -> PhysAddr {
+        self.headers + slot as u64 * HEADER_SIZE
+    }
+
+    fn status(&self, slot: usize) -> PhysAddr {
+        self.headers + MAX_SLOTS as u64 * HEADER_SIZE + slot as u64
+    }
+
+    /// Забирает завершённые запросы из used-кольца и будит их владельцев.
+    fn poll_completions(&self) {
+        let mut queue = self.queue.lock();
+        let queue = &mut *queue;
+        while let Some((head, _)) = queue.vq.pop_used() {
+            let index = head as usize / 3;
+            // Устройство вернуло чужой id: такого запроса не было
+            if head % 3 != 0 || index >= queue.slots.len() {
+                continue;
+            }
+            let status = unsafe { *memory::phys_to_virt(self.status(index)).as_ptr::<u8>() };
+            let slot = &mut queue.slots[index];
+            match slot.state {
+                SlotState::InFlight => {
+                    slot.state = SlotState::Done;
+                    slot.status = status;
+                    if let Some(waker) = slot.waker.take() {
+                        waker.wake();
+                    }
+                }
+                SlotState::Abandoned => slot.state = SlotState::Free,
+                _ => {}
+            }
+        }
+    }
+
+    /// Ставит запрос в очередь; None, если все слоты заняты.
+    fn submit(&self, op: Op, sector: u64, data: &[u8], len: usize, waker: &Waker) -> Option<usize> {
+        let mut queue = self.queue.lock();
+        let index = queue.slots.iter().position(|s| s.state == SlotState::Free)?;
+        let data_phys = queue.slots[index].data;
+        let (kind, data_flags) = match op {
+            Op::Read => (REQ_IN, DESC_F_WRITE),
+            Op::Write => (REQ_OUT, 0),
+            Op::Flush => (REQ_FLUSH, 0),
+        };
+        unsafe {
+            let header = memory::phys_to_virt(self.header(index)).as_mut_ptr::<u8>();
+            core::ptr::copy_nonoverlapping(kind.to_le_bytes().as_ptr(), header, 4);
+            core::ptr::write_bytes(header.add(4), 0, 4);
+            core::ptr::copy_nonoverlapping(sector.to_le_bytes().as_ptr(), header.add(8), 8);
+            *memory::phys_to_virt(self.status(index)).as_mut_ptr::<u8>() = 0xFF;
+            if op == Op::Write {
+                core::ptr::copy_nonoverlapping(data.as_ptr(), memory::phys_to_virt(data_phys).as_mut_ptr::<u8>(), len);
+            }
+        }
+        let head = (index * 3) as u16;
+        let status = Descriptor { addr: self.status(index).as_u64(), len: 1, flags: DESC_F_WRITE, next: 0 };
+        if op == Op::Flush {
+            queue.vq.set_descriptor(head, Descriptor {
+                addr: self.header(index).as_u64(), len: HEADER_SIZE as u32, flags: DESC_F_NEXT, next: head + 2,
+            });
+        } else {
+            queue.vq.set_descriptor(head, Descriptor {
+                addr: self.header(index).as_u64(), len: HEADER_SIZE as u32, flags: DESC_F_NEXT, next: head + 1,
+            });
+            queue.vq.set_descriptor(head + 1, Descriptor {
+                addr: data_phys.as_u64(), len: len as u32, flags: data_flags | DESC_F_NEXT, next: head + 2,
+            });
+        }
+        queue.vq.set_descriptor(head + 2, status);
+        let slot = &mut queue.slots[index];
+        slot.state = SlotState::InFlight;
+        slot.waker = Some(waker.clone());
+        queue.vq.push_avail(head);
+        // Уведомление под замком: современный транспорт выбирает очередь через общий регистр
+        self.transport.notify(QUEUE);
+        Some(index)
+    }
+
+    /// Результат запроса в слоте, если он завершён; для чтения копирует данные в `out`.
+    fn complete(&self, index: usize, out: Option<&mut [u8]>, waker: &Waker) -> Poll<BlockResult<()>> {
+        let mut queue = self.queue.lock();
+        let slot = &mut queue.slots[index];
+        if slot.state != SlotState::Done {
+            slot.waker = Some(waker.clone());
+            return Poll::Pending;
+        }
+        slot.state = SlotState::Free;
+        Poll::Ready(match slot.status {
+            STATUS_OK => {
+                if let Some(out) = out {
+                    let src = memory::phys_to_virt(slot.data).as_ptr::<u8>();
+                    unsafe { core::ptr::copy_nonoverlapping(src, out.as_mut_ptr(), out.len()) };
+                }
+                Ok(())
+            }
+            _ => Err(BlockError::Io),
+        })
+    }
+
+    fn abandon(&self, index: usize) {
+        let mut queue = self.queue.lock();
+        let slot = &mut queue.slots[index];
+        slot.waker = None;
+        slot.state = match slot.state {
+            SlotState::InFlight => SlotState::Abandoned,
+            _ => SlotState::Free,
+        };
+    }
+
+    fn request<'a>(&'a self, op: Op, lba: u64, buf: Buffer<'a>) -> Request<'a> {
+        Request { dev: self, op, lba, buf, done: 0, slot: None }
+    }
+
+    /// Синхронное ожидание: крутится, опрашивая used-кольцо, поэтому работает
+    /// и с выключенными прерываниями. Задачам лучше ждать через `*_blocks_async`.
+    fn wait(&self, mut request: Request) -> BlockResult<()> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        loop {
+            if let Poll::Ready(result) = Pin::new(&mut request).poll(&mut cx) {
+                return result;
+            }
+            self.poll_completions();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+enum Buffer<'a> {
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+    None,
+}
+
+impl Buffer<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Buffer::In(buf) => buf.len(),
+            Buffer::Out(buf) => buf.len(),
+            Buffer::None => 0,
+        }
+    }
+}
+
+/// Запрос, разбитый на куски по одной DMA-странице; куски идут по очереди.
+struct Request<'a> {
+    dev: &'a VirtioBlk,
+    op: Op,
+    lba: u64,
+    buf: Buffer<'a>,
+    done: usize,
+    slot: Option<usize>,
+}
+
+impl Future for Request<'_> {
+    type Output = BlockResult<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let chunk = (this.buf.len() - this.done).min(SLOT_BYTES);
+            match this.slot {
+                None => {
+                    if chunk == 0 && this.op != Op::Flush {
+                        return Poll::Ready(Ok(()));
+                    }
+                    let sector = this.lba + (this.done / SECTOR_SIZE) as u64;
+                    let data = match &this.buf {
+                        Buffer::Out(buf) => &buf[this.done..this.done + chunk],
+                        _ => &[],
+                    };
+                    match this.dev.submit(this.op, sector, data, chunk, cx.waker()) {
+                        Some(slot) => this.slot = Some(slot),
+                        None => {
+                            // Все слоты заняты: попробуем на следующем опросе
+                            cx.waker().wake_by_ref();
+                            return Poll::Pending;
+                        }
+                    }
+                }
+                Some(slot) => {
+                    let out = match &mut this.buf {
+                        Buffer::In(buf) => Some(&mut buf[this.done..this.done + chunk]),
+                        _ => None,
+                    };
+                    match this.dev.complete(slot, out, cx.waker()) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(result) => {
+                            this.slot = None;
+                            result?;
+                            if this.op == Op::Flush {
+                                return Poll::Ready(Ok(()));
+                            }
+                            this.done += chunk;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Request<'_> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.dev.abandon(slot);
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        self.wait(self.request(Op::Read, lba, Buffer::In(buf)))
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_range(self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        self.wait(self.request(Op::Write, lba, Buffer::Out(buf)))
+    }
+
+    fn read_blocks_async<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        match check_range(self, lba, buf.len()) {
+            Ok(_) => Box::pin(self.request(Op::Read, lba, Buffer::In(buf))),
+            Err(e) => Box::pin(core::future::ready(Err(e))),
+        }
+    }
+
+    fn write_blocks_async<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        let checked = check_range(self, lba, buf.len())
+            .and(if self.read_only { Err(BlockError::ReadOnly) } else { Ok(()) });
+        match checked {
+            Ok(()) => Box::pin(self.request(Op::Write, lba, Buffer::Out(buf))),
+            Err(e) => Box::pin(core::future::ready(Err(e))),
+        }
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        if !self.can_flush {
+            return Ok(());
+        }
+        self.wait(self.request(Op::Flush, 0, Buffer::None))
+    }
+
+    fn description(&self) -> String {
+        format!("virtio-blk ({}, {}{})",
+            if self.transport.is_modern() { "virtio 1.0" } else { "legacy" },
+            self.interrupt,
+            if self.read_only { ", read-only" } else { "" })
+    }
+}
+
+fn probe(dev: &PciDevice) -> Result<(), &'static str> {
+    dev.enable();
+    let mut transport = Transport::new(dev)?;
+    transport.begin_init();
+    let prepared = match prepare(dev, &mut transport) {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            transport.fail();
+            return Err(e);
+        }
+    };
+    static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+    let blk = Arc::new(VirtioBlk {
+        name: format!("vd{}", (b'a' + index) as char),
+        transport,
+        queue: IrqSafeMutex::new("virtio_blk_queue", prepared.queue),
+        headers: prepared.headers,
+        sectors: prepared.sectors,
+        read_only: prepared.features & F_RO != 0,
+        can_flush: prepared.features & F_FLUSH != 0,
+        interrupt: match prepared.msix_table {
+            Some(_) => "MSI-X",
+            None if use_msi(dev) => "MSI",
+            None => "INTx",
+        },
+    });
+    if let Err(e) = connect_interrupt(dev, &blk, prepared.msix_table) {
+        blk.transport.fail();
+        return Err(e);
+    }
+    blk.transport.add_status(virtio::STATUS_DRIVER_OK);
+    super::register(blk);
+    Ok(())
+}
+
+struct Prepared {
+    features: u64,
+    msix_table: Option<MsixTable>,
+    sectors: u64,
+    queue: Queue,
+    headers: PhysAddr,
+}
+
+fn prepare(dev: &PciDevice, transport: &mut Transport) -> Result<Prepared, &'static str> {
+    let features = transport.negotiate(F_RO | F_FLUSH)?;
+    // MSI-X включаем до настройки очереди: в legacy от него зависит раскладка регистров
+    let msix_table = match dev.msix {
+        Some(_) if crate::apic::is_enabled() => {
+            let table = dev.map_msix_table()?;
+            dev.enable_msix()?;
+            transport.set_msix(true);
+            Some(table)
+        }
+        _ => None,
+    };
+    let sectors = transport.read_config_u64(0);
+    if sectors == 0 {
+        return Err("device has zero capacity");
+    }
+    let size = transport.queue_size(QUEUE);
+    if size < 3 {
+        return Err("request queue is missing");
+    }
+    let vq = VirtQueue::new(size).ok_or("out of DMA memory")?;
+    let slot_count = MAX_SLOTS.min(size as usize / 3);
+    let mut slots = Vec::with_capacity(slot_count);
+    for _ in 0..slot_count {
+        let data = memory::allocate_dma(SLOT_BYTES / 4096).ok_or("out of DMA memory")?;
+        slots.push(Slot { state: SlotState::Free, status: 0, waker: None, data });
+    }
+    let headers = memory::allocate_dma(1).ok_or("out of DMA memory")?;
+    Ok(Prepared { features, msix_table, sectors, queue: Queue { vq, slots }, headers })
+}
+
+fn use_msi(dev: &PciDevice) -> bool {
+    dev.msi.is_some() && crate::apic::is_enabled()
+}
+
+// Замок очереди не держим при регистрации: dispatch вызывает обработчик под замком таблицы IRQ
+fn connect_interrupt(dev: &PciDevice, blk: &Arc<VirtioBlk>, msix_table: Option<MsixTable>) -> Result<(), &'static str> {
+    let handler_blk = blk.clone();
+    let (handle, vector) = match msix_table {
+        Some(table) => {
+            let (handle, vector) = irq::register_msi("virtio-blk", move || {
+                handler_blk.poll_completions();
+                IrqReturn::Handled
+            })?;
+            if let Err(e) = table.set_vector(0, vector) {
+                irq::unregister_irq(handle);
+                return Err(e);
+            }
+            blk.transport.disable_config_vector();
+            // Для virtio вектор очереди — номер записи в таблице MSI-X
+            (handle, 0)
+        }
+        // Обычный MSI приходит вместо INTx, поэтому ISR всё равно читаем: он сбрасывает прерывание
+        None if use_msi(dev) => {
+            let (handle, vector) = irq::register_msi("virtio-blk", move || {
+                handler_blk.transport.ack_interrupt();
+                handler_blk.poll_completions();
+                IrqReturn::Handled
+            })?;
+            if let Err(e) = dev.enable_msi(vector) {
+                irq::unregister_irq(handle);
+                return Err(e);
+            }
+            (handle, NO_VECTOR)
+        }
+        None => {
+            if dev.interrupt_pin == 0 {
+                return Err("device has neither MSI nor INTx");
+            }
+            let handle = irq::register_irq(dev.interrupt_line, "virtio-blk", move || {
+                if handler_blk.transport.ack_interrupt() {
+                    handler_blk.poll_completions();
+                    IrqReturn::Handled
+                } else {
+                    IrqReturn::None
+                }
+            })?;
+            (handle, NO_VECTOR)
+        }
+    };
+    let result = {
+        let queue = blk.queue.lock();
+        blk.transport.setup_queue(QUEUE, &queue.vq, vector)
+    };
+    // Без очереди устройство не используется: обработчик (и с ним ссылка на blk) не нужен
+    if result.is_err() {
+        irq::unregister_irq(handle);
+    }
+    result
+}
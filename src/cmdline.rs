@@ -0,0 +1,20 @@
+//! Параметры загрузки. bootloader 0.9 не передаёт командную строку,
+//! поэтому она задаётся при сборке: `TM_OS_CMDLINE="noapic" cargo run`.
+
+const CMDLINE: &str = match option_env!("TM_OS_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+/// Есть ли флаг вида `noapic`.
+pub fn has_flag(flag: &str) -> bool {
+    CMDLINE.split_whitespace().any(|arg| arg == flag)
+}
+
+/// Значение параметра вида `key=value`.
+pub fn value(key: &str) -> Option<&'static str> {
+    CMDLINE.split_whitespace()
+        .filter_map(|arg| arg.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
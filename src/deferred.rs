@@ -0,0 +1,75 @@
+//! Отложенная работа ("нижние половины"): обработчик прерывания кладёт сюда
+//! короткое задание и сразу возвращается, а выполняет его задача `softirq`
+//! уже вне прерывания, где можно брать замки вроде `WRITER` и выделять память.
+
+use conquer_once::spin::OnceCell;
+use core::{future::Future, pin::Pin, task::{Context, Poll}};
+use core::sync::atomic::{AtomicU64, Ordering};
+use crossbeam_queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+use crate::println;
+
+const QUEUE_CAPACITY: usize = 64;
+
+#[derive(Clone, Copy)]
+struct Work {
+    func: fn(u64),
+    arg: u64,
+}
+
+static QUEUE: OnceCell<ArrayQueue<Work>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static DONE: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Создаёт очередь; до этого `defer` отбрасывает задания.
+pub fn init() {
+    QUEUE.init_once(|| ArrayQueue::new(QUEUE_CAPACITY));
+}
+
+/// Ставит `func(arg)` в очередь. Можно звать из прерывания: не выделяет память
+/// и не берёт замков. Если очередь переполнена, задание теряется.
+pub fn defer(func: fn(u64), arg: u64) -> bool {
+    let queued = QUEUE.get().is_some_and(|queue| queue.push(Work { func, arg }).is_ok());
+    if queued {
+        WAKER.wake();
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    queued
+}
+
+/// Задача, выполняющая отложенную работу.
+pub async fn run() {
+    loop {
+        let work = NextWork.await;
+        (work.func)(work.arg);
+        DONE.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct NextWork;
+
+impl Future for NextWork {
+    type Output = Work;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Work> {
+        let queue = QUEUE.try_get().expect("deferred queue not initialized");
+        if let Some(work) = queue.pop() {
+            return Poll::Ready(work);
+        }
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(work) => {
+                WAKER.take();
+                Poll::Ready(work)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+pub fn print_stats() {
+    let pending = QUEUE.get().map_or(0, |queue| queue.len());
+    println!("Deferred work: {} done, {} pending, {} dropped",
+        DONE.load(Ordering::Relaxed), pending, DROPPED.load(Ordering::Relaxed));
+}
@@ -0,0 +1,1037 @@
+//! ext2 на блочном устройстве: суперблок, группы блоков, inode с прямыми и косвенными
+//! блоками, каталоги и символические ссылки. Пишет файлы и каталоги, если ФС не
+//! требует неизвестных нам возможностей; иначе монтируется только для чтения.
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::convert::TryInto;
+use spin::Mutex;
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+use crate::block::BlockDevice;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xEF53;
+const ROOT_INO: u32 = 2;
+
+// Значения для ревизии 0, где в суперблоке этих полей нет
+const GOOD_OLD_FIRST_INO: u32 = 11;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+const GROUP_DESC_SIZE: usize = 32;
+
+const S_IFMT: u16 = 0xF000;
+const S_IFREG: u16 = 0x8000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFLNK: u16 = 0xA000;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_SYMLINK: u8 = 7;
+
+// Каталог с хеш-индексом; при линейном изменении индекс сбрасываем, как делает Linux
+const INDEX_FL: u32 = 0x1000;
+
+const DIRECT_BLOCKS: usize = 12;
+const BLOCK_POINTERS: usize = 15;
+const DIR_ENTRY_HEADER: usize = 8;
+const MAX_NAME: usize = 255;
+
+fn u16_at(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off..off + 2].try_into().unwrap())
+}
+
+fn u32_at(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+fn put_u16(b: &mut [u8], off: usize, value: u16) {
+    b[off..off + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(b: &mut [u8], off: usize, value: u32) {
+    b[off..off + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn dir_entry_len(name_len: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_len + 3) & !3
+}
+
+/// Поля inode, с которыми мы работаем; остальное на диске не трогается.
+#[derive(Clone)]
+struct RawInode {
+    mode: u16,
+    size: u64,
+    dtime: u32,
+    links: u16,
+    // В 512-байтных секторах, включая косвенные блоки
+    sectors: u32,
+    flags: u32,
+    block: [u32; BLOCK_POINTERS],
+    generation: u32,
+    file_acl: u32,
+}
+
+impl RawInode {
+    fn parse(b: &[u8]) -> RawInode {
+        let mode = u16_at(b, 0);
+        let mut size = u32_at(b, 4) as u64;
+        // Для каталогов старшая половина размера — это dir_acl
+        if mode & S_IFMT == S_IFREG {
+            size |= (u32_at(b, 108) as u64) << 32;
+        }
+        let mut block = [0u32; BLOCK_POINTERS];
+        for (i, ptr) in block.iter_mut().enumerate() {
+            *ptr = u32_at(b, 40 + i * 4);
+        }
+        RawInode {
+            mode,
+            size,
+            dtime: u32_at(b, 20),
+            links: u16_at(b, 26),
+            sectors: u32_at(b, 28),
+            flags: u32_at(b, 32),
+            block,
+            generation: u32_at(b, 100),
+            file_acl: u32_at(b, 104),
+        }
+    }
+
+    fn store(&self, b: &mut [u8]) {
+        put_u16(b, 0, self.mode);
+        put_u32(b, 4, self.size as u32);
+        put_u32(b, 20, self.dtime);
+        put_u16(b, 26, self.links);
+        put_u32(b, 28, self.sectors);
+        put_u32(b, 32, self.flags);
+        for (i, ptr) in self.block.iter().enumerate() {
+            put_u32(b, 40 + i * 4, *ptr);
+        }
+        put_u32(b, 100, self.generation);
+        if self.mode & S_IFMT == S_IFREG {
+            put_u32(b, 108, (self.size >> 32) as u32);
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            _ => FileType::File,
+        }
+    }
+}
+
+struct Group {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+}
+
+struct RawDirEntry {
+    ino: u32,
+    file_type: u8,
+    name: String,
+}
+
+// Запись внутри блока каталога: смещение, inode, длина записи и длина имени
+struct Slot {
+    offset: usize,
+    ino: u32,
+    rec_len: usize,
+    name_len: usize,
+}
+
+fn parse_block(block: &[u8]) -> FsResult<Vec<Slot>> {
+    let mut slots = Vec::new();
+    let mut offset = 0;
+    while offset + DIR_ENTRY_HEADER <= block.len() {
+        let rec_len = u16_at(block, offset + 4) as usize;
+        let name_len = block[offset + 6] as usize;
+        if rec_len < DIR_ENTRY_HEADER || !rec_len.is_multiple_of(4) || offset + rec_len > block.len()
+            || DIR_ENTRY_HEADER + name_len > rec_len {
+            return Err(FsError::Io);
+        }
+        slots.push(Slot { offset, ino: u32_at(block, offset), rec_len, name_len });
+        offset += rec_len;
+    }
+    Ok(slots)
+}
+
+struct Ext2 {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    sectors_per_block: u64,
+    blocks_count: u32,
+    inodes_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    first_ino: u32,
+    group_count: u32,
+    filetype: bool,
+    large_file: bool,
+    read_only: bool,
+    // Время последней записи из суперблока: своих часов реального времени у нас нет
+    write_time: u32,
+    // Метаданные (битовые карты, inode, каталоги) меняются только под этим замком
+    lock: Mutex<()>,
+}
+
+impl Ext2 {
+    fn parse(device: Arc<dyn BlockDevice>) -> FsResult<Ext2> {
+        let sb = read_bytes(&*device, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE)?;
+        if u16_at(&sb, 56) != EXT2_MAGIC {
+            return Err(FsError::Unsupported);
+        }
+        let log_block_size = u32_at(&sb, 24);
+        if log_block_size > 6 {
+            return Err(FsError::Unsupported);
+        }
+        let block_size = 1024usize << log_block_size;
+        if !block_size.is_multiple_of(device.block_size()) {
+            return Err(FsError::Unsupported);
+        }
+        let revision = u32_at(&sb, 76);
+        let (first_ino, inode_size, incompat, ro_compat) = if revision == 0 {
+            (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE, 0, 0)
+        } else {
+            (u32_at(&sb, 84), u16_at(&sb, 88) as usize, u32_at(&sb, 96), u32_at(&sb, 100))
+        };
+        // Экстенты, 64-битные группы, незавершённый журнал и прочее из ext3/4 не читаем
+        if incompat & !(INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG) != 0 {
+            return Err(FsError::Unsupported);
+        }
+        let blocks_count = u32_at(&sb, 4);
+        let first_data_block = u32_at(&sb, 20);
+        let blocks_per_group = u32_at(&sb, 32);
+        let inodes_per_group = u32_at(&sb, 40);
+        if blocks_per_group == 0 || inodes_per_group == 0 || blocks_count <= first_data_block
+            || inode_size < GOOD_OLD_INODE_SIZE || !inode_size.is_power_of_two() || inode_size > block_size
+            || blocks_count as u64 * (block_size / device.block_size()) as u64 > device.block_count() {
+            return Err(FsError::Unsupported);
+        }
+        // Битовые карты блоков и inode группы занимают ровно по одному блоку
+        let bits_per_block = 8 * block_size as u64;
+        if blocks_per_group as u64 > bits_per_block || inodes_per_group as u64 > bits_per_block {
+            return Err(FsError::Unsupported);
+        }
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group);
+        Ok(Ext2 {
+            block_size,
+            sectors_per_block: (block_size / device.block_size()) as u64,
+            device,
+            blocks_count,
+            inodes_count: u32_at(&sb, 0),
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            inode_size,
+            first_ino,
+            group_count,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            large_file: ro_compat & RO_COMPAT_LARGE_FILE != 0,
+            read_only: ro_compat & !(RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE) != 0,
+            write_time: u32_at(&sb, 48),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn read_block(&self, block: u32) -> FsResult<Vec<u8>> {
+        if block >= self.blocks_count {
+            return Err(FsError::Io);
+        }
+        let mut data = vec![0u8; self.block_size];
+        self.device.read_blocks(block as u64 * self.sectors_per_block, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&self, block: u32, data: &[u8]) -> FsResult<()> {
+        if block >= self.blocks_count {
+            return Err(FsError::Io);
+        }
+        Ok(self.device.write_blocks(block as u64 * self.sectors_per_block, data)?)
+    }
+
+    // Дескрипторы групп лежат в блоке сразу за суперблоком
+    fn group_location(&self, group: u32) -> (u32, usize) {
+        let byte = group as usize * GROUP_DESC_SIZE;
+        (self.first_data_block + 1 + (byte / self.block_size) as u32, byte % self.block_size)
+    }
+
+    fn group(&self, group: u32) -> FsResult<Group> {
+        if group >= self.group_count {
+            return Err(FsError::Io);
+        }
+        let (block, off) = self.group_location(group);
+        let data = self.read_block(block)?;
+        let d = &data[off..off + GROUP_DESC_SIZE];
+        Ok(Group {
+            block_bitmap: u32_at(d, 0),
+            inode_bitmap: u32_at(d, 4),
+            inode_table: u32_at(d, 8),
+            free_blocks: u16_at(d, 12),
+            free_inodes: u16_at(d, 14),
+        })
+    }
+
+    /// Поправляет счётчики свободных блоков и inode в группе и суперблоке.
+    fn adjust_counts(&self, group: u32, blocks: i32, inodes: i32, dirs: i32) -> FsResult<()> {
+        let (block, off) = self.group_location(group);
+        let mut data = self.read_block(block)?;
+        for (field, delta) in [(12, blocks), (14, inodes), (16, dirs)] {
+            let value = u16_at(&data, off + field) as i32 + delta;
+            put_u16(&mut data, off + field, value as u16);
+        }
+        self.write_block(block, &data)?;
+        let mut sb = read_bytes(&*self.device, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE)?;
+        for (field, delta) in [(12, blocks), (16, inodes)] {
+            let value = u32_at(&sb, field) as i64 + delta as i64;
+            put_u32(&mut sb, field, value as u32);
+        }
+        write_bytes(&*self.device, SUPERBLOCK_OFFSET, &sb)
+    }
+
+    // Первый свободный бит карты среди `limit`; помечается занятым
+    fn take_bit(&self, bitmap: u32, limit: u32) -> FsResult<Option<u32>> {
+        let mut data = self.read_block(bitmap)?;
+        for bit in 0..limit.min(self.block_size as u32 * 8) {
+            let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+            if data[byte] & mask == 0 {
+                data[byte] |= mask;
+                self.write_block(bitmap, &data)?;
+                return Ok(Some(bit));
+            }
+        }
+        Ok(None)
+    }
+
+    fn clear_bit(&self, bitmap: u32, bit: u32) -> FsResult<()> {
+        let mut data = self.read_block(bitmap)?;
+        let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+        if data[byte] & mask == 0 {
+            return Err(FsError::Io);
+        }
+        data[byte] &= !mask;
+        self.write_block(bitmap, &data)
+    }
+
+    /// Занимает обнулённый блок, по возможности в группе `goal`, и учитывает его в `inode`.
+    fn allocate_block(&self, inode: &mut RawInode, goal: u32) -> FsResult<u32> {
+        let goal = goal.min(self.group_count - 1);
+        for g in (goal..self.group_count).chain(0..goal) {
+            let group = self.group(g)?;
+            if group.free_blocks == 0 {
+                continue;
+            }
+            let first = self.first_data_block + g * self.blocks_per_group;
+            let limit = self.blocks_per_group.min(self.blocks_count - first);
+            if let Some(bit) = self.take_bit(group.block_bitmap, limit)? {
+                self.adjust_counts(g, -1, 0, 0)?;
+                let block = first + bit;
+                self.write_block(block, &vec![0u8; self.block_size])?;
+                inode.sectors += (self.block_size / 512) as u32;
+                return Ok(block);
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_block(&self, inode: &mut RawInode, block: u32) -> FsResult<()> {
+        if block < self.first_data_block || block >= self.blocks_count {
+            return Err(FsError::Io);
+        }
+        let g = (block - self.first_data_block) / self.blocks_per_group;
+        let bit = (block - self.first_data_block) % self.blocks_per_group;
+        self.clear_bit(self.group(g)?.block_bitmap, bit)?;
+        self.adjust_counts(g, 1, 0, 0)?;
+        inode.sectors = inode.sectors.saturating_sub((self.block_size / 512) as u32);
+        Ok(())
+    }
+
+    fn allocate_inode(&self, goal: u32, is_dir: bool) -> FsResult<u32> {
+        let goal = goal.min(self.group_count - 1);
+        for g in (goal..self.group_count).chain(0..goal) {
+            let group = self.group(g)?;
+            if group.free_inodes == 0 {
+                continue;
+            }
+            if let Some(bit) = self.take_bit(group.inode_bitmap, self.inodes_per_group)? {
+                let ino = g * self.inodes_per_group + bit + 1;
+                // Зарезервированные inode mkfs помечает занятыми; это просто страховка
+                if ino < self.first_ino || ino > self.inodes_count {
+                    return Err(FsError::Io);
+                }
+                self.adjust_counts(g, 0, -1, if is_dir { 1 } else { 0 })?;
+                return Ok(ino);
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_inode(&self, ino: u32, is_dir: bool) -> FsResult<()> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(FsError::Io);
+        }
+        let g = (ino - 1) / self.inodes_per_group;
+        self.clear_bit(self.group(g)?.inode_bitmap, (ino - 1) % self.inodes_per_group)?;
+        self.adjust_counts(g, 0, 1, if is_dir { -1 } else { 0 })
+    }
+
+    fn group_of_inode(&self, ino: u32) -> u32 {
+        (ino - 1) / self.inodes_per_group
+    }
+
+    fn inode_location(&self, ino: u32) -> FsResult<(u32, usize)> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(FsError::Io);
+        }
+        let table = self.group(self.group_of_inode(ino))?.inode_table;
+        let byte = ((ino - 1) % self.inodes_per_group) as usize * self.inode_size;
+        Ok((table + (byte / self.block_size) as u32, byte % self.block_size))
+    }
+
+    fn read_inode(&self, ino: u32) -> FsResult<RawInode> {
+        let (block, off) = self.inode_location(ino)?;
+        Ok(RawInode::parse(&self.read_block(block)?[off..off + self.inode_size]))
+    }
+
+    fn write_inode(&self, ino: u32, inode: &RawInode) -> FsResult<()> {
+        let (block, off) = self.inode_location(ino)?;
+        let mut data = self.read_block(block)?;
+        inode.store(&mut data[off..off + self.inode_size]);
+        self.write_block(block, &data)
+    }
+
+    // Новый inode: запись на диске обнуляется целиком, поколение растёт
+    fn init_inode(&self, ino: u32, mode: u16, links: u16) -> FsResult<RawInode> {
+        let (block, off) = self.inode_location(ino)?;
+        let mut data = self.read_block(block)?;
+        let record = &mut data[off..off + self.inode_size];
+        let generation = u32_at(record, 100).wrapping_add(1);
+        record.iter_mut().for_each(|b| *b = 0);
+        let inode = RawInode {
+            mode,
+            size: 0,
+            dtime: 0,
+            links,
+            sectors: 0,
+            flags: 0,
+            block: [0; BLOCK_POINTERS],
+            generation,
+            file_acl: 0,
+        };
+        inode.store(record);
+        self.write_block(block, &data)?;
+        Ok(inode)
+    }
+
+    fn pointers_per_block(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    /// Ячейка `i_block` и смещения в косвенных блоках для логического блока `index`.
+    fn block_path(&self, index: u64) -> FsResult<(usize, Vec<usize>)> {
+        let p = self.pointers_per_block();
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok((index as usize, Vec::new()));
+        }
+        let mut index = index - DIRECT_BLOCKS as u64;
+        let mut span = p;
+        for depth in 1..=3 {
+            if index < span {
+                let mut offsets = vec![0; depth];
+                for level in (0..depth).rev() {
+                    offsets[level] = (index % p) as usize;
+                    index /= p;
+                }
+                return Ok((DIRECT_BLOCKS - 1 + depth, offsets));
+            }
+            index -= span;
+            span *= p;
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Физический блок для логического `index`; 0 — дыра.
+    fn map_block(&self, inode: &RawInode, index: u64) -> FsResult<u32> {
+        let (slot, offsets) = self.block_path(index)?;
+        let mut ptr = inode.block[slot];
+        for off in offsets {
+            if ptr == 0 {
+                return Ok(0);
+            }
+            ptr = u32_at(&self.read_block(ptr)?, off * 4);
+        }
+        Ok(ptr)
+    }
+
+    /// Как `map_block`, но недостающие блоки (и косвенные) выделяются.
+    fn map_block_alloc(&self, inode: &mut RawInode, index: u64, goal: u32) -> FsResult<u32> {
+        let (slot, offsets) = self.block_path(index)?;
+        if inode.block[slot] == 0 {
+            inode.block[slot] = self.allocate_block(inode, goal)?;
+        }
+        let mut ptr = inode.block[slot];
+        for off in offsets {
+            let mut data = self.read_block(ptr)?;
+            let mut next = u32_at(&data, off * 4);
+            if next == 0 {
+                next = self.allocate_block(inode, goal)?;
+                put_u32(&mut data, off * 4, next);
+                self.write_block(ptr, &data)?;
+            }
+            ptr = next;
+        }
+        Ok(ptr)
+    }
+
+    /// Освобождает все блоки начиная с логического `keep`.
+    fn truncate_blocks(&self, inode: &mut RawInode, keep: u64) -> FsResult<()> {
+        let mut first = 0u64;
+        for slot in 0..BLOCK_POINTERS {
+            let depth = slot.saturating_sub(DIRECT_BLOCKS - 1) as u32;
+            let block = inode.block[slot];
+            if block != 0 && self.trim(inode, block, depth, first, keep)? {
+                inode.block[slot] = 0;
+            }
+            first += self.pointers_per_block().pow(depth);
+        }
+        Ok(())
+    }
+
+    // Поддерево глубины `depth` покрывает логические блоки с `first`; true — освобождено целиком
+    fn trim(&self, inode: &mut RawInode, block: u32, depth: u32, first: u64, keep: u64) -> FsResult<bool> {
+        let p = self.pointers_per_block();
+        if first >= keep {
+            self.free_tree(inode, block, depth)?;
+            return Ok(true);
+        }
+        if first + p.pow(depth) <= keep {
+            return Ok(false);
+        }
+        let child_span = p.pow(depth - 1);
+        let mut data = self.read_block(block)?;
+        let mut changed = false;
+        for i in 0..p as usize {
+            let child = u32_at(&data, i * 4);
+            if child != 0 && self.trim(inode, child, depth - 1, first + i as u64 * child_span, keep)? {
+                put_u32(&mut data, i * 4, 0);
+                changed = true;
+            }
+        }
+        if changed {
+            self.write_block(block, &data)?;
+        }
+        Ok(false)
+    }
+
+    fn free_tree(&self, inode: &mut RawInode, block: u32, depth: u32) -> FsResult<()> {
+        if depth > 0 {
+            let data = self.read_block(block)?;
+            for i in 0..self.pointers_per_block() as usize {
+                let child = u32_at(&data, i * 4);
+                if child != 0 {
+                    self.free_tree(inode, child, depth - 1)?;
+                }
+            }
+        }
+        self.free_block(inode, block)
+    }
+
+    fn read_data(&self, inode: &RawInode, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let end = inode.size.min(offset + buf.len() as u64);
+        let bs = self.block_size as u64;
+        let mut pos = offset;
+        while pos < end {
+            let within = (pos % bs) as usize;
+            let n = ((bs - within as u64).min(end - pos)) as usize;
+            let dst = (pos - offset) as usize;
+            match self.map_block(inode, pos / bs)? {
+                0 => buf[dst..dst + n].iter_mut().for_each(|b| *b = 0),
+                block => buf[dst..dst + n].copy_from_slice(&self.read_block(block)?[within..within + n]),
+            }
+            pos += n as u64;
+        }
+        Ok((end - offset) as usize)
+    }
+
+    fn write_data(&self, ino: u32, inode: &mut RawInode, offset: u64, buf: &[u8]) -> FsResult<()> {
+        let bs = self.block_size as u64;
+        let end = offset + buf.len() as u64;
+        let max = if self.large_file { u64::MAX } else { i32::MAX as u64 };
+        if end > max {
+            return Err(FsError::NoSpace);
+        }
+        let goal = self.group_of_inode(ino);
+        let mut pos = offset;
+        while pos < end {
+            let within = (pos % bs) as usize;
+            let n = ((bs - within as u64).min(end - pos)) as usize;
+            let block = self.map_block_alloc(inode, pos / bs, goal)?;
+            let mut data = if n == self.block_size { vec![0u8; self.block_size] } else { self.read_block(block)? };
+            let src = (pos - offset) as usize;
+            data[within..within + n].copy_from_slice(&buf[src..src + n]);
+            self.write_block(block, &data)?;
+            pos += n as u64;
+            inode.size = inode.size.max(pos);
+        }
+        Ok(())
+    }
+
+    fn is_fast_symlink(&self, inode: &RawInode) -> bool {
+        let acl = if inode.file_acl != 0 { (self.block_size / 512) as u32 } else { 0 };
+        inode.mode & S_IFMT == S_IFLNK && inode.sectors == acl
+    }
+
+    fn dir_blocks(&self, dir: &RawInode) -> u64 {
+        dir.size.div_ceil(self.block_size as u64)
+    }
+
+    fn dir_entries(&self, dir: &RawInode) -> FsResult<Vec<RawDirEntry>> {
+        let mut entries = Vec::new();
+        for index in 0..self.dir_blocks(dir) {
+            let block = self.map_block(dir, index)?;
+            if block == 0 {
+                continue;
+            }
+            let data = self.read_block(block)?;
+            for slot in parse_block(&data)?.into_iter().filter(|s| s.ino != 0) {
+                let name = &data[slot.offset + DIR_ENTRY_HEADER..slot.offset + DIR_ENTRY_HEADER + slot.name_len];
+                entries.push(RawDirEntry {
+                    ino: slot.ino,
+                    file_type: if self.filetype { data[slot.offset + 7] } else { 0 },
+                    name: String::from_utf8_lossy(name).into_owned(),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn find_entry(&self, dir: &RawInode, name: &str) -> FsResult<RawDirEntry> {
+        self.dir_entries(dir)?.into_iter().find(|e| e.name == name).ok_or(FsError::NotFound)
+    }
+
+    fn dir_file_type(&self, file_type: FileType) -> u8 {
+        if !self.filetype {
+            return 0;
+        }
+        match file_type {
+            FileType::File => FT_REG_FILE,
+            FileType::Directory => FT_DIR,
+            FileType::Symlink => FT_SYMLINK,
+        }
+    }
+
+    /// Добавляет запись в каталог, при нехватке места — в новый блок.
+    fn add_entry(&self, dir_ino: u32, dir: &mut RawInode, name: &str, ino: u32, file_type: FileType) -> FsResult<()> {
+        if self.find_entry(dir, name).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+        dir.flags &= !INDEX_FL;
+        let needed = dir_entry_len(name.len());
+        let fill = |data: &mut [u8], off: usize, rec_len: usize| {
+            put_u32(data, off, ino);
+            put_u16(data, off + 4, rec_len as u16);
+            data[off + 6] = name.len() as u8;
+            data[off + 7] = self.dir_file_type(file_type);
+            data[off + DIR_ENTRY_HEADER..off + DIR_ENTRY_HEADER + name.len()].copy_from_slice(name.as_bytes());
+        };
+        for index in 0..self.dir_blocks(dir) {
+            let block = self.map_block(dir, index)?;
+            if block == 0 {
+                continue;
+            }
+            let mut data = self.read_block(block)?;
+            for slot in parse_block(&data)? {
+                if slot.ino == 0 && slot.rec_len >= needed {
+                    fill(&mut data, slot.offset, slot.rec_len);
+                    return self.write_block(block, &data);
+                }
+                // Хвост живой записи, не занятый её именем, можно отрезать
+                let used = dir_entry_len(slot.name_len);
+                if slot.ino != 0 && slot.rec_len >= used + needed {
+                    put_u16(&mut data, slot.offset + 4, used as u16);
+                    fill(&mut data, slot.offset + used, slot.rec_len - used);
+                    return self.write_block(block, &data);
+                }
+            }
+        }
+        let index = self.dir_blocks(dir);
+        let block = self.map_block_alloc(dir, index, self.group_of_inode(dir_ino))?;
+        let mut data = vec![0u8; self.block_size];
+        fill(&mut data, 0, self.block_size);
+        self.write_block(block, &data)?;
+        dir.size = (index + 1) * self.block_size as u64;
+        Ok(())
+    }
+
+    /// Убирает запись: её место достаётся предыдущей записи того же блока.
+    fn remove_entry(&self, dir: &mut RawInode, name: &str) -> FsResult<()> {
+        dir.flags &= !INDEX_FL;
+        for index in 0..self.dir_blocks(dir) {
+            let block = self.map_block(dir, index)?;
+            if block == 0 {
+                continue;
+            }
+            let mut data = self.read_block(block)?;
+            let slots = parse_block(&data)?;
+            for (i, slot) in slots.iter().enumerate() {
+                let entry_name = &data[slot.offset + DIR_ENTRY_HEADER..slot.offset + DIR_ENTRY_HEADER + slot.name_len];
+                if slot.ino == 0 || entry_name != name.as_bytes() {
+                    continue;
+                }
+                match i.checked_sub(1).map(|p| &slots[p]) {
+                    Some(prev) => put_u16(&mut data, prev.offset + 4, (prev.rec_len + slot.rec_len) as u16),
+                    None => put_u32(&mut data, slot.offset, 0),
+                }
+                return self.write_block(block, &data);
+            }
+        }
+        Err(FsError::NotFound)
+    }
+
+    // ".." — вторая запись первого блока каталога
+    fn set_parent(&self, dir: &RawInode, parent: u32) -> FsResult<()> {
+        let block = self.map_block(dir, 0)?;
+        let mut data = self.read_block(block)?;
+        let slots = parse_block(&data)?;
+        let dotdot = slots.get(1).ok_or(FsError::Io)?;
+        put_u32(&mut data, dotdot.offset, parent);
+        self.write_block(block, &data)
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        if self.read_only { Err(FsError::ReadOnly) } else { Ok(()) }
+    }
+}
+
+// Произвольный байтовый диапазон через сектора устройства (для суперблока)
+fn read_bytes(device: &dyn BlockDevice, offset: u64, len: usize) -> FsResult<Vec<u8>> {
+    let sector = device.block_size() as u64;
+    let first = offset / sector;
+    let count = (offset % sector + len as u64).div_ceil(sector);
+    let mut data = vec![0u8; (count * sector) as usize];
+    device.read_blocks(first, &mut data)?;
+    let start = (offset % sector) as usize;
+    Ok(data[start..start + len].to_vec())
+}
+
+fn write_bytes(device: &dyn BlockDevice, offset: u64, bytes: &[u8]) -> FsResult<()> {
+    let sector = device.block_size() as u64;
+    let first = offset / sector;
+    let count = (offset % sector + bytes.len() as u64).div_ceil(sector);
+    let mut data = vec![0u8; (count * sector) as usize];
+    device.read_blocks(first, &mut data)?;
+    let start = (offset % sector) as usize;
+    data[start..start + bytes.len()].copy_from_slice(bytes);
+    Ok(device.write_blocks(first, &data)?)
+}
+
+fn validate_name(name: &str) -> FsResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME || name.contains('/') {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+/// Inode ext2. Состояние не кэшируется: каждая операция читает inode с диска, а
+/// поколение отличает удалённый файл от нового, получившего тот же номер.
+pub struct Ext2Node {
+    fs: Arc<Ext2>,
+    ino: u32,
+    generation: u32,
+}
+
+impl Ext2Node {
+    fn open(fs: &Arc<Ext2>, ino: u32) -> FsResult<Ext2Node> {
+        let inode = fs.read_inode(ino)?;
+        if inode.links == 0 || inode.dtime != 0 {
+            return Err(FsError::Io);
+        }
+        Ok(Ext2Node { fs: fs.clone(), ino, generation: inode.generation })
+    }
+
+    fn inode(&self) -> FsResult<RawInode> {
+        let inode = self.fs.read_inode(self.ino)?;
+        if inode.links == 0 || inode.generation != self.generation {
+            return Err(FsError::NotFound);
+        }
+        Ok(inode)
+    }
+
+    fn dir_inode(&self) -> FsResult<RawInode> {
+        let inode = self.inode()?;
+        if inode.file_type() != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(inode)
+    }
+
+    fn file_inode(&self) -> FsResult<RawInode> {
+        let inode = self.inode()?;
+        match inode.file_type() {
+            FileType::File => Ok(inode),
+            FileType::Directory => Err(FsError::IsADirectory),
+            FileType::Symlink => Err(FsError::InvalidArgument),
+        }
+    }
+
+    // Уменьшает число ссылок; на нуле освобождает блоки и сам inode
+    fn drop_link(&self, ino: u32, inode: &mut RawInode, count: u16) -> FsResult<()> {
+        let fs = &*self.fs;
+        inode.links = inode.links.saturating_sub(count);
+        if inode.links == 0 {
+            if !fs.is_fast_symlink(inode) {
+                fs.truncate_blocks(inode, 0)?;
+            }
+            // dtime меньше числа inode e2fsck примет за ссылку в списке сирот
+            inode.dtime = fs.write_time.max(fs.inodes_count);
+            fs.free_inode(ino, inode.file_type() == FileType::Directory)?;
+        }
+        fs.write_inode(ino, inode)
+    }
+}
+
+impl Inode for Ext2Node {
+    fn metadata(&self) -> Metadata {
+        let _guard = self.fs.lock.lock();
+        match self.inode() {
+            Ok(inode) => Metadata {
+                file_type: inode.file_type(),
+                size: inode.size,
+                mode: inode.mode & 0o7777,
+                created: 0,
+                modified: 0,
+            },
+            Err(_) => Metadata { file_type: FileType::File, size: 0, mode: 0, created: 0, modified: 0 },
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let _guard = self.fs.lock.lock();
+        let inode = self.file_inode()?;
+        self.fs.read_data(&inode, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        self.fs.check_writable()?;
+        let _guard = self.fs.lock.lock();
+        let mut inode = self.file_inode()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Дыры не выделяются: при чтении они дают нули
+        let result = self.fs.write_data(self.ino, &mut inode, offset, buf);
+        self.fs.write_inode(self.ino, &inode)?;
+        result.map(|_| buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        self.fs.check_writable()?;
+        let fs = &*self.fs;
+        let _guard = fs.lock.lock();
+        let mut inode = self.file_inode()?;
+        if size < inode.size {
+            let bs = fs.block_size as u64;
+            fs.truncate_blocks(&mut inode, size.div_ceil(bs))?;
+            // Хвост последнего блока обнуляем, чтобы при росте файла там были нули
+            let within = (size % bs) as usize;
+            if within != 0 {
+                let block = fs.map_block(&inode, size / bs)?;
+                if block != 0 {
+                    let mut data = fs.read_block(block)?;
+                    data[within..].iter_mut().for_each(|b| *b = 0);
+                    fs.write_block(block, &data)?;
+                }
+            }
+        }
+        inode.size = size;
+        fs.write_inode(self.ino, &inode)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let _guard = self.fs.lock.lock();
+        let dir = self.dir_inode()?;
+        let entry = self.fs.find_entry(&dir, name)?;
+        Ok(Arc::new(Ext2Node::open(&self.fs, entry.ino)?))
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        let fs = &*self.fs;
+        let _guard = fs.lock.lock();
+        let dir = self.dir_inode()?;
+        let mut entries = Vec::new();
+        for entry in fs.dir_entries(&dir)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let file_type = match entry.file_type {
+                FT_REG_FILE => FileType::File,
+                FT_DIR => FileType::Directory,
+                FT_SYMLINK => FileType::Symlink,
+                // Тип в записи не хранится (или это устройство, сокет и т.п.) — смотрим inode
+                _ => fs.read_inode(entry.ino)?.file_type(),
+            };
+            entries.push(DirEntry { name: entry.name, file_type });
+        }
+        Ok(entries)
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        let fs = &*self.fs;
+        let _guard = fs.lock.lock();
+        let inode = self.inode()?;
+        if inode.file_type() != FileType::Symlink {
+            return Err(FsError::InvalidArgument);
+        }
+        // Длиннее блока цель ссылки не бывает; иначе размер в inode испорчен
+        if inode.size > fs.block_size as u64 {
+            return Err(FsError::Io);
+        }
+        let mut target = vec![0u8; inode.size as usize];
+        if fs.is_fast_symlink(&inode) {
+            // Короткая цель хранится прямо в массиве указателей на блоки
+            let raw: Vec<u8> = inode.block.iter().flat_map(|p| p.to_le_bytes()).collect();
+            let len = target.len().min(raw.len());
+            target[..len].copy_from_slice(&raw[..len]);
+        } else {
+            fs.read_data(&inode, 0, &mut target)?;
+        }
+        String::from_utf8(target).map_err(|_| FsError::Io)
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> FsResult<Arc<dyn Inode>> {
+        let fs = &*self.fs;
+        fs.check_writable()?;
+        validate_name(name)?;
+        let _guard = fs.lock.lock();
+        let mut dir = self.dir_inode()?;
+        if fs.find_entry(&dir, name).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+        let is_dir = match file_type {
+            FileType::File => false,
+            FileType::Directory => true,
+            // Цель ссылки через `create` не передать
+            FileType::Symlink => return Err(FsError::Unsupported),
+        };
+        let ino = fs.allocate_inode(fs.group_of_inode(self.ino), is_dir)?;
+        let (mode, links) = if is_dir { (S_IFDIR | 0o755, 2) } else { (S_IFREG | 0o644, 1) };
+        let mut inode = fs.init_inode(ino, mode, links)?;
+        let result = (|| {
+            if is_dir {
+                let block = fs.map_block_alloc(&mut inode, 0, fs.group_of_inode(ino))?;
+                let mut data = vec![0u8; fs.block_size];
+                for (off, entry_ino, name, rec_len) in [(0, ino, ".", 12), (12, self.ino, "..", fs.block_size - 12)] {
+                    put_u32(&mut data, off, entry_ino);
+                    put_u16(&mut data, off + 4, rec_len as u16);
+                    data[off + 6] = name.len() as u8;
+                    data[off + 7] = fs.dir_file_type(FileType::Directory);
+                    data[off + DIR_ENTRY_HEADER..off + DIR_ENTRY_HEADER + name.len()].copy_from_slice(name.as_bytes());
+                }
+                fs.write_block(block, &data)?;
+                inode.size = fs.block_size as u64;
+            }
+            fs.write_inode(ino, &inode)?;
+            fs.add_entry(self.ino, &mut dir, name, ino, file_type)
+        })();
+        if let Err(e) = result {
+            // Откатываем выделенный inode, чтобы он не потерялся
+            fs.write_inode(self.ino, &dir)?;
+            inode.links = 1;
+            self.drop_link(ino, &mut inode, 1)?;
+            return Err(e);
+        }
+        if is_dir {
+            dir.links += 1;
+        }
+        fs.write_inode(self.ino, &dir)?;
+        Ok(Arc::new(Ext2Node { fs: self.fs.clone(), ino, generation: inode.generation }))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        let fs = &*self.fs;
+        fs.check_writable()?;
+        validate_name(name)?;
+        let _guard = fs.lock.lock();
+        let mut dir = self.dir_inode()?;
+        let entry = fs.find_entry(&dir, name)?;
+        let mut inode = fs.read_inode(entry.ino)?;
+        let is_dir = inode.file_type() == FileType::Directory;
+        if is_dir && fs.dir_entries(&inode)?.iter().any(|e| e.name != "." && e.name != "..") {
+            return Err(FsError::NotEmpty);
+        }
+        fs.remove_entry(&mut dir, name)?;
+        // Каталог теряет и запись в родителе, и собственную "."; родитель — ссылку ".."
+        if is_dir {
+            dir.links = dir.links.saturating_sub(1);
+        }
+        fs.write_inode(self.ino, &dir)?;
+        self.drop_link(entry.ino, &mut inode, if is_dir { 2 } else { 1 })
+    }
+
+    fn rename(&self, name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> FsResult<()> {
+        let target = match (**new_dir).as_any().downcast_ref::<Ext2Node>() {
+            Some(target) if Arc::ptr_eq(&target.fs, &self.fs) => target,
+            _ => return Err(FsError::Unsupported),
+        };
+        let fs = &*self.fs;
+        fs.check_writable()?;
+        validate_name(name)?;
+        validate_name(new_name)?;
+        let _guard = fs.lock.lock();
+        let mut from = self.dir_inode()?;
+        let entry = fs.find_entry(&from, name)?;
+        let moved = fs.read_inode(entry.ino)?;
+        let file_type = moved.file_type();
+        if target.ino == self.ino {
+            fs.add_entry(self.ino, &mut from, new_name, entry.ino, file_type)?;
+            fs.remove_entry(&mut from, name)?;
+            return fs.write_inode(self.ino, &from);
+        }
+        let mut to = target.dir_inode()?;
+        fs.add_entry(target.ino, &mut to, new_name, entry.ino, file_type)?;
+        fs.remove_entry(&mut from, name)?;
+        if file_type == FileType::Directory {
+            fs.set_parent(&moved, target.ino)?;
+            from.links = from.links.saturating_sub(1);
+            to.links += 1;
+        }
+        fs.write_inode(self.ino, &from)?;
+        fs.write_inode(target.ino, &to)
+    }
+}
+
+pub struct Ext2Fs {
+    root: Arc<Ext2Node>,
+    read_only: bool,
+}
+
+impl Ext2Fs {
+    pub fn new(device: Arc<dyn BlockDevice>) -> FsResult<Ext2Fs> {
+        let fs = Arc::new(Ext2::parse(device)?);
+        let root = Arc::new(Ext2Node::open(&fs, ROOT_INO)?);
+        Ok(Ext2Fs { read_only: fs.read_only, root })
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        if self.read_only { "ext2 (ro)" } else { "ext2" }
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
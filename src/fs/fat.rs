@@ -0,0 +1,989 @@
+//! FAT12/16/32 на блочном устройстве: длинные имена VFAT, чтение и запись файлов,
+//! создание, удаление и перенос записей каталогов.
+
+use alloc::{collections::BTreeMap, string::String, sync::{Arc, Weak}, vec, vec::Vec};
+use core::convert::TryInto;
+use spin::Mutex;
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+use crate::block::BlockDevice;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+const ENTRY_SIZE: usize = 32;
+const ENTRY_FREE: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+// Байт 0 короткого имени, равный 0xE5, хранится как 0x05
+const ENTRY_KANJI_E5: u8 = 0x05;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+const MAX_NAME: usize = 255;
+
+// Флаги Windows NT в байте 12: имя или расширение целиком в нижнем регистре
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+// Времени реального нет: все записи датируются 1980-01-01
+const DOS_EPOCH_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    fn end_of_chain(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    // Всё, начиная с "плохого" кластера, цепочку продолжать не может
+    fn is_end(self, value: u32) -> bool {
+        value < 2 || value >= self.end_of_chain() - 8
+    }
+}
+
+/// Место короткой записи: кластер каталога (0 — фиксированный корень FAT12/16) и номер слота.
+type Location = (u32, usize);
+
+struct State {
+    next_free: u32,
+    // Открытые inode по месту их записи: два `lookup` одного файла дают один объект
+    nodes: BTreeMap<Location, Weak<FatNode>>,
+}
+
+/// Последний прочитанный сектор таблицы: соседние записи цепочки обычно лежат в нём же.
+/// Живёт только на время одного обхода, пока таблицу никто не пишет.
+#[derive(Default)]
+struct FatSector {
+    index: Option<u64>,
+    data: Vec<u8>,
+}
+
+struct Fat {
+    device: Arc<dyn BlockDevice>,
+    kind: FatType,
+    sector_size: usize,
+    cluster_sectors: u64,
+    fat_start: u64,
+    fat_sectors: u64,
+    fat_count: u64,
+    root_start: u64,
+    root_sectors: u64,
+    root_cluster: u32,
+    data_start: u64,
+    cluster_count: u32,
+    // Все изменения метаданных и данных идут под одним замком
+    state: Mutex<State>,
+}
+
+fn u16_at(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off..off + 2].try_into().unwrap())
+}
+
+fn u32_at(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+impl Fat {
+    fn parse(device: Arc<dyn BlockDevice>) -> FsResult<Fat> {
+        let mut boot = vec![0u8; device.block_size()];
+        device.read_blocks(0, &mut boot)?;
+        if boot.len() < 512 || boot[510] != 0x55 || boot[511] != 0xAA {
+            return Err(FsError::Unsupported);
+        }
+        let sector_size = u16_at(&boot, 11) as usize;
+        let cluster_sectors = boot[13] as u64;
+        let reserved = u16_at(&boot, 14) as u64;
+        let fat_count = boot[16] as u64;
+        let root_entries = u16_at(&boot, 17) as u64;
+        let total = match u16_at(&boot, 19) {
+            0 => u32_at(&boot, 32) as u64,
+            n => n as u64,
+        };
+        let fat_sectors = match u16_at(&boot, 22) {
+            0 => u32_at(&boot, 36) as u64,
+            n => n as u64,
+        };
+        // Сектор ФС должен совпадать с блоком устройства
+        if sector_size != device.block_size() || !cluster_sectors.is_power_of_two()
+            || fat_count == 0 || fat_sectors == 0 || reserved == 0 {
+            return Err(FsError::Unsupported);
+        }
+        let root_sectors = (root_entries * ENTRY_SIZE as u64).div_ceil(sector_size as u64);
+        let fat_start = reserved;
+        let root_start = fat_start + fat_count * fat_sectors;
+        let data_start = root_start + root_sectors;
+        if total <= data_start || total > device.block_count() {
+            return Err(FsError::Unsupported);
+        }
+        let cluster_count = ((total - data_start) / cluster_sectors) as u32;
+        // Тип FAT определяется только числом кластеров
+        let kind = match cluster_count {
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        let root_cluster = if kind == FatType::Fat32 { u32_at(&boot, 44) } else { 0 };
+        Ok(Fat {
+            device,
+            kind,
+            sector_size,
+            cluster_sectors,
+            fat_start,
+            fat_sectors,
+            fat_count,
+            root_start,
+            root_sectors,
+            root_cluster,
+            data_start,
+            cluster_count,
+            state: Mutex::new(State { next_free: 2, nodes: BTreeMap::new() }),
+        })
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sector_size * self.cluster_sectors as usize
+    }
+
+    fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> FsResult<()> {
+        Ok(self.device.read_blocks(sector, buf)?)
+    }
+
+    fn write_sectors(&self, sector: u64, buf: &[u8]) -> FsResult<()> {
+        Ok(self.device.write_blocks(sector, buf)?)
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.cluster_sectors
+    }
+
+    fn read_cluster(&self, cluster: u32, buf: &mut [u8]) -> FsResult<()> {
+        self.read_sectors(self.cluster_sector(cluster), buf)
+    }
+
+    fn write_cluster(&self, cluster: u32, buf: &[u8]) -> FsResult<()> {
+        self.write_sectors(self.cluster_sector(cluster), buf)
+    }
+
+    fn fat_offset(&self, cluster: u32) -> u64 {
+        match self.kind {
+            FatType::Fat12 => cluster as u64 + cluster as u64 / 2,
+            FatType::Fat16 => cluster as u64 * 2,
+            FatType::Fat32 => cluster as u64 * 4,
+        }
+    }
+
+    // Байты таблицы в копии 0; запись FAT12 может пересекать границу сектора
+    fn fat_read_bytes(&self, offset: u64, out: &mut [u8], sector: &mut FatSector) -> FsResult<()> {
+        for (i, byte) in out.iter_mut().enumerate() {
+            let index = (offset + i as u64) / self.sector_size as u64;
+            if sector.index != Some(index) {
+                sector.index = None;
+                sector.data.resize(self.sector_size, 0);
+                self.read_sectors(self.fat_start + index, &mut sector.data)?;
+                sector.index = Some(index);
+            }
+            *byte = sector.data[((offset + i as u64) % self.sector_size as u64) as usize];
+        }
+        Ok(())
+    }
+
+    fn fat_write_bytes(&self, offset: u64, bytes: &[u8]) -> FsResult<()> {
+        let mut sector = vec![0u8; self.sector_size];
+        let mut i = 0;
+        while i < bytes.len() {
+            let index = (offset + i as u64) / self.sector_size as u64;
+            self.read_sectors(self.fat_start + index, &mut sector)?;
+            while i < bytes.len() && (offset + i as u64) / self.sector_size as u64 == index {
+                sector[((offset + i as u64) % self.sector_size as u64) as usize] = bytes[i];
+                i += 1;
+            }
+            for copy in 0..self.fat_count {
+                self.write_sectors(self.fat_start + copy * self.fat_sectors + index, &sector)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_entry(&self, cluster: u32, sector: &mut FatSector) -> FsResult<u32> {
+        let offset = self.fat_offset(cluster);
+        let mut raw = [0u8; 4];
+        Ok(match self.kind {
+            FatType::Fat12 => {
+                self.fat_read_bytes(offset, &mut raw[..2], sector)?;
+                let value = u16::from_le_bytes([raw[0], raw[1]]) as u32;
+                if cluster & 1 == 1 { value >> 4 } else { value & 0xFFF }
+            }
+            FatType::Fat16 => {
+                self.fat_read_bytes(offset, &mut raw[..2], sector)?;
+                u16::from_le_bytes([raw[0], raw[1]]) as u32
+            }
+            FatType::Fat32 => {
+                self.fat_read_bytes(offset, &mut raw, sector)?;
+                u32::from_le_bytes(raw) & 0x0FFF_FFFF
+            }
+        })
+    }
+
+    fn set_entry(&self, cluster: u32, value: u32) -> FsResult<()> {
+        let offset = self.fat_offset(cluster);
+        let mut raw = [0u8; 4];
+        match self.kind {
+            FatType::Fat12 => {
+                self.fat_read_bytes(offset, &mut raw[..2], &mut FatSector::default())?;
+                let old = u16::from_le_bytes([raw[0], raw[1]]);
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | ((value as u16) << 4)
+                } else {
+                    (old & 0xF000) | (value as u16 & 0x0FFF)
+                };
+                self.fat_write_bytes(offset, &new.to_le_bytes())
+            }
+            FatType::Fat16 => self.fat_write_bytes(offset, &(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                // Старшие 4 бита зарезервированы и сохраняются
+                self.fat_read_bytes(offset, &mut raw, &mut FatSector::default())?;
+                let old = u32::from_le_bytes(raw);
+                self.fat_write_bytes(offset, &((old & 0xF000_0000) | (value & 0x0FFF_FFFF)).to_le_bytes())
+            }
+        }
+    }
+
+    /// Кластеры цепочки по порядку.
+    fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut sector = FatSector::default();
+        let mut cluster = first;
+        while !self.kind.is_end(cluster) {
+            if clusters.len() > self.cluster_count as usize {
+                return Err(FsError::Io);
+            }
+            clusters.push(cluster);
+            cluster = self.get_entry(cluster, &mut sector)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Берёт свободный кластер, заполняет нулями и пристёгивает после `prev`.
+    fn allocate(&self, state: &mut State, prev: Option<u32>) -> FsResult<u32> {
+        let last = self.cluster_count + 1;
+        let start = state.next_free.max(2).min(last);
+        let mut found = None;
+        let mut sector = FatSector::default();
+        for cluster in (start..=last).chain(2..start) {
+            if self.get_entry(cluster, &mut sector)? == 0 {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(FsError::NoSpace)?;
+        self.set_entry(cluster, self.kind.end_of_chain())?;
+        self.write_cluster(cluster, &vec![0u8; self.cluster_size()])?;
+        if let Some(prev) = prev {
+            self.set_entry(prev, cluster)?;
+        }
+        state.next_free = cluster + 1;
+        Ok(cluster)
+    }
+
+    fn free_chain(&self, first: u32) -> FsResult<()> {
+        for cluster in self.chain(first)? {
+            self.set_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Сектора каталога по порядку: фиксированная область корня или цепочка кластеров.
+    fn dir_sectors(&self, dir: u32) -> FsResult<Vec<u64>> {
+        if dir == 0 {
+            return Ok((self.root_start..self.root_start + self.root_sectors).collect());
+        }
+        Ok(self.chain(dir)?.into_iter()
+            .flat_map(|c| {
+                let first = self.cluster_sector(c);
+                first..first + self.cluster_sectors
+            })
+            .collect())
+    }
+
+    fn read_dir_data(&self, dir: u32) -> FsResult<(Vec<u64>, Vec<u8>)> {
+        let sectors = self.dir_sectors(dir)?;
+        let mut data = vec![0u8; sectors.len() * self.sector_size];
+        for (sector, chunk) in sectors.iter().zip(data.chunks_mut(self.sector_size)) {
+            self.read_sectors(*sector, chunk)?;
+        }
+        Ok((sectors, data))
+    }
+
+    fn write_slot(&self, sectors: &[u64], index: usize, entry: &[u8]) -> FsResult<()> {
+        let per_sector = self.sector_size / ENTRY_SIZE;
+        let sector = *sectors.get(index / per_sector).ok_or(FsError::Io)?;
+        let mut buf = vec![0u8; self.sector_size];
+        self.read_sectors(sector, &mut buf)?;
+        let off = (index % per_sector) * ENTRY_SIZE;
+        buf[off..off + ENTRY_SIZE].copy_from_slice(entry);
+        self.write_sectors(sector, &buf)
+    }
+
+    fn read_slot(&self, location: Location) -> FsResult<[u8; ENTRY_SIZE]> {
+        let sectors = self.dir_sectors(location.0)?;
+        let per_sector = self.sector_size / ENTRY_SIZE;
+        let sector = *sectors.get(location.1 / per_sector).ok_or(FsError::Io)?;
+        let mut buf = vec![0u8; self.sector_size];
+        self.read_sectors(sector, &mut buf)?;
+        let off = (location.1 % per_sector) * ENTRY_SIZE;
+        Ok(buf[off..off + ENTRY_SIZE].try_into().unwrap())
+    }
+
+    /// Записывает в короткую запись первый кластер и размер.
+    fn update_slot(&self, location: Location, first: u32, size: u32) -> FsResult<()> {
+        let mut entry = self.read_slot(location)?;
+        set_first_cluster(&mut entry, first);
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        self.write_slot(&self.dir_sectors(location.0)?, location.1, &entry)
+    }
+
+    /// Добавляет запись (с длинным именем, если нужно); возвращает слот короткой записи.
+    fn add_entry(&self, state: &mut State, dir: u32, name: &str, template: &[u8; ENTRY_SIZE]) -> FsResult<usize> {
+        validate_name(name)?;
+        let (_, data) = self.read_dir_data(dir)?;
+        let existing = parse_dir(&data);
+        if existing.iter().any(|e| e.matches(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+        let (short, nt_flags, long) = short_name(name, &existing);
+        let lfn: Vec<u16> = if long { name.encode_utf16().collect() } else { Vec::new() };
+        let lfn_slots = lfn.len().div_ceil(LFN_CHARS);
+        let needed = lfn_slots + 1;
+
+        let (sectors, start) = loop {
+            let (sectors, data) = self.read_dir_data(dir)?;
+            if let Some(start) = find_free_run(&data, needed) {
+                break (sectors, start);
+            }
+            // Корень FAT12/16 фиксированного размера не растёт
+            if dir == 0 {
+                return Err(FsError::NoSpace);
+            }
+            let last = *self.chain(dir)?.last().ok_or(FsError::Io)?;
+            self.allocate(state, Some(last))?;
+        };
+
+        let checksum = short_checksum(&short);
+        for k in 0..lfn_slots {
+            // Части длинного имени лежат в обратном порядке, последняя — первой
+            let part = lfn_slots - k;
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[0] = part as u8 | if k == 0 { LFN_LAST } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            let chars = (0..LFN_CHARS).map(|i| {
+                let pos = (part - 1) * LFN_CHARS + i;
+                match pos.cmp(&lfn.len()) {
+                    core::cmp::Ordering::Less => lfn[pos],
+                    core::cmp::Ordering::Equal => 0x0000,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                }
+            });
+            for (c, off) in chars.zip(LFN_OFFSETS.iter()) {
+                entry[*off..*off + 2].copy_from_slice(&c.to_le_bytes());
+            }
+            self.write_slot(&sectors, start + k, &entry)?;
+        }
+        let mut entry = *template;
+        entry[..11].copy_from_slice(&short);
+        entry[12] = nt_flags;
+        let index = start + lfn_slots;
+        self.write_slot(&sectors, index, &entry)?;
+        Ok(index)
+    }
+
+    fn remove_entry(&self, dir: u32, entry: &RawEntry) -> FsResult<()> {
+        let (sectors, data) = self.read_dir_data(dir)?;
+        for index in entry.first_slot..=entry.slot {
+            let mut slot: [u8; ENTRY_SIZE] = data[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE].try_into().unwrap();
+            slot[0] = ENTRY_DELETED;
+            self.write_slot(&sectors, index, &slot)?;
+        }
+        Ok(())
+    }
+
+    fn find(&self, dir: u32, name: &str) -> FsResult<RawEntry> {
+        let (_, data) = self.read_dir_data(dir)?;
+        parse_dir(&data).into_iter().find(|e| e.matches(name)).ok_or(FsError::NotFound)
+    }
+}
+
+// Смещения 13 символов UCS-2 в записи длинного имени
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+fn first_cluster(entry: &[u8]) -> u32 {
+    (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32
+}
+
+fn set_first_cluster(entry: &mut [u8], cluster: u32) {
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+fn short_checksum(short: &[u8]) -> u8 {
+    short[..11].iter().fold(0u8, |sum, &b| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b))
+}
+
+/// Короткая запись каталога вместе с предшествующими записями длинного имени.
+struct RawEntry {
+    slot: usize,
+    first_slot: usize,
+    name: String,
+    short_name: String,
+    data: [u8; ENTRY_SIZE],
+}
+
+impl RawEntry {
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.short_name.eq_ignore_ascii_case(name)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.data[11] & ATTR_DIRECTORY != 0
+    }
+}
+
+fn display_short_name(entry: &[u8]) -> String {
+    let mut base: Vec<u8> = entry[..8].to_vec();
+    if base[0] == ENTRY_KANJI_E5 {
+        base[0] = ENTRY_DELETED;
+    }
+    let lower = |bytes: &[u8], flag: u8| -> String {
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end();
+        if entry[12] & flag != 0 { text.to_ascii_lowercase() } else { String::from(text) }
+    };
+    let base = lower(&base, NT_LOWER_BASE);
+    let ext = lower(&entry[8..11], NT_LOWER_EXT);
+    if ext.is_empty() { base } else { alloc::format!("{}.{}", base, ext) }
+}
+
+/// Живые записи каталога без `.`, `..` и метки тома.
+fn parse_dir(data: &[u8]) -> Vec<RawEntry> {
+    let mut entries = Vec::new();
+    let mut lfn: Vec<u16> = Vec::new();
+    let mut lfn_start = None;
+    let mut lfn_checksum = 0;
+    let mut lfn_expected = 0u8;
+    for (slot, entry) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        match entry[0] {
+            ENTRY_FREE => break,
+            ENTRY_DELETED => {
+                lfn_start = None;
+                continue;
+            }
+            _ => {}
+        }
+        if entry[11] & 0x3F == ATTR_LONG_NAME {
+            let seq = entry[0] & 0x1F;
+            if entry[0] & LFN_LAST != 0 {
+                lfn = vec![0xFFFF; seq as usize * LFN_CHARS];
+                lfn_start = Some(slot);
+                lfn_checksum = entry[13];
+            } else if lfn_start.is_none() || seq != lfn_expected || entry[13] != lfn_checksum {
+                lfn_start = None;
+                continue;
+            }
+            if seq == 0 {
+                lfn_start = None;
+                continue;
+            }
+            let base = (seq as usize - 1) * LFN_CHARS;
+            for (i, off) in LFN_OFFSETS.iter().enumerate() {
+                lfn[base + i] = u16_at(entry, *off);
+            }
+            lfn_expected = seq - 1;
+            continue;
+        }
+        let long_start = lfn_start.take();
+        if entry[11] & ATTR_VOLUME_ID != 0 || entry[0] == b'.' {
+            continue;
+        }
+        let short_name = display_short_name(entry);
+        let long = long_start
+            .filter(|_| lfn_expected == 0 && short_checksum(entry) == lfn_checksum)
+            .map(|start| {
+                let end = lfn.iter().position(|&c| c == 0 || c == 0xFFFF).unwrap_or(lfn.len());
+                (start, core::char::decode_utf16(lfn[..end].iter().copied())
+                    .map(|c| c.unwrap_or('?'))
+                    .collect::<String>())
+            });
+        let (first_slot, name) = match long {
+            Some((start, name)) => (start, name),
+            None => (slot, short_name.clone()),
+        };
+        entries.push(RawEntry { slot, first_slot, name, short_name, data: entry.try_into().unwrap() });
+    }
+    entries
+}
+
+fn find_free_run(data: &[u8], needed: usize) -> Option<usize> {
+    let mut run = 0;
+    for (slot, entry) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        if entry[0] == ENTRY_FREE || entry[0] == ENTRY_DELETED {
+            run += 1;
+            if run == needed {
+                return Some(slot + 1 - needed);
+            }
+        } else {
+            run = 0;
+        }
+    }
+    None
+}
+
+fn validate_name(name: &str) -> FsResult<()> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name == "." || name == ".." || name.encode_utf16().count() > MAX_NAME
+        || name.chars().any(invalid) {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+fn short_char(c: char) -> Option<u8> {
+    match c {
+        'A'..='Z' | '0'..='9' => Some(c as u8),
+        'a'..='z' => Some(c.to_ascii_uppercase() as u8),
+        '!' | '#' | '$' | '%' | '&' | '\'' | '(' | ')' | '-' | '@' | '^' | '_' | '`' | '{' | '}' | '~' => Some(c as u8),
+        _ => None,
+    }
+}
+
+// Регистр части имени для флагов NT: Some(true) — вся в нижнем, Some(false) — в верхнем
+fn uniform_case(part: &str) -> Option<bool> {
+    let has_lower = part.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = part.chars().any(|c| c.is_ascii_uppercase());
+    match (has_lower, has_upper) {
+        (true, true) => None,
+        (lower, _) => Some(lower),
+    }
+}
+
+/// Короткое имя 8.3, флаги регистра NT и нужна ли запись длинного имени.
+fn short_name(name: &str, existing: &[RawEntry]) -> ([u8; 11], u8, bool) {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+    let mut short = [b' '; 11];
+    let fits = (1..=8).contains(&base.len()) && ext.len() <= 3
+        && base.chars().chain(ext.chars()).all(|c| short_char(c).is_some());
+    if fits {
+        if let (Some(base_lower), Some(ext_lower)) = (uniform_case(base), uniform_case(ext)) {
+            for (dst, c) in short.iter_mut().zip(base.chars()) {
+                *dst = short_char(c).unwrap();
+            }
+            for (dst, c) in short[8..].iter_mut().zip(ext.chars()) {
+                *dst = short_char(c).unwrap();
+            }
+            let flags = if base_lower { NT_LOWER_BASE } else { 0 } | if ext_lower { NT_LOWER_EXT } else { 0 };
+            return (short, flags, false);
+        }
+    }
+    // Основа вида NAME~N: до 6 допустимых символов и наименьший свободный номер
+    let basis: Vec<u8> = base.chars().filter_map(short_char).take(6).collect();
+    let basis = if basis.is_empty() { vec![b'_'] } else { basis };
+    for (dst, c) in short[8..].iter_mut().zip(ext.chars().filter_map(short_char)) {
+        *dst = c;
+    }
+    for n in 1u32.. {
+        let tail = alloc::format!("~{}", n);
+        let keep = basis.len().min(8 - tail.len());
+        let mut candidate = short;
+        candidate[..8].copy_from_slice(b"        ");
+        candidate[..keep].copy_from_slice(&basis[..keep]);
+        candidate[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        if !existing.iter().any(|e| e.data[..11] == candidate) {
+            return (candidate, 0, true);
+        }
+    }
+    unreachable!()
+}
+
+fn entry_template(attr: u8, first: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[11] = attr;
+    for off in [16, 18, 24] {
+        entry[off..off + 2].copy_from_slice(&DOS_EPOCH_DATE.to_le_bytes());
+    }
+    set_first_cluster(&mut entry, first);
+    entry
+}
+
+struct NodeState {
+    first: u32,
+    size: u32,
+    // None у корня; у удалённого файла тоже None — писать в него больше нельзя
+    location: Option<Location>,
+    removed: bool,
+}
+
+pub struct FatNode {
+    fs: Arc<Fat>,
+    is_dir: bool,
+    read_only: bool,
+    state: Mutex<NodeState>,
+}
+
+impl FatNode {
+    /// Кластер каталога в терминах `Location` (0 — фиксированный корень).
+    fn dir_cluster(&self) -> u32 {
+        self.state.lock().first
+    }
+
+    fn node_for(&self, fs_state: &mut State, dir: u32, entry: &RawEntry) -> Arc<FatNode> {
+        let location = (dir, entry.slot);
+        if let Some(node) = fs_state.nodes.get(&location).and_then(Weak::upgrade) {
+            return node;
+        }
+        let node = Arc::new(FatNode {
+            fs: self.fs.clone(),
+            is_dir: entry.is_dir(),
+            read_only: entry.data[11] & ATTR_READ_ONLY != 0,
+            state: Mutex::new(NodeState {
+                first: first_cluster(&entry.data),
+                size: u32_at(&entry.data, 28),
+                location: Some(location),
+                removed: false,
+            }),
+        });
+        fs_state.nodes.retain(|_, weak| weak.strong_count() > 0);
+        fs_state.nodes.insert(location, Arc::downgrade(&node));
+        node
+    }
+
+    fn write_locked(&self, fs_state: &mut State, offset: u64, buf: &[u8]) -> FsResult<()> {
+        let fs = &*self.fs;
+        let cluster_size = fs.cluster_size() as u64;
+        let mut node = self.state.lock();
+        if node.removed {
+            return Err(FsError::NotFound);
+        }
+        let end = offset + buf.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+        let mut chain = fs.chain(node.first)?;
+        let needed = end.div_ceil(cluster_size) as usize;
+        while chain.len() < needed {
+            let cluster = fs.allocate(fs_state, chain.last().copied())?;
+            if chain.is_empty() {
+                node.first = cluster;
+            }
+            chain.push(cluster);
+        }
+        let mut data = vec![0u8; cluster_size as usize];
+        let mut pos = offset;
+        while pos < end {
+            let cluster = chain[(pos / cluster_size) as usize];
+            let within = (pos % cluster_size) as usize;
+            let n = ((cluster_size as usize - within) as u64).min(end - pos) as usize;
+            // Неполный кластер дописываем поверх прочитанного
+            if n != cluster_size as usize {
+                fs.read_cluster(cluster, &mut data)?;
+            }
+            let src = (pos - offset) as usize;
+            data[within..within + n].copy_from_slice(&buf[src..src + n]);
+            fs.write_cluster(cluster, &data)?;
+            pos += n as u64;
+        }
+        node.size = node.size.max(end as u32);
+        if let Some(location) = node.location {
+            fs.update_slot(location, node.first, node.size)?;
+        }
+        Ok(())
+    }
+
+    // Дыру между концом файла и `offset` заполняем нулями: там могли остаться старые данные
+    fn zero_fill(&self, fs_state: &mut State, from: u64, to: u64) -> FsResult<()> {
+        let zeros = vec![0u8; self.fs.cluster_size()];
+        let mut pos = from;
+        while pos < to {
+            let n = (to - pos).min(zeros.len() as u64) as usize;
+            self.write_locked(fs_state, pos, &zeros[..n])?;
+            pos += n as u64;
+        }
+        Ok(())
+    }
+}
+
+impl Inode for FatNode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        let mode = match (self.is_dir, self.read_only) {
+            (true, false) => 0o755,
+            (true, true) => 0o555,
+            (false, false) => 0o644,
+            (false, true) => 0o444,
+        };
+        Metadata {
+            file_type: if self.is_dir { FileType::Directory } else { FileType::File },
+            size: if self.is_dir { 0 } else { state.size as u64 },
+            mode,
+            created: 0,
+            modified: 0,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        if self.is_dir {
+            return Err(FsError::IsADirectory);
+        }
+        let fs = &*self.fs;
+        let _fs_state = fs.state.lock();
+        let (first, size) = {
+            let node = self.state.lock();
+            (node.first, node.size as u64)
+        };
+        if offset >= size {
+            return Ok(0);
+        }
+        let end = size.min(offset + buf.len() as u64);
+        let cluster_size = fs.cluster_size() as u64;
+        let mut sector = FatSector::default();
+        let mut cluster = first;
+        for _ in 0..offset / cluster_size {
+            cluster = fs.get_entry(cluster, &mut sector)?;
+        }
+        let mut data = vec![0u8; cluster_size as usize];
+        let mut pos = offset;
+        while pos < end {
+            if fs.kind.is_end(cluster) {
+                return Err(FsError::Io);
+            }
+            fs.read_cluster(cluster, &mut data)?;
+            let within = (pos % cluster_size) as usize;
+            let n = ((cluster_size as usize - within) as u64).min(end - pos) as usize;
+            let dst = (pos - offset) as usize;
+            buf[dst..dst + n].copy_from_slice(&data[within..within + n]);
+            pos += n as u64;
+            cluster = fs.get_entry(cluster, &mut sector)?;
+        }
+        Ok((end - offset) as usize)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        if self.is_dir {
+            return Err(FsError::IsADirectory);
+        }
+        if self.read_only {
+            return Err(FsError::PermissionDenied);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut fs_state = self.fs.state.lock();
+        let size = self.state.lock().size as u64;
+        if offset > size {
+            self.zero_fill(&mut fs_state, size, offset)?;
+        }
+        self.write_locked(&mut fs_state, offset, buf)?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        if self.is_dir {
+            return Err(FsError::IsADirectory);
+        }
+        if self.read_only {
+            return Err(FsError::PermissionDenied);
+        }
+        let fs = &*self.fs;
+        let mut fs_state = fs.state.lock();
+        let old = self.state.lock().size as u64;
+        if size > old {
+            return self.zero_fill(&mut fs_state, old, size);
+        }
+        let mut node = self.state.lock();
+        let cluster_size = fs.cluster_size() as u64;
+        let keep = size.div_ceil(cluster_size) as usize;
+        let chain = fs.chain(node.first)?;
+        if keep == 0 {
+            if node.first != 0 {
+                fs.free_chain(node.first)?;
+            }
+            node.first = 0;
+        } else if keep < chain.len() {
+            fs.set_entry(chain[keep - 1], fs.kind.end_of_chain())?;
+            fs.free_chain(chain[keep])?;
+        }
+        node.size = size as u32;
+        if let Some(location) = node.location {
+            fs.update_slot(location, node.first, node.size)?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        if !self.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        let mut fs_state = self.fs.state.lock();
+        let dir = self.dir_cluster();
+        let entry = self.fs.find(dir, name)?;
+        Ok(self.node_for(&mut fs_state, dir, &entry))
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        if !self.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        let _fs_state = self.fs.state.lock();
+        let (_, data) = self.fs.read_dir_data(self.dir_cluster())?;
+        Ok(parse_dir(&data).into_iter()
+            .map(|e| DirEntry {
+                file_type: if e.is_dir() { FileType::Directory } else { FileType::File },
+                name: e.name,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> FsResult<Arc<dyn Inode>> {
+        if !self.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        let fs = &*self.fs;
+        let mut fs_state = fs.state.lock();
+        let dir = self.dir_cluster();
+        let template = match file_type {
+            FileType::File => entry_template(ATTR_ARCHIVE, 0),
+            FileType::Directory => {
+                let cluster = fs.allocate(&mut fs_state, None)?;
+                let mut data = vec![0u8; fs.cluster_size()];
+                let mut dot = entry_template(ATTR_DIRECTORY, cluster);
+                dot[..11].copy_from_slice(b".          ");
+                // ".." на корень всегда хранит 0, даже в FAT32
+                let parent = if dir == fs.root_cluster { 0 } else { dir };
+                let mut dotdot = entry_template(ATTR_DIRECTORY, parent);
+                dotdot[..11].copy_from_slice(b"..         ");
+                data[..ENTRY_SIZE].copy_from_slice(&dot);
+                data[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(&dotdot);
+                fs.write_cluster(cluster, &data)?;
+                entry_template(ATTR_DIRECTORY, cluster)
+            }
+            // В FAT символических ссылок нет
+            FileType::Symlink => return Err(FsError::Unsupported),
+        };
+        let slot = match fs.add_entry(&mut fs_state, dir, name, &template) {
+            Ok(slot) => slot,
+            Err(e) => {
+                if file_type == FileType::Directory {
+                    fs.free_chain(first_cluster(&template))?;
+                }
+                return Err(e);
+            }
+        };
+        let (_, data) = fs.read_dir_data(dir)?;
+        let entry = parse_dir(&data).into_iter().find(|e| e.slot == slot).ok_or(FsError::Io)?;
+        Ok(self.node_for(&mut fs_state, dir, &entry))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        if !self.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        let fs = &*self.fs;
+        let mut fs_state = fs.state.lock();
+        let dir = self.dir_cluster();
+        let entry = fs.find(dir, name)?;
+        let first = first_cluster(&entry.data);
+        // Каталог без кластеров пуст; кластер 0 для read_dir_data означает корень
+        if entry.is_dir() && first != 0 {
+            let (_, data) = fs.read_dir_data(first)?;
+            if !parse_dir(&data).is_empty() {
+                return Err(FsError::NotEmpty);
+            }
+        }
+        fs.remove_entry(dir, &entry)?;
+        if first != 0 {
+            fs.free_chain(first)?;
+        }
+        if let Some(node) = fs_state.nodes.remove(&(dir, entry.slot)).and_then(|w| w.upgrade()) {
+            let mut node = node.state.lock();
+            node.location = None;
+            node.removed = true;
+        }
+        Ok(())
+    }
+
+    fn rename(&self, name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> FsResult<()> {
+        let target = match (**new_dir).as_any().downcast_ref::<FatNode>() {
+            Some(target) if Arc::ptr_eq(&target.fs, &self.fs) => target,
+            _ => return Err(FsError::Unsupported),
+        };
+        if !self.is_dir || !target.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        let fs = &*self.fs;
+        let mut fs_state = fs.state.lock();
+        let (from, to) = (self.dir_cluster(), target.dir_cluster());
+        let entry = fs.find(from, name)?;
+        // Старая запись ещё на месте, поэтому новая не займёт её слоты
+        let slot = fs.add_entry(&mut fs_state, to, new_name, &entry.data)?;
+        fs.remove_entry(from, &entry)?;
+        if let Some(node) = fs_state.nodes.remove(&(from, entry.slot)) {
+            if let Some(live) = node.upgrade() {
+                live.state.lock().location = Some((to, slot));
+            }
+            fs_state.nodes.insert((to, slot), node);
+        }
+        if entry.is_dir() && from != to {
+            let first = first_cluster(&entry.data);
+            let parent = if to == fs.root_cluster { 0 } else { to };
+            let mut data = vec![0u8; fs.cluster_size()];
+            fs.read_cluster(first, &mut data)?;
+            set_first_cluster(&mut data[ENTRY_SIZE..2 * ENTRY_SIZE], parent);
+            fs.write_cluster(first, &data)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct FatFs {
+    name: &'static str,
+    root: Arc<FatNode>,
+}
+
+impl FatFs {
+    pub fn new(device: Arc<dyn BlockDevice>) -> FsResult<FatFs> {
+        let fs = Arc::new(Fat::parse(device)?);
+        let name = match fs.kind {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        };
+        let root = Arc::new(FatNode {
+            is_dir: true,
+            read_only: false,
+            state: Mutex::new(NodeState { first: fs.root_cluster, size: 0, location: None, removed: false }),
+            fs,
+        });
+        Ok(FatFs { name, root })
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
@@ -0,0 +1,93 @@
+//! Initrd: USTAR-архив каталога `rootfs/`, собранный build.rs и встроенный в образ ядра.
+
+use alloc::{string::String, sync::Arc};
+use core::str;
+use super::{FileType, FsError, FsResult, Inode, tmpfs::{TmpDir, TmpFile}};
+
+pub static ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.tar"));
+
+const BLOCK: usize = 512;
+
+/// Распаковывает архив в каталог `root`; возвращает число созданных записей.
+/// Недостающие промежуточные каталоги создаются с правами 0o755; уже существующим
+/// каталогам ставятся права из архива, а существующие файлы заменяются.
+pub fn unpack(archive: &[u8], root: &Arc<dyn Inode>) -> FsResult<usize> {
+    let mut offset = 0;
+    let mut count = 0;
+    while offset + BLOCK <= archive.len() {
+        let header = &archive[offset..offset + BLOCK];
+        // Конец архива — нулевой блок
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if &header[257..262] != b"ustar" || checksum(header) != octal(&header[148..156])? {
+            return Err(FsError::Io);
+        }
+        let size = octal(&header[124..136])? as usize;
+        let data_start = offset + BLOCK;
+        let data = archive.get(data_start..data_start + size).ok_or(FsError::Io)?;
+        offset = data_start + size.div_ceil(BLOCK) * BLOCK;
+
+        let path = path(header)?;
+        let mode = octal(&header[100..108])? as u16;
+        let mut components = path.split('/').filter(|c| !c.is_empty() && *c != ".");
+        let name = match components.next_back() {
+            Some(name) => name,
+            None => continue,
+        };
+        let mut dir = root.clone();
+        for component in components {
+            dir = match dir.lookup(component) {
+                Ok(inode) => inode,
+                Err(FsError::NotFound) => dir.create(component, FileType::Directory)?,
+                Err(e) => return Err(e),
+            };
+        }
+        match header[156] {
+            b'0' | 0 => {
+                let file = TmpFile::with_data(mode, data.into());
+                match dir.link(name, file.clone()) {
+                    Err(FsError::AlreadyExists) => {
+                        dir.unlink(name)?;
+                        dir.link(name, file)?;
+                    }
+                    result => result?,
+                }
+            }
+            // Каталог мог быть уже создан как промежуточный
+            b'5' => match dir.lookup(name) {
+                Ok(existing) if existing.metadata().file_type == FileType::Directory => existing.set_mode(mode)?,
+                Ok(_) => return Err(FsError::NotADirectory),
+                Err(FsError::NotFound) => dir.link(name, TmpDir::new(mode))?,
+                Err(e) => return Err(e),
+            },
+            // Ссылки и устройства пока не поддерживаются
+            _ => continue,
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn path(header: &[u8]) -> FsResult<String> {
+    let name = field_str(&header[..100])?;
+    let prefix = field_str(&header[345..500])?;
+    Ok(if prefix.is_empty() { String::from(name) } else { alloc::format!("{}/{}", prefix, name) })
+}
+
+fn field_str(field: &[u8]) -> FsResult<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..len]).map_err(|_| FsError::InvalidPath)
+}
+
+// Числовые поля — восьмеричные ASCII, дополненные пробелами или NUL
+fn octal(field: &[u8]) -> FsResult<u64> {
+    let digits = field_str(field)?.trim_matches(' ');
+    u64::from_str_radix(digits, 8).map_err(|_| FsError::Io)
+}
+
+fn checksum(header: &[u8]) -> u64 {
+    header.iter().enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+        .sum()
+}
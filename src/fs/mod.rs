@@ -0,0 +1,525 @@
+//! Виртуальная файловая система: точки монтирования, разбор путей и открытые
+//! файлы поверх конкретных ФС, реализующих `FileSystem` и `Inode`.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{any::Any, fmt, ops::BitOr};
+use spin::Mutex;
+use crate::{print, println};
+
+pub mod ext2;
+pub mod fat;
+pub mod initrd;
+pub mod tmpfs;
+
+pub type FsResult<T> = Result<T, FsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    NotEmpty,
+    ReadOnly,
+    InvalidPath,
+    InvalidArgument,
+    BadDescriptor,
+    PermissionDenied,
+    Unsupported,
+    NoSpace,
+    SymlinkLoop,
+    Io,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::NotADirectory => "not a directory",
+            FsError::IsADirectory => "is a directory",
+            FsError::AlreadyExists => "file exists",
+            FsError::NotEmpty => "directory not empty",
+            FsError::ReadOnly => "read-only file system",
+            FsError::InvalidPath => "invalid path",
+            FsError::InvalidArgument => "invalid argument",
+            FsError::BadDescriptor => "bad file descriptor",
+            FsError::PermissionDenied => "permission denied",
+            FsError::Unsupported => "operation not supported",
+            FsError::NoSpace => "no space left on device",
+            FsError::SymlinkLoop => "too many levels of symbolic links",
+            FsError::Io => "I/O error",
+        })
+    }
+}
+
+impl From<crate::block::BlockError> for FsError {
+    fn from(_: crate::block::BlockError) -> Self {
+        FsError::Io
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub size: u64,
+    /// Права в стиле Unix (0o755 и т.п.).
+    pub mode: u16,
+    /// Время создания и последнего изменения, мс с загрузки.
+    pub created: u64,
+    pub modified: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+}
+
+/// Доступ к конкретному типу inode, чтобы ФС узнала свой каталог в `rename`.
+/// Вызывать на `dyn Inode` (`(*arc).as_any()`), а не на самом `Arc`.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Файл или каталог конкретной ФС. Операции, которых ФС не умеет, по умолчанию
+/// отвечают ошибкой.
+pub trait Inode: AsAny + Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn set_mode(&self, _mode: u16) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn lookup(&self, _name: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Путь, на который указывает символическая ссылка.
+    fn read_link(&self) -> FsResult<String> {
+        Err(FsError::InvalidArgument)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Добавляет в каталог уже существующий inode той же ФС (для `rename`).
+    fn link(&self, _name: &str, _inode: Arc<dyn Inode>) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Убирает запись из каталога; каталог должен быть пуст.
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Переносит запись `name` в каталог `new_dir` той же ФС под именем `new_name`
+    /// (записи с таким именем там нет).
+    fn rename(&self, name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> FsResult<()> {
+        let inode = self.lookup(name)?;
+        new_dir.link(new_name, inode)?;
+        self.unlink(name)
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn root(&self) -> Arc<dyn Inode>;
+}
+
+struct Mount {
+    path: Vec<String>,
+    fs: Arc<dyn FileSystem>,
+}
+
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// Корень — tmpfs, заполненная из встроенного initrd. Возвращает число распакованных записей;
+/// если архив повреждён, корень всё равно монтируется с тем, что успели распаковать.
+pub fn init() -> FsResult<usize> {
+    let root = tmpfs::TmpFs::new();
+    let result = initrd::unpack(initrd::ARCHIVE, &root.root());
+    mount("/", Arc::new(root)).expect("cannot mount root");
+    result
+}
+
+/// Разбирает путь в список компонентов; `.` и `..` сворачиваются,
+/// `..` из корня остаётся в корне. Относительные пути считаются от `/`.
+pub fn normalize(path: &str) -> FsResult<Vec<String>> {
+    let mut components: Vec<String> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name if name.contains('\0') => return Err(FsError::InvalidPath),
+            name => components.push(String::from(name)),
+        }
+    }
+    Ok(components)
+}
+
+fn to_path(components: &[String]) -> String {
+    let mut path = String::new();
+    for c in components {
+        path.push('/');
+        path.push_str(c);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+/// Монтирует `fs` в существующий каталог `path` (или корнем).
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> FsResult<()> {
+    let components = normalize(path)?;
+    if !components.is_empty() && lookup(path)?.metadata().file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|m| m.path == components) {
+        return Err(FsError::AlreadyExists);
+    }
+    mounts.push(Mount { path: components, fs });
+    Ok(())
+}
+
+// ФС с самой длинной точкой монтирования, которая является префиксом пути
+fn find_mount(components: &[String]) -> FsResult<(Arc<dyn FileSystem>, usize)> {
+    MOUNTS.lock().iter()
+        .filter(|m| components.starts_with(&m.path))
+        .max_by_key(|m| m.path.len())
+        .map(|m| (m.fs.clone(), m.path.len()))
+        .ok_or(FsError::NotFound)
+}
+
+// Глубже этого цепочки ссылок считаются петлёй
+const MAX_SYMLINKS: usize = 8;
+
+// Ссылки в середине пути раскрываются всегда, последний компонент — только при `follow`.
+// Цель ссылки подставляется в путь, и разбор начинается заново: так ссылка может
+// вести через точку монтирования.
+fn resolve_with(components: &[String], follow: bool) -> FsResult<Arc<dyn Inode>> {
+    let mut components = components.to_vec();
+    let mut links = 0;
+    'restart: loop {
+        let (fs, skip) = find_mount(&components)?;
+        let mut inode = fs.root();
+        for i in skip..components.len() {
+            inode = inode.lookup(&components[i])?;
+            let last = i + 1 == components.len();
+            if inode.metadata().file_type != FileType::Symlink || (last && !follow) {
+                continue;
+            }
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(FsError::SymlinkLoop);
+            }
+            let target = inode.read_link()?;
+            let mut path = if target.starts_with('/') { String::new() } else { to_path(&components[..i]) };
+            path.push('/');
+            path.push_str(&target);
+            for rest in &components[i + 1..] {
+                path.push('/');
+                path.push_str(rest);
+            }
+            components = normalize(&path)?;
+            continue 'restart;
+        }
+        return Ok(inode);
+    }
+}
+
+fn resolve(components: &[String]) -> FsResult<Arc<dyn Inode>> {
+    resolve_with(components, true)
+}
+
+pub fn lookup(path: &str) -> FsResult<Arc<dyn Inode>> {
+    resolve(&normalize(path)?)
+}
+
+/// Как `lookup`, но ссылка в конце пути не раскрывается.
+pub fn lookup_link(path: &str) -> FsResult<Arc<dyn Inode>> {
+    resolve_with(&normalize(path)?, false)
+}
+
+// Каталог, в котором лежит последний компонент пути, и имя этого компонента
+fn resolve_parent(components: &[String]) -> FsResult<(Arc<dyn Inode>, &str)> {
+    let (name, parent) = components.split_last().ok_or(FsError::InvalidPath)?;
+    // Точку монтирования нельзя удалить или переименовать
+    if find_mount(components)?.1 == components.len() {
+        return Err(FsError::PermissionDenied);
+    }
+    Ok((resolve(parent)?, name))
+}
+
+pub fn create(path: &str, file_type: FileType) -> FsResult<Arc<dyn Inode>> {
+    let components = normalize(path)?;
+    let (parent, name) = resolve_parent(&components)?;
+    parent.create(name, file_type)
+}
+
+pub fn mkdir(path: &str) -> FsResult<()> {
+    create(path, FileType::Directory).map(|_| ())
+}
+
+/// Удаляет файл или пустой каталог.
+pub fn unlink(path: &str) -> FsResult<()> {
+    let components = normalize(path)?;
+    let (parent, name) = resolve_parent(&components)?;
+    parent.unlink(name)
+}
+
+/// Переносит файл или каталог в пределах одной ФС; существующий файл назначения заменяется.
+/// Замена не атомарна: старый файл удаляется до переноса, и если перенос затем
+/// не удастся, назначения уже не будет.
+pub fn rename(from: &str, to: &str) -> FsResult<()> {
+    let from = normalize(from)?;
+    let to = normalize(to)?;
+    if to.starts_with(&from) {
+        return Err(if to == from { FsError::AlreadyExists } else { FsError::InvalidArgument });
+    }
+    let (from_fs, _) = find_mount(&from)?;
+    let (to_fs, _) = find_mount(&to)?;
+    if !Arc::ptr_eq(&from_fs, &to_fs) {
+        return Err(FsError::Unsupported);
+    }
+    let (from_parent, from_name) = resolve_parent(&from)?;
+    let (to_parent, to_name) = resolve_parent(&to)?;
+    let inode = from_parent.lookup(from_name)?;
+    match to_parent.lookup(to_name) {
+        // Имена без учёта регистра (FAT) могут указывать на тот же файл — удалять его нельзя
+        Ok(existing) if Arc::as_ptr(&existing) as *const u8 == Arc::as_ptr(&inode) as *const u8 => {
+            return Err(FsError::AlreadyExists)
+        }
+        Ok(existing) if existing.metadata().file_type == FileType::Directory => return Err(FsError::IsADirectory),
+        Ok(_) => to_parent.unlink(to_name)?,
+        Err(FsError::NotFound) => {}
+        Err(e) => return Err(e),
+    }
+    drop(inode);
+    from_parent.rename(from_name, &to_parent, to_name)
+}
+
+/// Копирует содержимое файла (в том числе между разными ФС).
+pub fn copy(from: &str, to: &str) -> FsResult<()> {
+    let source = open(from, OpenFlags::READ)?;
+    let target = open(to, OpenFlags::WRITE | OpenFlags::CREATE)?;
+    // Усечение цели стёрло бы и источник
+    if Arc::as_ptr(&source.inode) as *const u8 == Arc::as_ptr(&target.inode) as *const u8 {
+        return Err(FsError::InvalidArgument);
+    }
+    target.inode.truncate(0)?;
+    // Копируем кусками: файл может не поместиться в кучу целиком
+    let mut chunk = [0u8; 512];
+    loop {
+        match source.read(&mut chunk)? {
+            0 => return Ok(()),
+            n => write_all(&target, &chunk[..n])?,
+        }
+    }
+}
+
+fn write_all(file: &OpenFile, mut buf: &[u8]) -> FsResult<()> {
+    while !buf.is_empty() {
+        match file.write(buf)? {
+            0 => return Err(FsError::NoSpace),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Создаёт пустой файл или обновляет время изменения существующего.
+pub fn touch(path: &str) -> FsResult<()> {
+    let file = open(path, OpenFlags::WRITE | OpenFlags::CREATE)?;
+    // Пустая запись в конец только обновляет время изменения
+    file.inode.write_at(file.metadata().size, &[])?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags(1 << 0);
+    pub const WRITE: OpenFlags = OpenFlags(1 << 1);
+    pub const APPEND: OpenFlags = OpenFlags(1 << 2);
+    pub const TRUNCATE: OpenFlags = OpenFlags(1 << 3);
+    pub const CREATE: OpenFlags = OpenFlags(1 << 4);
+
+    pub fn contains(self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = OpenFlags;
+    fn bitor(self, other: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Открытый файл: inode плюс текущая позиция. Разделяется между дескрипторами.
+pub struct OpenFile {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    offset: Mutex<u64>,
+}
+
+pub fn open(path: &str, flags: OpenFlags) -> FsResult<Arc<OpenFile>> {
+    let components = normalize(path)?;
+    let inode = match resolve(&components) {
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = resolve_parent(&components)?;
+            parent.create(name, FileType::File)?
+        }
+        result => result?,
+    };
+    let is_dir = inode.metadata().file_type == FileType::Directory;
+    if is_dir && flags.contains(OpenFlags::WRITE) {
+        return Err(FsError::IsADirectory);
+    }
+    if flags.contains(OpenFlags::TRUNCATE) {
+        inode.truncate(0)?;
+    }
+    Ok(Arc::new(OpenFile { inode, flags, offset: Mutex::new(0) }))
+}
+
+impl OpenFile {
+    pub fn metadata(&self) -> Metadata {
+        self.inode.metadata()
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(FsError::PermissionDenied);
+        }
+        let mut offset = self.offset.lock();
+        let n = self.inode.read_at(*offset, buf)?;
+        *offset += n as u64;
+        Ok(n)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::PermissionDenied);
+        }
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.inode.metadata().size;
+        }
+        let n = self.inode.write_at(*offset, buf)?;
+        *offset += n as u64;
+        Ok(n)
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => offset.checked_add_signed(n),
+            SeekFrom::End(n) => self.inode.metadata().size.checked_add_signed(n),
+        };
+        *offset = new.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+}
+
+pub fn list_dir(path: &str) {
+    let entries = match lookup(path).and_then(|dir| dir.read_dir()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("ls: {}: {}", path, e);
+            return;
+        }
+    };
+    for entry in entries {
+        match entry.file_type {
+            FileType::Directory => print!("{}/  ", entry.name),
+            FileType::File => print!("{}  ", entry.name),
+            FileType::Symlink => print!("{}@  ", entry.name),
+        }
+    }
+    println!("");
+}
+
+/// Монтирует ФС с блочного устройства, определив её тип; возвращает название типа.
+pub fn mount_device(device: &str, path: &str) -> FsResult<&'static str> {
+    let device = crate::block::get(device).map_err(|_| FsError::NotFound)?;
+    let fs: Arc<dyn FileSystem> = match ext2::Ext2Fs::new(device.clone()) {
+        Ok(fs) => Arc::new(fs),
+        Err(FsError::Unsupported) => Arc::new(fat::FatFs::new(device)?),
+        Err(e) => return Err(e),
+    };
+    let name = fs.name();
+    mount(path, fs)?;
+    Ok(name)
+}
+
+pub fn print_stat(path: &str) {
+    match lookup_link(path) {
+        Ok(inode) => {
+            let m = inode.metadata();
+            let kind = match m.file_type {
+                FileType::File => "file",
+                FileType::Directory => "directory",
+                FileType::Symlink => "symlink",
+            };
+            println!("{}: {}, {} bytes, mode {:o}", path, kind, m.size, m.mode);
+            if let Ok(target) = inode.read_link() {
+                println!("-> {}", target);
+            }
+            println!("created at {} ms, modified at {} ms", m.created, m.modified);
+        }
+        Err(e) => println!("stat: {}: {}", path, e),
+    }
+}
+
+pub fn list_mounts() {
+    for m in MOUNTS.lock().iter() {
+        println!("{} on {}", m.fs.name(), to_path(&m.path));
+    }
+}
+
+pub fn mount_count() -> usize {
+    MOUNTS.lock().len()
+}
@@ -0,0 +1,198 @@
+//! Файловая система в куче: вложенные каталоги, произвольное (двоичное) содержимое.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU16, Ordering};
+use spin::Mutex;
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+use crate::interrupts::TICKS;
+
+// Содержимое файлов лежит в куче ядра: один файл не должен съесть её всю
+const MAX_FILE_SIZE: usize = crate::allocator::HEAP_SIZE / 4;
+
+fn now_ms() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub struct TmpFs {
+    root: Arc<TmpDir>,
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        TmpFs { root: TmpDir::new(0o755) }
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct Times {
+    created: u64,
+    modified: u64,
+}
+
+impl Times {
+    fn new() -> Self {
+        let now = now_ms();
+        Times { created: now, modified: now }
+    }
+}
+
+pub struct TmpFile {
+    mode: AtomicU16,
+    state: Mutex<(Vec<u8>, Times)>,
+}
+
+impl TmpFile {
+    pub fn new(mode: u16) -> Arc<TmpFile> {
+        Self::with_data(mode, Vec::new())
+    }
+
+    pub fn with_data(mode: u16, data: Vec<u8>) -> Arc<TmpFile> {
+        Arc::new(TmpFile { mode: AtomicU16::new(mode), state: Mutex::new((data, Times::new())) })
+    }
+}
+
+impl Inode for TmpFile {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        Metadata {
+            file_type: FileType::File,
+            size: state.0.len() as u64,
+            mode: self.mode.load(Ordering::Relaxed),
+            created: state.1.created,
+            modified: state.1.modified,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let state = self.state.lock();
+        let data = &state.0;
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        let (data, times) = &mut *state;
+        let end = offset.checked_add(buf.len() as u64).ok_or(FsError::NoSpace)?;
+        if end > MAX_FILE_SIZE as u64 {
+            return Err(FsError::NoSpace);
+        }
+        let (start, end) = (offset as usize, end as usize);
+        // Запись за концом файла заполняет дыру нулями
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        times.modified = now_ms();
+        Ok(buf.len())
+    }
+
+    fn set_mode(&self, mode: u16) -> FsResult<()> {
+        self.mode.store(mode, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        if size > MAX_FILE_SIZE as u64 {
+            return Err(FsError::NoSpace);
+        }
+        let mut state = self.state.lock();
+        state.0.resize(size as usize, 0);
+        state.1.modified = now_ms();
+        Ok(())
+    }
+}
+
+type Entries = BTreeMap<String, Arc<dyn Inode>>;
+
+pub struct TmpDir {
+    mode: AtomicU16,
+    state: Mutex<(Entries, Times)>,
+}
+
+impl TmpDir {
+    pub fn new(mode: u16) -> Arc<TmpDir> {
+        Arc::new(TmpDir { mode: AtomicU16::new(mode), state: Mutex::new((BTreeMap::new(), Times::new())) })
+    }
+}
+
+impl Inode for TmpDir {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        Metadata {
+            file_type: FileType::Directory,
+            size: state.0.len() as u64,
+            mode: self.mode.load(Ordering::Relaxed),
+            created: state.1.created,
+            modified: state.1.modified,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        self.state.lock().0.get(name).cloned().ok_or(FsError::NotFound)
+    }
+
+    fn set_mode(&self, mode: u16) -> FsResult<()> {
+        self.mode.store(mode, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        Ok(self.state.lock().0.iter()
+            .map(|(name, inode)| DirEntry { name: name.clone(), file_type: inode.metadata().file_type })
+            .collect())
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> FsResult<Arc<dyn Inode>> {
+        let inode: Arc<dyn Inode> = match file_type {
+            FileType::File => TmpFile::new(0o644),
+            FileType::Directory => TmpDir::new(0o755),
+            FileType::Symlink => return Err(FsError::InvalidArgument),
+        };
+        self.link(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn link(&self, name: &str, inode: Arc<dyn Inode>) -> FsResult<()> {
+        let mut state = self.state.lock();
+        if state.0.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        state.0.insert(String::from(name), inode);
+        state.1.modified = now_ms();
+        Ok(())
+    }
+
+    fn rename(&self, name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> FsResult<()> {
+        let inode = self.lookup(name)?;
+        new_dir.link(new_name, inode)?;
+        // Просто убираем запись: каталог переезжает вместе с содержимым
+        let mut state = self.state.lock();
+        state.0.remove(name);
+        state.1.modified = now_ms();
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        let mut state = self.state.lock();
+        let inode = state.0.get(name).ok_or(FsError::NotFound)?;
+        let meta = inode.metadata();
+        if meta.file_type == FileType::Directory && meta.size != 0 {
+            return Err(FsError::NotEmpty);
+        }
+        state.0.remove(name);
+        state.1.modified = now_ms();
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::{boxed::Box, vec};
 use x86_64::{
     instructions::segmentation::{CS, Segment},
     instructions::tables::load_tss,
@@ -8,20 +9,22 @@ use x86_64::{
 use lazy_static::lazy_static;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
+fn new_tss(double_fault_stack_end: VirtAddr) -> TaskStateSegment {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack_end;
+    tss
+}
 
-        const STACK_SIZE: usize = 4096 * 5;
-        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+lazy_static! {
+    static ref BSP_TSS: TaskStateSegment = {
+        static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
         let stack_end = VirtAddr::from_ptr(unsafe { &STACK as *const _ })
-            + STACK_SIZE as u64;
-
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_end;
-
-        tss
+            + DOUBLE_FAULT_STACK_SIZE as u64;
+        new_tss(stack_end)
     };
+    static ref BSP_TABLES: CpuTables = CpuTables::new(&BSP_TSS);
 }
 
 struct Selectors {
@@ -29,23 +32,37 @@ struct Selectors {
     tss_selector: SegmentSelector,
 }
 
-lazy_static! {
-    static ref GDT_AND_SELECTORS: (GlobalDescriptorTable, Selectors) = {
+/// GDT и TSS одного процессора: у каждого свой стек для double fault.
+pub struct CpuTables {
+    gdt: GlobalDescriptorTable,
+    selectors: Selectors,
+}
 
+impl CpuTables {
+    fn new(tss: &'static TaskStateSegment) -> Self {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&*TSS));
+        let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+        CpuTables { gdt, selectors: Selectors { code_selector, tss_selector } }
+    }
 
-        (gdt, Selectors { code_selector, tss_selector })
-    };
+    /// Таблицы для AP; выделяются в куче и живут до конца работы.
+    pub fn allocate() -> &'static CpuTables {
+        let stack = Box::leak(vec![0u8; DOUBLE_FAULT_STACK_SIZE].into_boxed_slice());
+        let stack_end = VirtAddr::from_ptr(stack.as_ptr()) + DOUBLE_FAULT_STACK_SIZE as u64;
+        let tss = Box::leak(Box::new(new_tss(stack_end)));
+        Box::leak(Box::new(CpuTables::new(tss)))
+    }
+
+    pub fn load(&'static self) {
+        self.gdt.load();
+        unsafe {
+            CS::set_reg(self.selectors.code_selector);
+            load_tss(self.selectors.tss_selector);
+        }
+    }
 }
 
 pub fn init() {
-
-    GDT_AND_SELECTORS.0.load();
-
-    unsafe {
-        CS::set_reg(GDT_AND_SELECTORS.1.code_selector);
-        load_tss(GDT_AND_SELECTORS.1.tss_selector);
-    }
-}
\ No newline at end of file
+    BSP_TABLES.load();
+}
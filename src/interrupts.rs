@@ -1,52 +1,130 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
-use spin;
-use crate::{gdt, println, task::keyboard::add_scancode};
+use crate::{gdt, lock::IrqSafeMutex, println};
 use core::sync::atomic::{AtomicU64, Ordering};
 
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub static PICS: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: IrqSafeMutex<ChainedPics> =
+    IrqSafeMutex::new("pics", unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 pub static TICKS: AtomicU64 = AtomicU64::new(0);
+static LAST_SECOND: AtomicU64 = AtomicU64::new(0);
+
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+// ~1 мс на тик
+pub const PIT_DIVISOR: u16 = 1193;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
-    Keyboard = PIC_1_OFFSET + 1,
 }
 
 impl InterruptIndex {
     fn as_u8(self) -> u8 { self as u8 }
 }
 
-lazy_static! {
-    static ref IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        unsafe {
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+/// EOI тому контроллеру, через который сейчас идут прерывания.
+pub fn end_of_interrupt(vector: u8) {
+    if crate::apic::is_enabled() {
+        crate::apic::eoi();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(vector) };
+    }
+}
+
+/// Инициализирует 8259; открыты только таймер и каскад, остальные линии
+/// открывает `irq::register_irq`.
+pub fn init_pics() {
+    unsafe { PICS.lock().initialize() };
+    use x86_64::instructions::port::Port;
+    unsafe {
+        Port::<u8>::new(0x21).write(!0b101);
+        Port::<u8>::new(0xA1).write(0xFF);
+    }
+}
+
+/// Маскирует или открывает линию 8259.
+pub fn set_pic_masked(irq: u8, masked: bool) {
+    use x86_64::instructions::port::Port;
+    let (mut port, bit) = if irq < 8 {
+        (Port::<u8>::new(0x21), irq)
+    } else {
+        (Port::<u8>::new(0xA1), irq - 8)
+    };
+    let _pics = PICS.lock();
+    unsafe {
+        let mask = port.read();
+        port.write(if masked { mask | 1 << bit } else { mask & !(1 << bit) });
+        // Линии ведомого контроллера приходят через IRQ2 ведущего
+        if irq >= 8 && !masked {
+            let mut master = Port::<u8>::new(0x21);
+            let mask = master.read();
+            master.write(mask & !(1 << 2));
         }
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
-        idt
+    }
+}
+
+/// Стоит ли линия `irq` в ISR 8259, то есть обслуживается ли сейчас.
+pub fn pic_in_service(irq: u8) -> bool {
+    use x86_64::instructions::port::Port;
+    const READ_ISR: u8 = 0x0B;
+    let (mut command, bit) = if irq < 8 {
+        (Port::<u8>::new(0x20), irq)
+    } else {
+        (Port::<u8>::new(0xA0), irq - 8)
     };
+    let _pics = PICS.lock();
+    unsafe {
+        command.write(READ_ISR);
+        command.read() & (1 << bit) != 0
+    }
+}
+
+/// Маскирует все линии обоих 8259 (после перехода на IOAPIC).
+pub fn disable_pic() {
+    use x86_64::instructions::port::Port;
+    unsafe {
+        Port::<u8>::new(0x21).write(0xFF);
+        Port::<u8>::new(0xA1).write(0xFF);
+    }
+}
+
+fn new_idt() -> InterruptDescriptorTable {
+    let mut idt = InterruptDescriptorTable::new();
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    unsafe {
+        idt.double_fault.set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
+    crate::irq::install_stubs(&mut idt);
+    idt[crate::apic::WAKEUP_VECTOR].set_handler_fn(wakeup_interrupt_handler);
+    idt[crate::apic::SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+    idt
+}
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = new_idt();
+}
+
+/// Своя IDT для AP; выделяется в куче и живёт до конца работы.
+pub fn allocate_idt() -> &'static InterruptDescriptorTable {
+    alloc::boxed::Box::leak(alloc::boxed::Box::new(new_idt()))
 }
 
 fn init_pit() {
-    let divisor: u16 = 1193;
+    let divisor = PIT_DIVISOR;
     unsafe {
         use x86_64::instructions::port::Port;
         let mut cmd_port = Port::new(0x43);
         let mut data_port = Port::new(0x40);
-        cmd_port.write(0x36u8); // Канал 0, lobyte/hibyte, режим 3
+        cmd_port.write(0x34u8); // Канал 0, lobyte/hibyte, режим 2: счётчик убывает ровно на 1 за такт
         data_port.write((divisor & 0xFF) as u8);
         data_port.write((divisor >> 8) as u8);
     }
@@ -60,25 +138,26 @@ pub fn init() {
 extern "x86-interrupt" fn timer_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
-    let current_ticks = TICKS.fetch_add(1, Ordering::Relaxed);
-    if current_ticks % 18 == 0 {
-        let seconds = current_ticks / 18;
-        crate::vga_buffer::print_timer(seconds);
-    }
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    crate::irq::account(0);
+    let current_ticks = crate::time::on_timer_interrupt();
+    // Часы в углу экрана рисует задача softirq: здесь WRITER не трогаем
+    let seconds = current_ticks / 1000;
+    if LAST_SECOND.swap(seconds, Ordering::Relaxed) != seconds {
+        crate::deferred::defer(crate::vga_buffer::print_timer, seconds);
     }
+    end_of_interrupt(InterruptIndex::Timer.as_u8());
+    // EOI уже отправлен: переключение может не вернуться сюда до следующего кванта
+    crate::thread::on_tick();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use x86_64::instructions::port::Port;
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    add_scancode(scancode);
-    unsafe { PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8()); }
+// IPI от другого CPU: достаточно того, что процессор вышел из hlt
+extern "x86-interrupt" fn wakeup_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::apic::eoi();
 }
 
+// Ложное прерывание local APIC: EOI для него не посылается
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) { println!("{:#?}", stack_frame); }
 extern "x86-interrupt" fn double_fault_handler(f: InterruptStackFrame, _: u64) -> ! { panic!("{:#?}", f); }
 extern "x86-interrupt" fn page_fault_handler(_: InterruptStackFrame, _: PageFaultErrorCode) { loop {} }
\ No newline at end of file
@@ -0,0 +1,220 @@
+//! Регистрация обработчиков аппаратных прерываний. Линия `irq` получает вектор
+//! `IRQ_BASE + irq`; на одной линии может висеть несколько обработчиков.
+//! Линии за `IRQ_COUNT` не связаны с выводами IOAPIC и раздаются под MSI.
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use crate::{apic, interrupts, lock::IrqSafeMutex, println};
+
+pub const IRQ_BASE: u8 = interrupts::PIC_1_OFFSET;
+/// ISA-линии 0..16 и GSI IOAPIC до 24.
+pub const IRQ_COUNT: usize = 24;
+pub const MSI_COUNT: usize = 8;
+const LINES: usize = IRQ_COUNT + MSI_COUNT;
+// Линия 0 занята таймером
+const TIMER_IRQ: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// Прерывание было от нашего устройства.
+    Handled,
+    /// Не наше (линия разделяется с другими устройствами).
+    None,
+}
+
+struct Action {
+    id: u64,
+    name: &'static str,
+    handler: Box<dyn Fn() -> IrqReturn + Send + Sync>,
+}
+
+impl Action {
+    fn new<F>(name: &'static str, handler: F) -> Action
+    where
+        F: Fn() -> IrqReturn + Send + Sync + 'static,
+    {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Action { id: NEXT_ID.fetch_add(1, Ordering::Relaxed), name, handler: Box::new(handler) }
+    }
+}
+
+/// Возвращается `register_irq`; нужен, чтобы снять обработчик.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandle {
+    irq: u8,
+    id: u64,
+}
+
+static ACTIONS: IrqSafeMutex<BTreeMap<u8, Vec<Action>>> = IrqSafeMutex::new("irq_actions", BTreeMap::new());
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static COUNTS: [AtomicU64; LINES] = [ZERO; LINES];
+static UNHANDLED: [AtomicU64; LINES] = [ZERO; LINES];
+
+/// Вешает `handler` на линию `irq`; первая регистрация открывает линию в PIC или IOAPIC.
+/// Обработчик выполняется в прерывании: без выделения памяти и без долгих замков.
+pub fn register_irq<F>(irq: u8, name: &'static str, handler: F) -> Result<IrqHandle, &'static str>
+where
+    F: Fn() -> IrqReturn + Send + Sync + 'static,
+{
+    if irq as usize >= IRQ_COUNT {
+        return Err("no such IRQ");
+    }
+    if irq == TIMER_IRQ {
+        return Err("IRQ 0 is reserved for the timer");
+    }
+    let action = Action::new(name, handler);
+    let id = action.id;
+    let mut actions = ACTIONS.lock();
+    let line = actions.entry(irq).or_default();
+    line.push(action);
+    if line.len() == 1 {
+        drop(actions);
+        unmask(irq)?;
+    }
+    Ok(IrqHandle { irq, id })
+}
+
+/// Выделяет свободную MSI-линию под `handler`; возвращает её вектор для адреса сообщения.
+/// MSI доставляются только через local APIC.
+pub fn register_msi<F>(name: &'static str, handler: F) -> Result<(IrqHandle, u8), &'static str>
+where
+    F: Fn() -> IrqReturn + Send + Sync + 'static,
+{
+    if !apic::is_enabled() {
+        return Err("MSI needs the local APIC");
+    }
+    let action = Action::new(name, handler);
+    let id = action.id;
+    let mut actions = ACTIONS.lock();
+    let irq = (IRQ_COUNT as u8..LINES as u8)
+        .find(|irq| !actions.contains_key(irq))
+        .ok_or("no free MSI vectors")?;
+    actions.insert(irq, alloc::vec![action]);
+    Ok((IrqHandle { irq, id }, IRQ_BASE + irq))
+}
+
+/// Снимает обработчик; последний снятый закрывает линию.
+pub fn unregister_irq(handle: IrqHandle) {
+    let removed = {
+        let mut actions = ACTIONS.lock();
+        let line = match actions.get_mut(&handle.irq) {
+            Some(line) => line,
+            None => return,
+        };
+        let removed = line.iter().position(|a| a.id == handle.id).map(|i| line.remove(i));
+        if line.is_empty() {
+            actions.remove(&handle.irq);
+            if (handle.irq as usize) < IRQ_COUNT {
+                mask(handle.irq);
+            }
+        }
+        removed
+    };
+    // Память обработчика освобождаем уже без замка
+    drop(removed);
+}
+
+fn unmask(irq: u8) -> Result<(), &'static str> {
+    if apic::is_enabled() {
+        if !apic::route_irq(irq, IRQ_BASE + irq) {
+            return Err("no IOAPIC handles this IRQ");
+        }
+    } else if irq >= 16 {
+        return Err("IRQ above 15 needs an IOAPIC");
+    } else {
+        interrupts::set_pic_masked(irq, false);
+    }
+    Ok(())
+}
+
+fn mask(irq: u8) {
+    if apic::is_enabled() {
+        apic::set_irq_masked(irq, true);
+    } else if irq < 16 {
+        interrupts::set_pic_masked(irq, true);
+    }
+}
+
+/// Заново маршрутизирует занятые линии (после перехода с PIC на IOAPIC).
+pub fn reroute() {
+    let lines: Vec<u8> = ACTIONS.lock().keys().copied().filter(|&irq| (irq as usize) < IRQ_COUNT).collect();
+    for irq in lines {
+        let _ = unmask(irq);
+    }
+}
+
+pub(crate) fn account(irq: u8) {
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+fn dispatch(irq: u8) {
+    account(irq);
+    // 8259 выдаёт ложный IRQ7 (IRQ15 у ведомого), если запрос снят до подтверждения.
+    // В ISR его нет, и EOI не нужен; за ложный IRQ15 ведущий всё же обслужил каскад
+    if (irq == 7 || irq == 15) && !apic::is_enabled() && !interrupts::pic_in_service(irq) {
+        UNHANDLED[irq as usize].fetch_add(1, Ordering::Relaxed);
+        if irq == 15 {
+            interrupts::end_of_interrupt(IRQ_BASE + 2);
+        }
+        return;
+    }
+    let mut handled = false;
+    if let Some(line) = ACTIONS.lock().get(&irq) {
+        // Разделяемая линия: опрашиваем всех, прерывание могли поднять несколько устройств
+        for action in line {
+            handled |= (action.handler)() == IrqReturn::Handled;
+        }
+    }
+    if !handled {
+        UNHANDLED[irq as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    interrupts::end_of_interrupt(IRQ_BASE + irq);
+}
+
+macro_rules! irq_stubs {
+    ($($irq:literal => $stub:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $stub(_stack_frame: InterruptStackFrame) {
+                dispatch($irq);
+            }
+        )*
+
+        /// Ставит общие обработчики на векторы всех линий, кроме таймерной.
+        pub fn install_stubs(idt: &mut InterruptDescriptorTable) {
+            $(
+                if $irq != TIMER_IRQ {
+                    idt[IRQ_BASE + $irq].set_handler_fn($stub);
+                }
+            )*
+        }
+    };
+}
+
+irq_stubs! {
+    0 => irq0, 1 => irq1, 2 => irq2, 3 => irq3, 4 => irq4, 5 => irq5,
+    6 => irq6, 7 => irq7, 8 => irq8, 9 => irq9, 10 => irq10, 11 => irq11,
+    12 => irq12, 13 => irq13, 14 => irq14, 15 => irq15, 16 => irq16, 17 => irq17,
+    18 => irq18, 19 => irq19, 20 => irq20, 21 => irq21, 22 => irq22, 23 => irq23,
+    24 => msi0, 25 => msi1, 26 => msi2, 27 => msi3, 28 => msi4, 29 => msi5, 30 => msi6, 31 => msi7,
+}
+
+pub fn list_irqs() {
+    let names: BTreeMap<u8, Vec<&'static str>> = ACTIONS.lock().iter()
+        .map(|(&irq, line)| (irq, line.iter().map(|a| a.name).collect()))
+        .collect();
+    println!(" IRQ  VECTOR      COUNT  UNHANDLED  HANDLERS");
+    for irq in 0..LINES as u8 {
+        let count = COUNTS[irq as usize].load(Ordering::Relaxed);
+        let unhandled = UNHANDLED[irq as usize].load(Ordering::Relaxed);
+        let handlers = match names.get(&irq) {
+            Some(list) => list.join(", "),
+            None if irq == TIMER_IRQ => alloc::string::String::from("timer"),
+            None if count == 0 => continue,
+            None => alloc::string::String::from("-"),
+        };
+        println!("{:>4}  {:>#6x}  {:>9}  {:>9}  {}", irq, IRQ_BASE + irq, count, unhandled, handlers);
+    }
+    crate::deferred::print_stats();
+}
@@ -0,0 +1,226 @@
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+
+/// Спинлок, который держится с выключенными прерываниями: обработчик
+/// прерывания не может прийти на этот же CPU и попытаться взять тот же замок,
+/// а поток с замком не будет вытеснен. При отпускании прерывания возвращаются
+/// в то состояние, в котором были до захвата.
+pub struct IrqSafeMutex<T> {
+    name: &'static str,
+    #[cfg(debug_assertions)]
+    class: lockdep::LockClass,
+    inner: spin::Mutex<T>,
+}
+
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        IrqSafeMutex {
+            name,
+            #[cfg(debug_assertions)]
+            class: lockdep::LockClass::new(),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T> {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        #[cfg(debug_assertions)]
+        let class = {
+            let class = self.class.id(self.name);
+            lockdep::before_acquire(class, self.name);
+            class
+        };
+        let guard = self.spin_lock();
+        #[cfg(debug_assertions)]
+        lockdep::acquired(class);
+        IrqSafeMutexGuard {
+            lock: self,
+            guard: Some(guard),
+            were_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<'_, T>> {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                #[cfg(debug_assertions)]
+                lockdep::acquired(self.class.id(self.name));
+                Some(IrqSafeMutexGuard { lock: self, guard: Some(guard), were_enabled })
+            }
+            None => {
+                if were_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    /// Снимает замок, не дожидаясь владельца. Только для panic handler'а,
+    /// когда владелец уже никогда его не отпустит.
+    pub unsafe fn force_unlock(&self) {
+        #[cfg(debug_assertions)]
+        lockdep::released(self.class.id(self.name));
+        self.inner.force_unlock();
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn spin_lock(&self) -> spin::MutexGuard<'_, T> {
+        self.inner.lock()
+    }
+
+    #[cfg(debug_assertions)]
+    fn spin_lock(&self) -> spin::MutexGuard<'_, T> {
+        let mut spins: u64 = 0;
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                return guard;
+            }
+            spins += 1;
+            if spins == lockdep::DEADLOCK_SPINS {
+                panic!("lockdep: possible deadlock waiting for {}", self.name);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub struct IrqSafeMutexGuard<'a, T> {
+    lock: &'a IrqSafeMutex<T>,
+    guard: Option<spin::MutexGuard<'a, T>>,
+    were_enabled: bool,
+}
+
+impl<T> Deref for IrqSafeMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for IrqSafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for IrqSafeMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.guard.take();
+        #[cfg(debug_assertions)]
+        lockdep::released(self.lock.class.id(self.lock.name));
+        #[cfg(not(debug_assertions))]
+        let _ = self.lock;
+        if self.were_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Отладочный детектор взаимоблокировок: запоминает, какие замки брались,
+/// пока удерживались другие, и паникует при обратном порядке или повторном захвате.
+#[cfg(debug_assertions)]
+mod lockdep {
+    use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use crate::smp::{cpu_index, MAX_CPUS};
+
+    const MAX_CLASSES: usize = 64;
+    const MAX_HELD: usize = 16;
+    // Класс 0 — "не отслеживается" (кончились номера)
+    const UNTRACKED: usize = 0;
+    pub const DEADLOCK_SPINS: u64 = 100_000_000;
+
+    static NEXT_CLASS: AtomicUsize = AtomicUsize::new(1);
+    static NAMES: spin::Mutex<[&str; MAX_CLASSES]> = spin::Mutex::new([""; MAX_CLASSES]);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_EDGES: AtomicU64 = AtomicU64::new(0);
+    // Бит b в AFTER[a]: замок класса b брали, удерживая замок класса a
+    static AFTER: [AtomicU64; MAX_CLASSES] = [NO_EDGES; MAX_CLASSES];
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicUsize = AtomicUsize::new(UNTRACKED);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE_HELD: [AtomicUsize; MAX_HELD] = [EMPTY; MAX_HELD];
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    // Удерживаемые замки каждого CPU; меняются только с выключенными прерываниями
+    static HELD: [[AtomicUsize; MAX_HELD]; MAX_CPUS] = [NONE_HELD; MAX_CPUS];
+    static DEPTH: [AtomicUsize; MAX_CPUS] = [ZERO; MAX_CPUS];
+
+    pub struct LockClass(AtomicUsize);
+
+    impl LockClass {
+        pub const fn new() -> Self {
+            LockClass(AtomicUsize::new(usize::MAX))
+        }
+
+        pub fn id(&self, name: &'static str) -> usize {
+            let id = self.0.load(Ordering::Relaxed);
+            if id != usize::MAX {
+                return id;
+            }
+            let mut id = NEXT_CLASS.fetch_add(1, Ordering::Relaxed);
+            if id >= MAX_CLASSES {
+                id = UNTRACKED;
+            } else {
+                NAMES.lock()[id] = name;
+            }
+            self.0.store(id, Ordering::Relaxed);
+            id
+        }
+    }
+
+    fn name(class: usize) -> &'static str {
+        NAMES.lock()[class]
+    }
+
+    pub fn before_acquire(class: usize, lock_name: &'static str) {
+        if class == UNTRACKED {
+            return;
+        }
+        let cpu = cpu_index();
+        let depth = DEPTH[cpu].load(Ordering::Relaxed).min(MAX_HELD);
+        for held in HELD[cpu][..depth].iter().map(|h| h.load(Ordering::Relaxed)) {
+            if held == UNTRACKED {
+                continue;
+            }
+            if held == class {
+                panic!("lockdep: recursive locking of {}", lock_name);
+            }
+            if AFTER[class].load(Ordering::Relaxed) & (1 << held) != 0 {
+                panic!("lockdep: lock order inversion: {} taken while holding {}, but earlier {} was taken while holding {}",
+                    lock_name, name(held), name(held), lock_name);
+            }
+            AFTER[held].fetch_or(1 << class, Ordering::Relaxed);
+        }
+    }
+
+    pub fn acquired(class: usize) {
+        let cpu = cpu_index();
+        let depth = DEPTH[cpu].load(Ordering::Relaxed);
+        if depth < MAX_HELD {
+            HELD[cpu][depth].store(class, Ordering::Relaxed);
+        }
+        DEPTH[cpu].store(depth + 1, Ordering::Relaxed);
+    }
+
+    pub fn released(class: usize) {
+        let cpu = cpu_index();
+        let (held, depth_slot) = (&HELD[cpu], &DEPTH[cpu]);
+        let depth = depth_slot.load(Ordering::Relaxed);
+        let tracked = depth.min(MAX_HELD);
+        // Guard'ы не обязаны отпускаться в обратном порядке
+        match (0..tracked).rev().find(|&i| held[i].load(Ordering::Relaxed) == class) {
+            Some(i) => {
+                for j in i..tracked - 1 {
+                    held[j].store(held[j + 1].load(Ordering::Relaxed), Ordering::Relaxed);
+                }
+                depth_slot.store(depth - 1, Ordering::Relaxed);
+            }
+            None if depth > MAX_HELD => depth_slot.store(depth - 1, Ordering::Relaxed),
+            None => {}
+        }
+    }
+}
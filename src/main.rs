@@ -8,17 +8,29 @@ extern crate alloc;
 use core::panic::PanicInfo;
 use bootloader::{BootInfo, entry_point};
 use x86_64::VirtAddr;
-use crate::task::{Task, simple_executor::SimpleExecutor};
+use crate::task::executor::Executor;
 
 mod vga_buffer;
 mod interrupts;
+mod irq;
+mod deferred;
 mod gdt;
 mod memory;
 mod allocator;
 mod task;
 mod fs;
-
-use crate::memory::{BootInfoFrameAllocator, init_offset_page_table};
+mod lock;
+mod cmdline;
+mod acpi;
+mod apic;
+mod power;
+mod thread;
+mod process;
+mod time;
+mod smp;
+mod block;
+mod pci;
+mod virtio;
 
 entry_point!(kernel_main);
 
@@ -26,26 +38,63 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     gdt::init();
     interrupts::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
-    let mut frame_allocator = unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap failed");
-    unsafe { interrupts::PICS.lock().initialize() };
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_map) };
+    memory::with_memory(|m| allocator::init_heap(&mut m.mapper, &mut m.frame_allocator))
+        .expect("Heap failed");
+    smp::init_bsp();
+    deferred::init();
+    let fs_status = fs::init();
+    thread::init();
+    process::init();
+    interrupts::init_pics();
     x86_64::instructions::interrupts::enable();
+    let acpi_status = acpi::init();
+    let apic_status = apic::init();
+    let clock_status = time::init();
+    task::keyboard::init().expect("Keyboard IRQ failed");
+    let pci_devices = pci::init();
+    let (disks, partitions) = block::init();
+    let smp_status = smp::init();
     vga_buffer::clear_screen();
     vga_buffer::draw_header();
 
     println!("\n\n"); 
     println!(" [BOOT]: GDT, IDT, PICS ........................ [ OK ]");
+    match acpi_status {
+        Ok(()) => println!(" [BOOT]: ACPI Tables ........................... [ OK ]"),
+        Err(e) => println!(" [BOOT]: ACPI Tables ........................... [FAIL] {}", e),
+    }
+    match apic_status {
+        Ok(()) => println!(" [BOOT]: Local APIC & IOAPIC ................... [ OK ]"),
+        Err(e) => println!(" [BOOT]: Local APIC & IOAPIC ................... [SKIP] {}, using 8259", e),
+    }
+    match clock_status {
+        Ok(name) => println!(" [BOOT]: Clocksource ........................... [ OK ] {}", name),
+        Err(e) => println!(" [BOOT]: Clocksource ........................... [FAIL] {}", e),
+    }
+    match smp_status {
+        Ok(cpus) => println!(" [BOOT]: SMP Application Processors ............ [ OK ] {} CPUs online", cpus),
+        Err(e) => println!(" [BOOT]: SMP Application Processors ............ [SKIP] {}", e),
+    }
     println!(" [BOOT]: Memory Mapping & Heap (1MB) ........... [ OK ]");
-    println!(" [BOOT]: RamFS (ReadOnly Filesystem) .......... [ OK ]");
+    match fs_status {
+        Ok(entries) => println!(" [BOOT]: VFS, initrd unpacked to / ............. [ OK ] {} entries", entries),
+        Err(e) => println!(" [BOOT]: VFS, initrd unpacked to / ............. [FAIL] {}", e),
+    }
+    println!(" [BOOT]: PCI Bus ............................... [ OK ] {} functions via {}",
+        pci_devices, pci::access_method());
+    println!(" [BOOT]: Block Devices ......................... [ OK ] {} disks, {} partitions",
+        disks, partitions);
     println!(" [BOOT]: Async Executor & Keyboard ............. [ OK ]");
+    println!(" [BOOT]: Preemptive Kernel Threads ............. [ OK ]");
+    println!(" [BOOT]: Process Table ......................... [ OK ]");
     println!("\nWelcome to Tm_Os. Type 'help' to see available commands.");
     println!("---------------------------------------------------------");
-    let mut executor = SimpleExecutor::new();
-    executor.spawn(Task::new(task::keyboard::shell_task()));
+    let mut executor = Executor::new();
+    task::spawn_system("softirq", deferred::run());
+    task::spawn_system("writeback", block::cache::writeback_task());
+    task::spawn_system("shell", task::keyboard::shell_task());
     executor.run();
-
-    loop { x86_64::instructions::hlt(); }
 }
 
 #[alloc_error_handler]
@@ -55,6 +104,9 @@ fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+    // Паника могла случиться, пока WRITER был захвачен
+    unsafe { vga_buffer::WRITER.force_unlock() };
     println!("{}", info);
     loop {
         x86_64::instructions::hlt();
@@ -5,7 +5,10 @@ use x86_64::{
     },
     PhysAddr, VirtAddr,
 };
+use x86_64::structures::paging::mapper::MapToError;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::lock::IrqSafeMutex;
 
 pub unsafe fn init_offset_page_table(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     let level_4_table = active_level_4_table(physical_memory_offset);
@@ -24,9 +27,14 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr
 }
 
+// Первый мегабайт не раздаём: там BIOS и трамполин для запуска AP (см. smp)
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    // Список возвращённых фреймов: в начале каждого лежит адрес следующего (0 — конец)
+    free: Option<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
@@ -34,10 +42,11 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            free: None,
         }
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+    fn usable_addresses(&self) -> impl Iterator<Item = u64> {
         let regions = self.memory_map.iter();
         let usable_regions = regions
             .filter(|r| r.region_type == MemoryRegionType::Usable);
@@ -45,15 +54,147 @@ impl BootInfoFrameAllocator {
         let addr_ranges = usable_regions
             .map(|r| r.range.start_addr()..r.range.end_addr());
         
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+        addr_ranges.flat_map(|r| r.step_by(4096))
+    }
+
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        let frame_addresses = self.usable_addresses().filter(|&addr| addr >= LOW_MEMORY_END);
         frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
     }
-}
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+    // Следующий ещё не выданный фрейм карты памяти, мимо списка свободных
+    fn next_usable(&mut self) -> Option<PhysFrame> {
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
-}
\ No newline at end of file
+
+    // Возвращает `count` фреймов начиная с `first` в список свободных
+    fn release(&mut self, first: PhysFrame, count: usize) {
+        for i in 0..count as u64 {
+            let frame = first + i;
+            let next = self.free.map_or(0, |f| f.start_address().as_u64());
+            unsafe { *phys_to_virt(frame.start_address()).as_mut_ptr::<u64>() = next };
+            self.free = Some(frame);
+        }
+    }
+
+    /// Свободный фрейм ниже 1 МиБ (кроме нулевого); их `allocate_frame` не выдаёт.
+    pub fn low_memory_frame(&self) -> Option<PhysFrame> {
+        self.usable_addresses()
+            .find(|addr| (0x1000..LOW_MEMORY_END).contains(addr))
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// `count` физически смежных фреймов. Фреймы до разрыва возвращаются
+    /// в список свободных и достанутся следующим `allocate_frame`.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        let mut first = self.next_usable()?;
+        let mut run = 1;
+        while run < count {
+            let frame = match self.next_usable() {
+                Some(frame) => frame,
+                None => {
+                    self.release(first, run);
+                    return None;
+                }
+            };
+            if frame == first + run as u64 {
+                run += 1;
+            } else {
+                self.release(first, run);
+                first = frame;
+                run = 1;
+            }
+        }
+        Some(first)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free.take() {
+            let next = unsafe { *phys_to_virt(frame.start_address()).as_ptr::<u64>() };
+            self.free = (next != 0).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+            return Some(frame);
+        }
+        self.next_usable()
+    }
+}
+
+pub struct MemoryManager {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BootInfoFrameAllocator,
+}
+
+static MEMORY: IrqSafeMutex<Option<MemoryManager>> = IrqSafeMutex::new("memory", None);
+static PHYS_MEM_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// Отсюда раздаются виртуальные адреса для MMIO-регионов устройств
+const MMIO_START: u64 = 0x_5555_0000_0000;
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
+pub unsafe fn init(physical_memory_offset: VirtAddr, memory_map: &'static MemoryMap) {
+    PHYS_MEM_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    *MEMORY.lock() = Some(MemoryManager {
+        mapper: init_offset_page_table(physical_memory_offset),
+        frame_allocator: BootInfoFrameAllocator::init(memory_map),
+    });
+}
+
+/// Даёт доступ к таблицам страниц и аллокатору фреймов под замком.
+pub fn with_memory<R>(f: impl FnOnce(&mut MemoryManager) -> R) -> R {
+    f(MEMORY.lock().as_mut().expect("memory not initialized"))
+}
+
+/// Вся физическая память отображена bootloader'ом со смещением.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+/// Обнулённые физически смежные страницы для DMA; доступны через `phys_to_virt`.
+pub fn allocate_dma(pages: usize) -> Option<PhysAddr> {
+    let first = with_memory(|m| m.frame_allocator.allocate_contiguous(pages))?;
+    let phys = first.start_address();
+    unsafe { core::ptr::write_bytes(phys_to_virt(phys).as_mut_ptr::<u8>(), 0, pages * 4096) };
+    Some(phys)
+}
+
+pub fn low_memory_frame() -> Option<PhysFrame> {
+    with_memory(|m| m.frame_allocator.low_memory_frame())
+}
+
+/// Отображает фрейм по виртуальному адресу, равному физическому.
+pub fn identity_map(frame: PhysFrame) -> Result<(), MapToError<Size4KiB>> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    with_memory(|m| unsafe {
+        match m.mapper.identity_map(frame, flags, &mut m.frame_allocator) {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(MapToError::PageAlreadyMapped(existing)) if existing == frame => Ok(()),
+            Err(e) => Err(e),
+        }
+    })
+}
+
+/// Отображает регистры устройства без кэширования и возвращает их виртуальный адрес.
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + size.max(1) - 1u64);
+    let pages = (last.start_address() - first.start_address()) / 4096 + 1;
+    let virt_start = NEXT_MMIO.fetch_add(pages * 4096, Ordering::Relaxed);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    with_memory(|m| {
+        for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+            let page = Page::containing_address(VirtAddr::new(virt_start + i as u64 * 4096));
+            unsafe {
+                m.mapper.map_to(page, frame, flags, &mut m.frame_allocator)?.flush();
+            }
+        }
+        Ok::<(), MapToError<Size4KiB>>(())
+    })?;
+    Ok(VirtAddr::new(virt_start + (phys.as_u64() - first.start_address().as_u64())))
+}
@@ -0,0 +1,142 @@
+//! Доступ к конфигурационному пространству: через ECAM (окно MMIO из ACPI MCFG),
+//! а без него — через порты 0xCF8/0xCFC (только первые 256 байт функции).
+
+use alloc::collections::BTreeMap;
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use spin::Mutex;
+use x86_64::{instructions::{interrupts, port::Port}, PhysAddr, VirtAddr};
+use crate::{acpi, lock::IrqSafeMutex, memory};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+// Байт на шину в окне ECAM: 32 устройства * 8 функций * 4 КиБ
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+/// Шина:устройство.функция в сегменте 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+struct Ecam {
+    base: u64,
+    bus_start: u8,
+    bus_end: u8,
+    // Окна шин отображаются по мере обращения: всё окно может занимать 256 МиБ
+    mapped: Mutex<BTreeMap<u8, VirtAddr>>,
+}
+
+enum Access {
+    Ecam(Ecam),
+    Ports,
+}
+
+static ACCESS: OnceCell<Access> = OnceCell::uninit();
+// Пара CONFIG_ADDRESS/CONFIG_DATA — одна транзакция, её нельзя прерывать
+static PORT_LOCK: IrqSafeMutex<()> = IrqSafeMutex::new("pci_ports", ());
+
+/// Выбирает способ доступа; возвращает его название для загрузочного лога.
+pub fn init() -> &'static str {
+    let ecam = acpi::mcfg()
+        .and_then(|mcfg| mcfg.entries.into_iter().find(|e| e.segment == 0))
+        .map(|e| Ecam { base: e.base, bus_start: e.bus_start, bus_end: e.bus_end, mapped: Mutex::new(BTreeMap::new()) });
+    let access = match ecam {
+        Some(ecam) => Access::Ecam(ecam),
+        None => Access::Ports,
+    };
+    ACCESS.try_init_once(|| access).ok();
+    method()
+}
+
+pub fn method() -> &'static str {
+    match ACCESS.get() {
+        Some(Access::Ecam(_)) => "ECAM",
+        _ => "ports 0xCF8/0xCFC",
+    }
+}
+
+impl Ecam {
+    fn address(&self, addr: PciAddress, offset: u16) -> Option<VirtAddr> {
+        if addr.bus < self.bus_start || addr.bus > self.bus_end {
+            return None;
+        }
+        let bus_base = interrupts::without_interrupts(|| {
+            let mut mapped = self.mapped.lock();
+            if let Some(&base) = mapped.get(&addr.bus) {
+                return Some(base);
+            }
+            let phys = self.base + (addr.bus - self.bus_start) as u64 * ECAM_BUS_SIZE;
+            let base = memory::map_mmio(PhysAddr::new(phys), ECAM_BUS_SIZE).ok()?;
+            mapped.insert(addr.bus, base);
+            Some(base)
+        })?;
+        let function = (addr.device as u64) << 15 | (addr.function as u64) << 12;
+        Some(bus_base + function + offset as u64)
+    }
+}
+
+fn port_address(addr: PciAddress, offset: u16) -> u32 {
+    0x8000_0000 | (addr.bus as u32) << 16 | (addr.device as u32) << 11
+        | (addr.function as u32) << 8 | (offset as u32 & 0xFC)
+}
+
+// Общая часть чтения и записи любой ширины: ECAM — обращением к памяти,
+// иначе — через порт данных со смещением внутри двойного слова
+macro_rules! config_access {
+    ($read:ident, $ty:ty) => {
+        impl PciAddress {
+            pub fn $read(self, offset: u16) -> $ty {
+                match ACCESS.get() {
+                    Some(Access::Ecam(ecam)) => match ecam.address(self, offset) {
+                        Some(virt) => unsafe { core::ptr::read_volatile(virt.as_ptr::<$ty>()) },
+                        None => <$ty>::MAX,
+                    },
+                    _ if offset >= 256 => <$ty>::MAX,
+                    _ => {
+                        let _guard = PORT_LOCK.lock();
+                        unsafe {
+                            Port::<u32>::new(CONFIG_ADDRESS).write(port_address(self, offset));
+                            Port::<$ty>::new(CONFIG_DATA + (offset & 3)).read()
+                        }
+                    }
+                }
+            }
+        }
+    };
+    ($read:ident, $write:ident, $ty:ty) => {
+        config_access!($read, $ty);
+
+        impl PciAddress {
+            pub fn $write(self, offset: u16, value: $ty) {
+                match ACCESS.get() {
+                    Some(Access::Ecam(ecam)) => {
+                        if let Some(virt) = ecam.address(self, offset) {
+                            unsafe { core::ptr::write_volatile(virt.as_mut_ptr::<$ty>(), value) };
+                        }
+                    }
+                    _ if offset >= 256 => {}
+                    _ => {
+                        let _guard = PORT_LOCK.lock();
+                        unsafe {
+                            Port::<u32>::new(CONFIG_ADDRESS).write(port_address(self, offset));
+                            Port::<$ty>::new(CONFIG_DATA + (offset & 3)).write(value);
+                        }
+                    }
+                }
+            }
+        }
+    };
+}
+
+config_access!(read_u8, u8);
+config_access!(read_u16, write_u16, u16);
+config_access!(read_u32, write_u32, u32);
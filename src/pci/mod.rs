@@ -0,0 +1,347 @@
+//! Шина PCI: перебор устройств, разбор BAR и capability, реестр драйверов.
+
+use alloc::{collections::BTreeSet, vec::Vec};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::println;
+
+mod config;
+pub mod msi;
+
+pub use config::PciAddress;
+pub use msi::{MsiCapability, MsixCapability, MsixTable};
+
+// Поля общего заголовка конфигурационного пространства
+pub const VENDOR_ID: u16 = 0x00;
+pub const DEVICE_ID: u16 = 0x02;
+pub const COMMAND: u16 = 0x04;
+pub const STATUS: u16 = 0x06;
+pub const REVISION: u16 = 0x08;
+pub const PROG_IF: u16 = 0x09;
+pub const SUBCLASS: u16 = 0x0A;
+pub const CLASS: u16 = 0x0B;
+pub const HEADER_TYPE: u16 = 0x0E;
+pub const BAR0: u16 = 0x10;
+pub const SECONDARY_BUS: u16 = 0x19;
+pub const CAPABILITIES: u16 = 0x34;
+pub const INTERRUPT_LINE: u16 = 0x3C;
+pub const INTERRUPT_PIN: u16 = 0x3D;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTIFUNCTION: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory { address: u64, size: u64, prefetchable: bool, is_64bit: bool },
+    Io { port: u16, size: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub bars: [Option<Bar>; 6],
+    pub interrupt_line: u8,
+    /// 0 — INTx не используется, 1..4 — INTA#..INTD#.
+    pub interrupt_pin: u8,
+    /// Все capability: (id, смещение).
+    pub capabilities: Vec<(u8, u16)>,
+    pub msi: Option<MsiCapability>,
+    pub msix: Option<MsixCapability>,
+}
+
+impl PciDevice {
+    fn probe(address: PciAddress) -> Option<PciDevice> {
+        let vendor_id = address.read_u16(VENDOR_ID);
+        if vendor_id == 0xFFFF {
+            return None;
+        }
+        let header_type = address.read_u8(HEADER_TYPE);
+        let capabilities = read_capabilities(address);
+        let find = |id| capabilities.iter().find(|&&(cap, _)| cap == id).map(|&(_, offset)| offset);
+        let msi = find(msi::CAP_MSI).map(|offset| MsiCapability::parse(address, offset));
+        let msix = find(msi::CAP_MSIX).map(|offset| MsixCapability::parse(address, offset));
+        Some(PciDevice {
+            address,
+            vendor_id,
+            device_id: address.read_u16(DEVICE_ID),
+            class: address.read_u8(CLASS),
+            subclass: address.read_u8(SUBCLASS),
+            prog_if: address.read_u8(PROG_IF),
+            revision: address.read_u8(REVISION),
+            header_type,
+            bars: read_bars(address, header_type & !HEADER_MULTIFUNCTION),
+            interrupt_line: address.read_u8(INTERRUPT_LINE),
+            interrupt_pin: address.read_u8(INTERRUPT_PIN),
+            capabilities,
+            msi,
+            msix,
+        })
+    }
+
+    /// Смещения всех capability с данным id (например, у virtio их несколько).
+    pub fn capabilities_of(&self, id: u8) -> impl Iterator<Item = u16> + '_ {
+        self.capabilities.iter().filter(move |&&(cap, _)| cap == id).map(|&(_, offset)| offset)
+    }
+
+    /// Включает декодирование адресов BAR и захват шины (DMA).
+    pub fn enable(&self) {
+        let command = self.address.read_u16(COMMAND);
+        self.address.write_u16(COMMAND, command | COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+fn read_capabilities(address: PciAddress) -> Vec<(u8, u16)> {
+    let mut capabilities = Vec::new();
+    if address.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+    let mut offset = (address.read_u8(CAPABILITIES) & 0xFC) as u16;
+    // Ограничение на случай зацикленного списка
+    while offset != 0 && capabilities.len() < 48 {
+        capabilities.push((address.read_u8(offset), offset));
+        offset = (address.read_u8(offset + 1) & 0xFC) as u16;
+    }
+    capabilities
+}
+
+// Размер BAR: пишем все единицы и смотрим, какие биты адреса остались нулями.
+// На время замера декодирование выключено, чтобы устройство не появилось по чужому адресу.
+fn read_bars(address: PciAddress, header_type: u8) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let count = match header_type {
+        0 => 6,
+        1 => 2,
+        _ => return bars,
+    };
+    let command = address.read_u16(COMMAND);
+    address.write_u16(COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+    let mut i = 0;
+    while i < count {
+        let offset = BAR0 + i as u16 * 4;
+        let raw = address.read_u32(offset);
+        address.write_u32(offset, 0xFFFF_FFFF);
+        let mask = address.read_u32(offset);
+        address.write_u32(offset, raw);
+        if raw & 1 == 1 {
+            let size = (!(mask & !0b11)).wrapping_add(1) & 0xFFFF;
+            if size != 0 {
+                bars[i] = Some(Bar::Io { port: (raw & !0b11) as u16, size });
+            }
+            i += 1;
+            continue;
+        }
+        let is_64bit = (raw >> 1) & 0b11 == 0b10 && i + 1 < count;
+        let (address_value, mask) = if is_64bit {
+            let high_offset = offset + 4;
+            let high = address.read_u32(high_offset);
+            address.write_u32(high_offset, 0xFFFF_FFFF);
+            let high_mask = address.read_u32(high_offset);
+            address.write_u32(high_offset, high);
+            ((high as u64) << 32 | (raw & !0xF) as u64, (high_mask as u64) << 32 | (mask & !0xF) as u64)
+        } else {
+            ((raw & !0xF) as u64, 0xFFFF_FFFF_0000_0000 | (mask & !0xF) as u64)
+        };
+        let size = (!mask).wrapping_add(1);
+        if mask & 0xFFFF_FFFF != 0 && size != 0 {
+            bars[i] = Some(Bar::Memory { address: address_value, size, prefetchable: raw & (1 << 3) != 0, is_64bit });
+        }
+        i += if is_64bit { 2 } else { 1 };
+    }
+    address.write_u16(COMMAND, command);
+    bars
+}
+
+/// Какие устройства берёт драйвер.
+#[derive(Debug, Clone, Copy)]
+pub enum PciMatch {
+    Device { vendor: u16, device: u16 },
+    Class { class: u8, subclass: u8 },
+}
+
+impl PciMatch {
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        match *self {
+            PciMatch::Device { vendor, device: id } => device.vendor_id == vendor && device.device_id == id,
+            PciMatch::Class { class, subclass } => device.class == class && device.subclass == subclass,
+        }
+    }
+}
+
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+    /// Вызывается для каждого подходящего свободного устройства; при ошибке устройство остаётся свободным.
+    pub probe: fn(&PciDevice) -> Result<(), &'static str>,
+}
+
+struct Slot {
+    device: PciDevice,
+    driver: Option<&'static str>,
+}
+
+static DEVICES: Mutex<Vec<Slot>> = Mutex::new(Vec::new());
+static DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+
+/// Перебирает шины и привязывает уже зарегистрированные драйверы; возвращает число функций.
+pub fn init() -> usize {
+    config::init();
+    let mut found = Vec::new();
+    let root = PciAddress { bus: 0, device: 0, function: 0 };
+    if root.read_u8(HEADER_TYPE) & HEADER_MULTIFUNCTION == 0 {
+        scan_bus(0, &mut found, &mut BTreeSet::new());
+    } else {
+        // Несколько хост-мостов: функция N моста 00:00 обслуживает шину N
+        let mut visited = BTreeSet::new();
+        for function in 0..8 {
+            let host = PciAddress { function, ..root };
+            if host.read_u16(VENDOR_ID) != 0xFFFF {
+                scan_bus(function, &mut found, &mut visited);
+            }
+        }
+    }
+    let count = found.len();
+    interrupts::without_interrupts(|| {
+        *DEVICES.lock() = found.into_iter().map(|device| Slot { device, driver: None }).collect();
+    });
+    bind_drivers();
+    count
+}
+
+fn scan_bus(bus: u8, found: &mut Vec<PciDevice>, visited: &mut BTreeSet<u8>) {
+    if !visited.insert(bus) {
+        return;
+    }
+    for device in 0..32 {
+        let first = PciAddress { bus, device, function: 0 };
+        if first.read_u16(VENDOR_ID) == 0xFFFF {
+            continue;
+        }
+        let functions = if first.read_u8(HEADER_TYPE) & HEADER_MULTIFUNCTION != 0 { 8 } else { 1 };
+        for function in 0..functions {
+            if let Some(dev) = PciDevice::probe(PciAddress { function, ..first }) {
+                // PCI-to-PCI мост: за ним своя шина
+                let secondary = (dev.class == 0x06 && dev.subclass == 0x04)
+                    .then(|| dev.address.read_u8(SECONDARY_BUS));
+                found.push(dev);
+                if let Some(secondary) = secondary {
+                    scan_bus(secondary, found, visited);
+                }
+            }
+        }
+    }
+}
+
+/// Регистрирует драйвер и сразу предлагает ему уже найденные устройства.
+pub fn register_driver(driver: &'static PciDriver) {
+    interrupts::without_interrupts(|| DRIVERS.lock().push(driver));
+    bind_drivers();
+}
+
+fn bind_drivers() {
+    let drivers: Vec<&'static PciDriver> = interrupts::without_interrupts(|| DRIVERS.lock().clone());
+    let unbound: Vec<PciDevice> = interrupts::without_interrupts(|| {
+        DEVICES.lock().iter().filter(|s| s.driver.is_none()).map(|s| s.device.clone()).collect()
+    });
+    for device in unbound {
+        for driver in drivers.iter().filter(|d| d.matches.iter().any(|m| m.matches(&device))) {
+            // probe может надолго занять устройство — вызываем без замка
+            match (driver.probe)(&device) {
+                Ok(()) => {
+                    interrupts::without_interrupts(|| {
+                        if let Some(slot) = DEVICES.lock().iter_mut().find(|s| s.device.address == device.address) {
+                            slot.driver = Some(driver.name);
+                        }
+                    });
+                    break;
+                }
+                Err(e) => println!("pci {}: {}: {}", device.address, driver.name, e),
+            }
+        }
+    }
+}
+
+pub fn devices() -> Vec<PciDevice> {
+    interrupts::without_interrupts(|| DEVICES.lock().iter().map(|s| s.device.clone()).collect())
+}
+
+pub fn access_method() -> &'static str {
+    config::method()
+}
+
+fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "Storage controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus controller",
+        (0x0C, _) => "Serial bus controller",
+        (0x0D, _) => "Wireless controller",
+        (0xFF, _) => "Unassigned class",
+        _ => "Unknown device",
+    }
+}
+
+/// Вывод для команды `lspci [-v]`.
+pub fn list_devices(verbose: bool) {
+    let slots: Vec<(PciDevice, Option<&'static str>)> = interrupts::without_interrupts(|| {
+        DEVICES.lock().iter().map(|s| (s.device.clone(), s.driver)).collect()
+    });
+    for (dev, driver) in slots {
+        println!("{} {:04x}:{:04x} [{:02x}{:02x}] {} (rev {:02x}){}",
+            dev.address, dev.vendor_id, dev.device_id, dev.class, dev.subclass,
+            dev.class_name(), dev.revision, driver.map_or(alloc::string::String::new(), |d| alloc::format!(" -> {}", d)));
+        if !verbose {
+            continue;
+        }
+        println!("    prog-if {:02x}, header type {:02x}{}", dev.prog_if, dev.header_type & 0x7F,
+            if dev.header_type & HEADER_MULTIFUNCTION != 0 { ", multifunction" } else { "" });
+        for (i, bar) in dev.bars.iter().enumerate() {
+            match bar {
+                Some(Bar::Memory { address, size, prefetchable, is_64bit }) => println!("    BAR{}: mem {:#x} size {:#x}{}{}",
+                    i, address, size, if *is_64bit { " 64-bit" } else { "" }, if *prefetchable { " prefetchable" } else { "" }),
+                Some(Bar::Io { port, size }) => println!("    BAR{}: io {:#x} size {:#x}", i, port, size),
+                None => {}
+            }
+        }
+        if dev.interrupt_pin != 0 {
+            println!("    INT{}# -> IRQ {}", (b'A' + dev.interrupt_pin - 1) as char, dev.interrupt_line);
+        }
+        if let Some(msi) = dev.msi {
+            println!("    MSI: {} vectors{}{}", msi.max_vectors,
+                if msi.is_64bit { ", 64-bit" } else { "" }, if msi.per_vector_masking { ", maskable" } else { "" });
+        }
+        if let Some(msix) = dev.msix {
+            println!("    MSI-X: {} entries, table BAR{}+{:#x}, PBA BAR{}+{:#x}",
+                msix.table_size, msix.table_bar, msix.table_offset, msix.pba_bar, msix.pba_offset);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Разбор capability MSI и MSI-X и их настройка на доставку в local APIC BSP.
+
+use x86_64::{PhysAddr, VirtAddr};
+use super::{Bar, PciAddress, PciDevice, COMMAND, COMMAND_INTX_DISABLE};
+use crate::{memory, smp};
+
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_MSIX: u8 = 0x11;
+
+// Адрес сообщения: окно local APIC, в битах 12..20 — APIC id получателя
+const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_ENABLE: u16 = 0b111 << 4;
+const MSI_64BIT: u16 = 1 << 7;
+const MSI_PER_VECTOR_MASK: u16 = 1 << 8;
+
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_VECTOR_MASKED: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MsiCapability {
+    /// Смещение capability в конфигурационном пространстве.
+    pub offset: u16,
+    pub is_64bit: bool,
+    pub per_vector_masking: bool,
+    /// Сколько векторов функция умеет запрашивать.
+    pub max_vectors: u8,
+}
+
+impl MsiCapability {
+    pub fn parse(addr: PciAddress, offset: u16) -> Self {
+        let control = addr.read_u16(offset + 2);
+        MsiCapability {
+            offset,
+            is_64bit: control & MSI_64BIT != 0,
+            per_vector_masking: control & MSI_PER_VECTOR_MASK != 0,
+            max_vectors: 1 << ((control >> 1) & 0b111),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MsixCapability {
+    pub offset: u16,
+    pub table_size: u16,
+    /// Номер BAR и смещение в нём таблицы векторов и битовой карты ожидающих.
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+impl MsixCapability {
+    pub fn parse(addr: PciAddress, offset: u16) -> Self {
+        let control = addr.read_u16(offset + 2);
+        let table = addr.read_u32(offset + 4);
+        let pba = addr.read_u32(offset + 8);
+        MsixCapability {
+            offset,
+            table_size: (control & 0x7FF) + 1,
+            table_bar: (table & 0b111) as u8,
+            table_offset: table & !0b111,
+            pba_bar: (pba & 0b111) as u8,
+            pba_offset: pba & !0b111,
+        }
+    }
+}
+
+fn message_address() -> u32 {
+    MSI_ADDRESS_BASE | (smp::boot_apic_id() as u32) << 12
+}
+
+/// Таблица векторов MSI-X, отображённая в память ядра.
+pub struct MsixTable {
+    base: VirtAddr,
+    size: u16,
+}
+
+impl MsixTable {
+    /// Направляет запись `entry` на `vector` и снимает с неё маску.
+    pub fn set_vector(&self, entry: u16, vector: u8) -> Result<(), &'static str> {
+        if entry >= self.size {
+            return Err("no such MSI-X entry");
+        }
+        let ptr = (self.base + entry as u64 * MSIX_ENTRY_SIZE).as_mut_ptr::<u32>();
+        unsafe {
+            ptr.write_volatile(message_address());
+            ptr.add(1).write_volatile(0);
+            ptr.add(2).write_volatile(vector as u32);
+            ptr.add(3).write_volatile(ptr.add(3).read_volatile() & !MSIX_VECTOR_MASKED);
+        }
+        Ok(())
+    }
+}
+
+impl PciDevice {
+    /// Включает MSI с одним вектором и выключает INTx.
+    pub fn enable_msi(&self, vector: u8) -> Result<(), &'static str> {
+        let cap = self.msi.ok_or("device has no MSI capability")?;
+        let a = self.address;
+        a.write_u32(cap.offset + 4, message_address());
+        let data_offset = if cap.is_64bit {
+            a.write_u32(cap.offset + 8, 0);
+            cap.offset + 12
+        } else {
+            cap.offset + 8
+        };
+        a.write_u16(data_offset, vector as u16);
+        let control = a.read_u16(cap.offset + 2);
+        a.write_u16(cap.offset + 2, (control & !MSI_MULTIPLE_ENABLE) | MSI_ENABLE);
+        a.write_u16(COMMAND, a.read_u16(COMMAND) | COMMAND_INTX_DISABLE);
+        Ok(())
+    }
+
+    /// Отображает таблицу MSI-X; все записи остаются замаскированы до `set_vector`.
+    pub fn map_msix_table(&self) -> Result<MsixTable, &'static str> {
+        let cap = self.msix.ok_or("device has no MSI-X capability")?;
+        let bar = match self.bars.get(cap.table_bar as usize).copied().flatten() {
+            Some(Bar::Memory { address, .. }) => address,
+            _ => return Err("MSI-X table BAR is not a memory BAR"),
+        };
+        let size = cap.table_size as u64 * MSIX_ENTRY_SIZE;
+        let base = memory::map_mmio(PhysAddr::new(bar + cap.table_offset as u64), size)
+            .map_err(|_| "cannot map MSI-X table")?;
+        Ok(MsixTable { base, size: cap.table_size })
+    }
+
+    /// Включает MSI-X (вместе с INTx выключается и обычный MSI).
+    pub fn enable_msix(&self) -> Result<(), &'static str> {
+        let cap = self.msix.ok_or("device has no MSI-X capability")?;
+        let a = self.address;
+        if let Some(msi) = self.msi {
+            a.write_u16(msi.offset + 2, a.read_u16(msi.offset + 2) & !MSI_ENABLE);
+        }
+        let control = a.read_u16(cap.offset + 2);
+        a.write_u16(cap.offset + 2, (control & !MSIX_FUNCTION_MASK) | MSIX_ENABLE);
+        a.write_u16(COMMAND, a.read_u16(COMMAND) | COMMAND_INTX_DISABLE);
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use x86_64::instructions::{hlt, interrupts, port::Port};
+use x86_64::{structures::DescriptorTablePointer, PhysAddr, VirtAddr};
+use crate::acpi::{self, GenericAddress};
+
+const SLP_EN: u16 = 1 << 13;
+const SCI_EN: u16 = 1;
+
+/// Выключение: ACPI S5 через PM1a/PM1b_CNT, затем порты эмуляторов.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    if let Err(e) = acpi_shutdown() {
+        crate::println!("ACPI shutdown failed: {}, trying emulator ports", e);
+    }
+    unsafe {
+        Port::<u16>::new(0x604).write(0x2000); // QEMU
+        Port::<u16>::new(0xB004).write(0x2000); // Bochs и старые QEMU
+        Port::<u16>::new(0x4004).write(0x3400); // VirtualBox
+    }
+    crate::println!("It is now safe to turn off your computer.");
+    loop {
+        hlt();
+    }
+}
+
+fn acpi_shutdown() -> Result<(), &'static str> {
+    let fadt = acpi::fadt().ok_or("no FADT")?;
+    let dsdt = acpi::dsdt().ok_or("no DSDT")?;
+    let (slp_typ_a, slp_typ_b) = acpi::dsdt::s5_sleep_types(&dsdt).ok_or("no \\_S5 object")?;
+    if fadt.pm1a_control_block == 0 {
+        return Err("no PM1a control block");
+    }
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control_block as u16);
+    unsafe {
+        // Переводим чипсет в режим ACPI, если прошивка этого ещё не сделала
+        if pm1a.read() & SCI_EN == 0 && fadt.smi_command != 0 && fadt.acpi_enable != 0 {
+            Port::<u8>::new(fadt.smi_command as u16).write(fadt.acpi_enable);
+            let mut spins = 0;
+            while pm1a.read() & SCI_EN == 0 && spins < 1_000_000 {
+                spins += 1;
+            }
+        }
+        pm1a.write(((slp_typ_a as u16) << 10) | SLP_EN);
+        if fadt.pm1b_control_block != 0 {
+            Port::<u16>::new(fadt.pm1b_control_block as u16).write(((slp_typ_b as u16) << 10) | SLP_EN);
+        }
+    }
+    // Если мы всё ещё здесь, выключение не сработало
+    for _ in 0..1_000_000 {
+        core::hint::spin_loop();
+    }
+    Err("PM1 write had no effect")
+}
+
+/// Перезагрузка: регистр сброса ACPI, импульс 8042, и в крайнем случае тройной отказ.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    acpi_reset();
+    keyboard_controller_reset();
+    triple_fault();
+}
+
+fn acpi_reset() {
+    let fadt = match acpi::fadt() {
+        Some(fadt) => fadt,
+        None => return,
+    };
+    let reg = match fadt.reset_register {
+        Some(reg) => reg,
+        None => return,
+    };
+    match reg.space {
+        GenericAddress::SYSTEM_IO => unsafe { Port::<u8>::new(reg.address as u16).write(fadt.reset_value) },
+        GenericAddress::SYSTEM_MEMORY => {
+            let ptr = crate::memory::phys_to_virt(PhysAddr::new(reg.address)).as_mut_ptr::<u8>();
+            unsafe { ptr.write_volatile(fadt.reset_value) };
+        }
+        _ => return,
+    }
+    for _ in 0..1_000_000 {
+        core::hint::spin_loop();
+    }
+}
+
+fn keyboard_controller_reset() {
+    let mut status = Port::<u8>::new(0x64);
+    unsafe {
+        // Ждём, пока освободится входной буфер контроллера
+        for _ in 0..100_000 {
+            if status.read() & 0b10 == 0 {
+                break;
+            }
+        }
+        status.write(0xFE);
+    }
+    for _ in 0..1_000_000 {
+        core::hint::spin_loop();
+    }
+}
+
+fn triple_fault() -> ! {
+    let empty = DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) };
+    unsafe {
+        x86_64::instructions::tables::lidt(&empty);
+    }
+    x86_64::instructions::interrupts::int3();
+    loop {
+        hlt();
+    }
+}
@@ -0,0 +1,294 @@
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::{instructions::interrupts, registers::control::Cr3, structures::paging::PhysFrame};
+use crate::{fs::{self, FsError, FsResult, OpenFile, OpenFlags, SeekFrom}, println, thread::{self, ThreadId}};
+
+/// Код выхода процесса, убитого через `kill`.
+pub const KILLED_EXIT_CODE: i32 = -9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    pub const KERNEL: Pid = Pid(0);
+
+    fn new() -> Self {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_u64(pid: u64) -> Self { Pid(pid) }
+
+    pub fn as_u64(self) -> u64 { self.0 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    Zombie(i32),
+}
+
+pub type Fd = usize;
+
+pub struct Process {
+    pid: Pid,
+    parent: Pid,
+    name: &'static str,
+    state: ProcessState,
+    // Пока все процессы живут в адресном пространстве ядра
+    address_space: PhysFrame,
+    threads: Vec<ThreadId>,
+    files: Vec<Option<Arc<OpenFile>>>,
+    waiting_tasks: Vec<Waker>,
+}
+
+impl Process {
+    fn new(pid: Pid, parent: Pid, name: &'static str) -> Self {
+        Process {
+            pid,
+            parent,
+            name,
+            state: ProcessState::Running,
+            address_space: Cr3::read().0,
+            threads: Vec::new(),
+            files: Vec::new(),
+            waiting_tasks: Vec::new(),
+        }
+    }
+
+    fn has_waiters(&self) -> bool {
+        !self.waiting_tasks.is_empty()
+    }
+}
+
+lazy_static! {
+    static ref PROCESSES: Mutex<BTreeMap<Pid, Process>> = {
+        let mut table = BTreeMap::new();
+        table.insert(Pid::KERNEL, Process::new(Pid::KERNEL, Pid::KERNEL, "kernel"));
+        Mutex::new(table)
+    };
+}
+
+pub fn init() {
+    lazy_static::initialize(&PROCESSES);
+}
+
+/// Запускает процесс с одним потоком; значение, которое вернёт `f`, станет кодом выхода.
+pub fn spawn<F>(name: &'static str, f: F) -> Pid
+where
+    F: FnOnce() -> i32 + Send + 'static,
+{
+    let pid = Pid::new();
+    let parent = current_pid();
+    // Ниже прерывания выключены, и thread::spawn не сможет убрать старые потоки сам
+    thread::reap();
+    interrupts::without_interrupts(|| {
+        let mut table = PROCESSES.lock();
+        reap_orphans(&mut table);
+        table.insert(pid, Process::new(pid, parent, name));
+        // Прерывания выключены: поток не запустится, пока мы не запишем его в процесс
+        let tid = thread::spawn(name, move || {
+            let code = f();
+            exit_process(pid, code);
+        });
+        table.get_mut(&pid).unwrap().threads.push(tid);
+    });
+    pid
+}
+
+pub fn current_pid() -> Pid {
+    // На AP потоков нет: там выполняется только ядро
+    let tid = match thread::current_id() {
+        Some(tid) => tid,
+        None => return Pid::KERNEL,
+    };
+    interrupts::without_interrupts(|| {
+        PROCESSES.lock().values()
+            .find(|p| p.threads.contains(&tid))
+            .map_or(Pid::KERNEL, |p| p.pid)
+    })
+}
+
+/// Завершает текущий процесс с кодом `code`.
+pub fn exit(code: i32) -> ! {
+    exit_process(current_pid(), code)
+}
+
+fn exit_process(pid: Pid, code: i32) -> ! {
+    terminate(pid, code);
+    thread::exit();
+}
+
+/// Убивает процесс: он сразу становится зомби с кодом `KILLED_EXIT_CODE`,
+/// а его потоки завершаются сами в ближайшей безопасной точке (см. `thread::kill`).
+pub fn kill(pid: Pid) -> Result<(), &'static str> {
+    if pid == Pid::KERNEL {
+        return Err("cannot kill the kernel");
+    }
+    let own_thread = thread::current_id();
+    let is_self = interrupts::without_interrupts(|| {
+        match PROCESSES.lock().get(&pid) {
+            Some(p) if p.state == ProcessState::Running => Ok(own_thread.is_some_and(|t| p.threads.contains(&t))),
+            _ => Err("no such process"),
+        }
+    })?;
+    if is_self {
+        exit_process(pid, KILLED_EXIT_CODE);
+    }
+    terminate(pid, KILLED_EXIT_CODE);
+    Ok(())
+}
+
+fn terminate(pid: Pid, code: i32) {
+    let own_thread = thread::current_id();
+    let (files, threads, waiting_tasks) = interrupts::without_interrupts(|| {
+        let mut table = PROCESSES.lock();
+        for child in table.values_mut().filter(|p| p.parent == pid) {
+            child.parent = Pid::KERNEL;
+        }
+        let process = match table.get_mut(&pid) {
+            Some(p) if p.state == ProcessState::Running => p,
+            _ => return (Vec::new(), Vec::new(), Vec::new()),
+        };
+        process.state = ProcessState::Zombie(code);
+        (
+            core::mem::take(&mut process.files),
+            core::mem::take(&mut process.threads),
+            core::mem::take(&mut process.waiting_tasks),
+        )
+    });
+    drop(files);
+    for tid in threads.into_iter().filter(|&t| Some(t) != own_thread) {
+        thread::kill(tid);
+    }
+    for waker in waiting_tasks {
+        waker.wake();
+    }
+}
+
+/// Ядро (PID 0) играет роль init: усыновлённых зомби, которых никто не ждёт, убираем сами.
+fn reap_orphans(table: &mut BTreeMap<Pid, Process>) {
+    table.retain(|_, p| {
+        !(matches!(p.state, ProcessState::Zombie(_)) && p.parent == Pid::KERNEL && !p.has_waiters())
+    });
+}
+
+/// Забирает код выхода зомби и удаляет его из таблицы.
+fn try_reap(table: &mut BTreeMap<Pid, Process>, pid: Pid) -> Option<Result<i32, &'static str>> {
+    match table.get(&pid).map(|p| p.state) {
+        None => Some(Err("no such process")),
+        Some(ProcessState::Zombie(code)) => {
+            table.remove(&pid);
+            Some(Ok(code))
+        }
+        Some(ProcessState::Running) => None,
+    }
+}
+
+/// Ждёт завершения процесса `pid` и возвращает его код выхода, убирая зомби.
+pub fn wait_async(pid: Pid) -> WaitFuture {
+    WaitFuture { pid }
+}
+
+pub struct WaitFuture {
+    pid: Pid,
+}
+
+impl Future for WaitFuture {
+    type Output = Result<i32, &'static str>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        interrupts::without_interrupts(|| {
+            let mut table = PROCESSES.lock();
+            match try_reap(&mut table, self.pid) {
+                Some(result) => Poll::Ready(result),
+                None => {
+                    table.get_mut(&self.pid).unwrap().waiting_tasks.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+/// Открывает файл и кладёт его в наименьший свободный дескриптор текущего процесса.
+pub fn open(path: &str, flags: OpenFlags) -> FsResult<Fd> {
+    let file = fs::open(path, flags)?;
+    let pid = current_pid();
+    interrupts::without_interrupts(|| {
+        let mut table = PROCESSES.lock();
+        let files = &mut table.get_mut(&pid).ok_or(FsError::BadDescriptor)?.files;
+        match files.iter().position(|f| f.is_none()) {
+            Some(fd) => {
+                files[fd] = Some(file);
+                Ok(fd)
+            }
+            None => {
+                files.push(Some(file));
+                Ok(files.len() - 1)
+            }
+        }
+    })
+}
+
+pub fn file(fd: Fd) -> FsResult<Arc<OpenFile>> {
+    let pid = current_pid();
+    interrupts::without_interrupts(|| {
+        PROCESSES.lock().get(&pid)
+            .and_then(|p| p.files.get(fd).cloned().flatten())
+            .ok_or(FsError::BadDescriptor)
+    })
+}
+
+// Сами операции идут без замка таблицы процессов: у файла своя позиция под замком
+pub fn read(fd: Fd, buf: &mut [u8]) -> FsResult<usize> {
+    file(fd)?.read(buf)
+}
+
+pub fn write(fd: Fd, buf: &[u8]) -> FsResult<usize> {
+    file(fd)?.write(buf)
+}
+
+/// Сдвигает позицию файла; возвращает новую позицию от начала.
+pub fn seek(fd: Fd, pos: SeekFrom) -> FsResult<u64> {
+    file(fd)?.seek(pos)
+}
+
+pub fn close(fd: Fd) -> FsResult<()> {
+    let pid = current_pid();
+    let file = interrupts::without_interrupts(|| {
+        PROCESSES.lock().get_mut(&pid)
+            .and_then(|p| p.files.get_mut(fd))
+            .and_then(|slot| slot.take())
+    });
+    // Последняя ссылка на открытый файл освобождается вне замка
+    file.map(drop).ok_or(FsError::BadDescriptor)
+}
+
+pub fn list_processes() {
+    let rows: Vec<(Pid, Pid, &'static str, ProcessState, usize, usize, u64)> =
+        interrupts::without_interrupts(|| {
+            PROCESSES.lock().values()
+                .map(|p| (
+                    p.pid,
+                    p.parent,
+                    p.name,
+                    p.state,
+                    p.threads.len(),
+                    p.files.iter().filter(|f| f.is_some()).count(),
+                    p.address_space.start_address().as_u64(),
+                ))
+                .collect()
+        });
+    println!(" PID  PPID  NAME         STATE       THR  FDS  CR3");
+    for (pid, parent, name, state, threads, files, cr3) in rows {
+        let state = match state {
+            ProcessState::Running => alloc::string::String::from("running"),
+            ProcessState::Zombie(code) => alloc::format!("zombie({})", code),
+        };
+        println!("{:>4}  {:>4}  {:<12} {:<11} {:>3}  {:>3}  {:#x}",
+            pid.as_u64(), parent.as_u64(), name, state, threads, files, cr3);
+    }
+}
@@ -0,0 +1,117 @@
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+use crate::{acpi, apic, cmdline, gdt::CpuTables, lock::IrqSafeMutex, println, time};
+use crate::task::executor::{self, Executor};
+
+pub mod percpu;
+mod trampoline;
+
+pub use percpu::{cpu_index, PerCpu};
+use trampoline::Trampoline;
+
+pub const MAX_CPUS: usize = 16;
+const AP_STACK_SIZE: usize = 4096 * 4;
+const AP_STARTUP_TIMEOUT_US: u64 = 100_000;
+
+static CPUS: IrqSafeMutex<Vec<&'static PerCpu>> = IrqSafeMutex::new("cpus", Vec::new());
+// Процессоры в работе имеют номера 0..ONLINE
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Данные загрузочного процессора; вызывается сразу после инициализации кучи.
+pub fn init_bsp() {
+    let cpu = PerCpu::new(0, percpu::initial_apic_id());
+    cpu.set_online();
+    unsafe { percpu::install(cpu) };
+    CPUS.lock().push(cpu);
+    ONLINE.store(1, Ordering::Release);
+}
+
+/// Будит все AP из MADT по INIT-SIPI-SIPI. Возвращает число процессоров в работе.
+pub fn init() -> Result<usize, &'static str> {
+    if cmdline::has_flag("nosmp") {
+        return Err("disabled by nosmp");
+    }
+    if !apic::is_enabled() {
+        return Err("no local APIC");
+    }
+    let madt = acpi::madt()?;
+    let bsp_id = apic::local_apic_id().unwrap_or(0);
+    let trampoline = Trampoline::install()?;
+    for entry in madt.local_apics.iter().filter(|l| l.enabled && l.apic_id != bsp_id) {
+        if online_count() >= MAX_CPUS {
+            break;
+        }
+        start_ap(&trampoline, entry.apic_id);
+    }
+    Ok(online_count())
+}
+
+fn start_ap(trampoline: &Trampoline, apic_id: u8) -> bool {
+    let index = CPUS.lock().len();
+    let cpu = PerCpu::new(index, apic_id);
+    cpu.tables = Some(CpuTables::allocate());
+    cpu.idt = Some(crate::interrupts::allocate_idt());
+    let cpu: &'static PerCpu = cpu;
+    let stack = vec![0u8; AP_STACK_SIZE].leak();
+    let stack_top = stack.as_ptr() as u64 + AP_STACK_SIZE as u64;
+    trampoline.set_entry(ap_main, cpu as *const PerCpu as u64, stack_top);
+
+    // Задержки по спецификации MP: 10 мс после INIT, до двух SIPI
+    apic::send_init(apic_id);
+    time::delay_us(10_000);
+    for _ in 0..2 {
+        apic::send_startup(apic_id, trampoline.startup_vector());
+        let start = time::now();
+        while !cpu.is_online() && time::now() - start < AP_STARTUP_TIMEOUT_US * 1000 {
+            core::hint::spin_loop();
+        }
+        if cpu.is_online() {
+            CPUS.lock().push(cpu);
+            ONLINE.fetch_add(1, Ordering::Release);
+            return true;
+        }
+    }
+    false
+}
+
+// Сюда AP приходит из трамполина: уже в long mode, на своём стеке, с таблицами страниц BSP
+extern "C" fn ap_main(cpu: u64) -> ! {
+    let cpu = unsafe { &*(cpu as *const PerCpu) };
+    unsafe { percpu::install(cpu) };
+    cpu.tables.unwrap().load();
+    cpu.idt.unwrap().load();
+    apic::init_ap();
+    interrupts::enable();
+    cpu.set_online();
+    Executor::new().run();
+}
+
+/// APIC ID загрузочного процессора: на него направляются внешние прерывания.
+pub fn boot_apic_id() -> u8 {
+    CPUS.lock().first().map_or(0, |cpu| cpu.apic_id())
+}
+
+pub fn online_count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Будит процессор `index` из `hlt` межпроцессорным прерыванием.
+pub fn wake_cpu(index: usize) {
+    let apic_id = CPUS.lock().get(index).map(|cpu| cpu.apic_id());
+    if let Some(apic_id) = apic_id {
+        apic::send_ipi(apic_id, apic::WAKEUP_VECTOR);
+    }
+}
+
+pub fn list_cpus() {
+    let cpus: Vec<&'static PerCpu> = CPUS.lock().clone();
+    println!(" CPU  APIC  ROLE  STATE    QUEUED  POLLS");
+    for cpu in cpus {
+        let (queued, polls) = executor::cpu_stats(cpu.index());
+        println!("{:>4}  {:>4}  {:<4}  {:<7}  {:>6}  {}", cpu.index(), cpu.apic_id(),
+            if cpu.index() == 0 { "BSP" } else { "AP" },
+            if cpu.is_online() { "online" } else { "offline" }, queued, polls);
+    }
+    println!("Current CPU: {}", cpu_index());
+}
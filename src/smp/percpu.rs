@@ -0,0 +1,82 @@
+use alloc::boxed::Box;
+use core::arch::{asm, x86_64::__cpuid};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::{registers::model_specific::GsBase, structures::idt::InterruptDescriptorTable, VirtAddr};
+use crate::gdt::CpuTables;
+
+// GS base загрузочного процессора уже указывает на его PerCpu
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Данные одного процессора; адрес структуры лежит в IA32_GS_BASE.
+#[repr(C)]
+pub struct PerCpu {
+    // gs:[0] — адрес самой структуры
+    self_addr: u64,
+    index: usize,
+    apic_id: u8,
+    online: AtomicBool,
+    // None у BSP: у него статические таблицы
+    pub(super) tables: Option<&'static CpuTables>,
+    pub(super) idt: Option<&'static InterruptDescriptorTable>,
+}
+
+impl PerCpu {
+    pub(super) fn new(index: usize, apic_id: u8) -> &'static mut PerCpu {
+        let cpu = Box::leak(Box::new(PerCpu {
+            self_addr: 0,
+            index,
+            apic_id,
+            online: AtomicBool::new(false),
+            tables: None,
+            idt: None,
+        }));
+        cpu.self_addr = cpu as *const PerCpu as u64;
+        cpu
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub(super) fn set_online(&self) {
+        self.online.store(true, Ordering::Release);
+    }
+}
+
+/// Делает `cpu` данными текущего процессора.
+pub(super) unsafe fn install(cpu: &'static PerCpu) {
+    GsBase::write(VirtAddr::new(cpu.self_addr));
+    if cpu.index == 0 {
+        READY.store(true, Ordering::Release);
+    }
+}
+
+/// Данные текущего процессора; None до `smp::init_bsp`.
+pub fn current() -> Option<&'static PerCpu> {
+    if !READY.load(Ordering::Acquire) {
+        return None;
+    }
+    let addr: u64;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) addr, options(nostack, readonly, preserves_flags));
+        Some(&*(addr as *const PerCpu))
+    }
+}
+
+/// Номер текущего процессора (0 — BSP).
+pub fn cpu_index() -> usize {
+    current().map_or(0, |cpu| cpu.index)
+}
+
+// Начальный APIC ID из CPUID: доступен ещё до включения local APIC
+pub(super) fn initial_apic_id() -> u8 {
+    (__cpuid(1).ebx >> 24) as u8
+}
@@ -0,0 +1,156 @@
+use core::arch::global_asm;
+use core::ptr;
+use x86_64::{registers::control::Cr3, structures::paging::PhysFrame};
+use crate::memory;
+
+// Код, с которого AP стартует после SIPI: real mode -> protected mode -> long mode
+// и вызов `entry(arg)` на выданном стеке. Он копируется в свободный фрейм ниже 1 МиБ
+// и должен работать с любого адреса, поэтому данные адресуются относительно
+// %cs (в real mode) или %esi (физический адрес трамполина) дальше. Синтаксис AT&T:
+// косвенные дальние переходы в нём записываются однозначно.
+global_asm!(
+    ".global tm_ap_trampoline",
+    ".global tm_ap_trampoline_end",
+    ".code16",
+    "tm_ap_trampoline:",
+    "    cli",
+    "    cld",
+    "    movw %cs, %ax",
+    "    movw %ax, %ds",
+    "    xorl %esi, %esi",
+    "    movw %ax, %si",
+    "    shll $4, %esi",
+    "    lgdtl (tm_ap_gdt_ptr - tm_ap_trampoline)",
+    "    movl %cr0, %eax",
+    "    orl $1, %eax",
+    "    movl %eax, %cr0",
+    "    ljmpl *(tm_ap_pm_jump - tm_ap_trampoline)",
+    ".code32",
+    "tm_ap_pm:",
+    "    movw $0x10, %ax",
+    "    movw %ax, %ds",
+    "    movw %ax, %es",
+    "    movw %ax, %ss",
+    // PAE, таблицы страниц BSP, EFER.LME | EFER.NXE, затем PG | WP
+    "    movl %cr4, %eax",
+    "    orl $(1 << 5), %eax",
+    "    movl %eax, %cr4",
+    "    movl (tm_ap_cr3 - tm_ap_trampoline)(%esi), %eax",
+    "    movl %eax, %cr3",
+    "    movl $0xC0000080, %ecx",
+    "    rdmsr",
+    "    orl $((1 << 8) | (1 << 11)), %eax",
+    "    wrmsr",
+    "    movl %cr0, %eax",
+    "    orl $((1 << 31) | (1 << 16)), %eax",
+    "    movl %eax, %cr0",
+    "    ljmpl *(tm_ap_lm_jump - tm_ap_trampoline)(%esi)",
+    ".code64",
+    "tm_ap_lm:",
+    "    movl %esi, %esi",
+    "    xorw %ax, %ax",
+    "    movw %ax, %ds",
+    "    movw %ax, %es",
+    "    movw %ax, %ss",
+    "    movq (tm_ap_stack - tm_ap_trampoline)(%rsi), %rsp",
+    "    movq (tm_ap_arg - tm_ap_trampoline)(%rsi), %rdi",
+    "    movq (tm_ap_entry - tm_ap_trampoline)(%rsi), %rax",
+    "    callq *%rax",
+    "    ud2",
+    ".p2align 3",
+    "tm_ap_gdt:",
+    "    .quad 0",
+    "    .quad 0x00CF9A000000FFFF", // 0x08: код, 32 бита
+    "    .quad 0x00CF92000000FFFF", // 0x10: данные, 32 бита
+    "    .quad 0x00AF9A000000FFFF", // 0x18: код, 64 бита
+    "tm_ap_gdt_ptr:",
+    "    .word tm_ap_gdt_ptr - tm_ap_gdt - 1",
+    "    .long 0",
+    "tm_ap_pm_jump:",
+    "    .long 0",
+    "    .word 0x08",
+    "tm_ap_lm_jump:",
+    "    .long 0",
+    "    .word 0x18",
+    ".p2align 3",
+    "tm_ap_cr3: .quad 0",
+    "tm_ap_stack: .quad 0",
+    "tm_ap_entry: .quad 0",
+    "tm_ap_arg: .quad 0",
+    "tm_ap_trampoline_end:",
+    ".code64",
+    options(att_syntax),
+);
+
+extern "C" {
+    static tm_ap_trampoline: u8;
+    static tm_ap_trampoline_end: u8;
+    static tm_ap_pm: u8;
+    static tm_ap_lm: u8;
+    static tm_ap_gdt: u8;
+    static tm_ap_gdt_ptr: u8;
+    static tm_ap_pm_jump: u8;
+    static tm_ap_lm_jump: u8;
+    static tm_ap_cr3: u8;
+    static tm_ap_stack: u8;
+    static tm_ap_entry: u8;
+    static tm_ap_arg: u8;
+}
+
+/// Установленная копия трамполина.
+pub struct Trampoline {
+    frame: PhysFrame,
+}
+
+// Смещение метки от начала трамполина
+fn offset(label: &u8) -> u64 {
+    label as *const u8 as u64 - unsafe { &tm_ap_trampoline as *const u8 as u64 }
+}
+
+impl Trampoline {
+    /// Копирует трамполин в свободный фрейм ниже 1 МиБ и отображает его
+    /// один к одному: после включения страничной адресации AP продолжает с того же адреса.
+    pub fn install() -> Result<Trampoline, &'static str> {
+        let (cr3, _) = Cr3::read();
+        // В cr3 AP пишет из 32-битного режима
+        if cr3.start_address().as_u64() >= 1 << 32 {
+            return Err("page tables above 4 GiB");
+        }
+        let frame = memory::low_memory_frame().ok_or("no free low memory for trampoline")?;
+        memory::identity_map(frame).map_err(|_| "cannot map trampoline")?;
+
+        let trampoline = Trampoline { frame };
+        let len = unsafe { offset(&tm_ap_trampoline_end) } as usize;
+        unsafe {
+            ptr::copy_nonoverlapping(&tm_ap_trampoline as *const u8, trampoline.ptr(0), len);
+            let base = frame.start_address().as_u64();
+            trampoline.write(offset(&tm_ap_gdt_ptr) + 2, (base + offset(&tm_ap_gdt)) as u32);
+            trampoline.write(offset(&tm_ap_pm_jump), (base + offset(&tm_ap_pm)) as u32);
+            trampoline.write(offset(&tm_ap_lm_jump), (base + offset(&tm_ap_lm)) as u32);
+            trampoline.write(offset(&tm_ap_cr3), cr3.start_address().as_u64());
+        }
+        Ok(trampoline)
+    }
+
+    fn ptr(&self, offset: u64) -> *mut u8 {
+        memory::phys_to_virt(self.frame.start_address() + offset).as_mut_ptr()
+    }
+
+    unsafe fn write<T>(&self, offset: u64, value: T) {
+        ptr::write_unaligned(self.ptr(offset) as *mut T, value);
+    }
+
+    /// Что AP вызовет после перехода в long mode: `entry(arg)` на стеке `stack_top`.
+    pub fn set_entry(&self, entry: extern "C" fn(u64) -> !, arg: u64, stack_top: u64) {
+        unsafe {
+            self.write(offset(&tm_ap_stack), stack_top & !0xF);
+            self.write(offset(&tm_ap_entry), entry as usize as u64);
+            self.write(offset(&tm_ap_arg), arg);
+        }
+    }
+
+    /// Номер страницы для SIPI: AP начнёт с адреса `vector << 12`.
+    pub fn startup_vector(&self) -> u8 {
+        (self.frame.start_address().as_u64() >> 12) as u8
+    }
+}
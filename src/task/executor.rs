@@ -0,0 +1,254 @@
+use super::{JoinHandle, Task};
+use alloc::{collections::VecDeque, string::String, sync::Arc, task::Wake, vec::Vec};
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Waker};
+use crossbeam_queue::ArrayQueue;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::smp::{self, MAX_CPUS};
+
+const QUEUE_CAPACITY: usize = 256;
+
+// Состояние задачи; CPU договариваются через него, кто её опрашивает
+const IDLE: u8 = 0; // ждёт пробуждения
+const SCHEDULED: u8 = 1; // стоит в одной из очередей
+const RUNNING: u8 = 2;
+const NOTIFIED: u8 = 3; // разбудили во время опроса: после него снова в очередь
+const DONE: u8 = 4;
+
+struct TaskCell {
+    task: Mutex<Option<Task>>,
+    state: AtomicU8,
+    // CPU, где задача выполнялась последней: туда её и будим
+    home: AtomicUsize,
+}
+
+/// Очередь готовых задач одного CPU. Без выделения памяти при push:
+/// задачи будят и из обработчиков прерываний.
+struct RunQueue {
+    tasks: ArrayQueue<Arc<TaskCell>>,
+    idle: AtomicBool,
+    polls: AtomicU64,
+}
+
+lazy_static! {
+    static ref QUEUES: Vec<RunQueue> = (0..MAX_CPUS)
+        .map(|_| RunQueue {
+            tasks: ArrayQueue::new(QUEUE_CAPACITY),
+            idle: AtomicBool::new(false),
+            polls: AtomicU64::new(0),
+        })
+        .collect();
+}
+
+// Куда попадают задачи, когда все очереди полны. Ёмкость резервируется в `spawn`
+// под число живых задач: каждая стоит не больше чем в одной очереди, так что push
+// из прерывания сюда никогда не выделяет память
+static OVERFLOW: Mutex<VecDeque<Arc<TaskCell>>> = Mutex::new(VecDeque::new());
+static LIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Запускает задачу на текущем CPU; простаивающие CPU могут её забрать.
+/// Нельзя вызывать из прерываний: выделяет память.
+pub fn spawn<F>(name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_task(name, false, future)
+}
+
+/// Запускает системную задачу на текущем CPU; простаивающие CPU могут её забрать.
+/// Такую задачу нельзя отменить командой `cancel`.
+pub fn spawn_system<F>(name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_task(name, true, future)
+}
+
+fn spawn_task<F>(name: impl Into<String>, system: bool, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, handle) = Task::new(name, system, future);
+    let live = LIVE_TASKS.fetch_add(1, Ordering::Relaxed) + 1;
+    interrupts::without_interrupts(|| {
+        let mut overflow = OVERFLOW.lock();
+        let len = overflow.len();
+        overflow.reserve(live.saturating_sub(len));
+    });
+    let cpu = smp::cpu_index();
+    let cell = Arc::new(TaskCell {
+        task: Mutex::new(Some(task)),
+        state: AtomicU8::new(SCHEDULED),
+        home: AtomicUsize::new(cpu),
+    });
+    push(cpu, cell);
+    if let Some(idle) = (0..smp::online_count()).find(|&i| i != cpu && QUEUES[i].idle.load(Ordering::SeqCst)) {
+        smp::wake_cpu(idle);
+    }
+    handle
+}
+
+// Кладёт задачу в очередь `cpu`, а если та полна — в соседнюю или в OVERFLOW
+fn push(cpu: usize, mut cell: Arc<TaskCell>) {
+    let online = smp::online_count().max(1);
+    for target in (0..online).map(|i| (cpu + i) % online) {
+        match QUEUES[target].tasks.push(cell) {
+            Ok(()) => {
+                kick(target);
+                return;
+            }
+            Err(rejected) => cell = rejected,
+        }
+    }
+    interrupts::without_interrupts(|| OVERFLOW.lock().push_back(cell));
+    kick(cpu);
+}
+
+/// Будит `cpu`, если он спит в ожидании задач.
+pub(crate) fn kick(cpu: usize) {
+    if cpu != smp::cpu_index() && QUEUES[cpu].idle.load(Ordering::SeqCst) {
+        smp::wake_cpu(cpu);
+    }
+}
+
+/// (задач в очереди, сколько раз опрашивались задачи) для CPU `cpu`.
+pub fn cpu_stats(cpu: usize) -> (usize, u64) {
+    let queue = &QUEUES[cpu];
+    (queue.tasks.len(), queue.polls.load(Ordering::Relaxed))
+}
+
+impl Wake for TaskCell {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) if next == SCHEDULED => break,
+                Ok(_) => return,
+                Err(actual) => state = actual,
+            }
+        }
+        push(self.home.load(Ordering::Relaxed), self.clone());
+    }
+}
+
+/// Executor одного CPU; на каждом процессоре работает свой.
+pub struct Executor {
+    cpu: usize,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        lazy_static::initialize(&QUEUES);
+        Executor { cpu: smp::cpu_index() }
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            // Тики (а с ними и sleep) есть только на BSP
+            if self.cpu == 0 {
+                super::wake_sleepers();
+            }
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&self) {
+        while let Some(cell) = self.next_task() {
+            self.run_task(cell);
+        }
+    }
+
+    fn next_task(&self) -> Option<Arc<TaskCell>> {
+        QUEUES[self.cpu].tasks.pop()
+            .or_else(|| interrupts::without_interrupts(|| OVERFLOW.lock().pop_front()))
+            .or_else(|| self.steal())
+    }
+
+    // Забираем половину очереди первого занятого соседа
+    fn steal(&self) -> Option<Arc<TaskCell>> {
+        let online = smp::online_count();
+        for victim in (1..online).map(|i| &QUEUES[(self.cpu + i) % online]) {
+            let count = victim.tasks.len().div_ceil(2);
+            let first = match victim.tasks.pop() {
+                Some(cell) => cell,
+                None => continue,
+            };
+            for _ in 1..count {
+                match victim.tasks.pop() {
+                    Some(cell) => push(self.cpu, cell),
+                    None => break,
+                }
+            }
+            return Some(first);
+        }
+        None
+    }
+
+    fn run_task(&self, cell: Arc<TaskCell>) {
+        cell.home.store(self.cpu, Ordering::Relaxed);
+        cell.state.store(RUNNING, Ordering::Release);
+        let waker = Waker::from(cell.clone());
+        let mut context = Context::from_waker(&waker);
+        let done = {
+            let mut slot = cell.task.lock();
+            let done = slot.as_mut().is_none_or(|task| task.poll(&mut context).is_ready());
+            if done {
+                *slot = None;
+            }
+            done
+        };
+        QUEUES[self.cpu].polls.fetch_add(1, Ordering::Relaxed);
+        if done {
+            cell.state.store(DONE, Ordering::Release);
+            LIVE_TASKS.fetch_sub(1, Ordering::Relaxed);
+        } else if cell.state.compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            cell.state.store(SCHEDULED, Ordering::Release);
+            push(self.cpu, cell);
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        QUEUES[..smp::online_count().max(1)].iter().any(|queue| !queue.tasks.is_empty())
+            || interrupts::without_interrupts(|| !OVERFLOW.lock().is_empty())
+    }
+
+    fn sleep_if_idle(&self) {
+        if self.has_work() {
+            return;
+        }
+        // Потоки живут только на BSP: сначала отдаём процессор готовым потокам
+        if self.cpu == 0 {
+            crate::thread::yield_now();
+        }
+        let queue = &QUEUES[self.cpu];
+        // Сначала помечаем себя спящим, потом проверяем очереди: push с другого CPU
+        // либо увидит флаг и пришлёт IPI, либо мы увидим его задачу
+        queue.idle.store(true, Ordering::SeqCst);
+        interrupts::disable();
+        if self.has_work() {
+            interrupts::enable();
+        } else if self.cpu == 0 {
+            let deadline = super::next_sleeper().into_iter().chain(crate::thread::next_deadline()).min();
+            crate::time::idle(deadline);
+        } else {
+            interrupts::enable_and_hlt();
+        }
+        queue.idle.store(false, Ordering::SeqCst);
+    }
+}
@@ -1,17 +1,40 @@
 use crate::{print, println, vga_buffer};
-use alloc::string::String;
+use alloc::{string::String, vec::Vec};
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
 use core::{pin::Pin, task::{Poll, Context}};
 use futures_util::stream::{Stream, StreamExt};
 use futures_util::task::AtomicWaker;
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1, KeyCode};
-use crate::task::sleep;
+use crate::task::{sleep, JoinHandle, TaskId};
+use crate::task::sync::mpsc;
+use lazy_static::lazy_static;
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
+// Фоновые задачи, запущенные через `cmd &`
+static JOBS: spin::Mutex<Vec<JoinHandle<()>>> = spin::Mutex::new(Vec::new());
 
-pub(crate) fn add_scancode(scancode: u8) {
+lazy_static! {
+    // Завершившиеся фоновые команды: оболочка сообщает о них перед приглашением
+    static ref FINISHED: (mpsc::UnboundedSender<String>, spin::Mutex<mpsc::Receiver<String>>) = {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (sender, spin::Mutex::new(receiver))
+    };
+}
+
+/// Вешает обработчик на IRQ1 (контроллер клавиатуры i8042).
+pub fn init() -> Result<(), &'static str> {
+    crate::irq::register_irq(1, "keyboard", || {
+        use x86_64::instructions::port::Port;
+        let scancode: u8 = unsafe { Port::new(0x60).read() };
+        add_scancode(scancode);
+        crate::irq::IrqReturn::Handled
+    })?;
+    Ok(())
+}
+
+fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Ok(_) = queue.push(scancode) {
             WAKER.wake();
@@ -46,12 +69,20 @@ impl Stream for ScancodeStream {
     }
 }
 
+fn prompt() {
+    let mut finished = FINISHED.1.lock();
+    while let Ok(cmd) = finished.try_recv() {
+        println!("Done: {}", cmd);
+    }
+    print!("> ");
+}
+
 pub async fn shell_task() {
     let mut scancodes = ScancodeStream::new();
     let mut keyboard = Keyboard::new(ScancodeSet1::new(), layouts::Us104Key, HandleControl::Ignore);
     let mut buffer = String::with_capacity(256);
 
-    print!("> ");
+    prompt();
 
     while let Some(scancode) = scancodes.next().await {
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
@@ -60,9 +91,9 @@ pub async fn shell_task() {
                     DecodedKey::Unicode(character) => match character {
                         '\n' => {
                             println!();
-                            execute_command(&buffer).await; // ТЕПЕРЬ AWAIT
+                            run_line(&buffer).await;
                             buffer.clear();
-                            print!("> ");
+                            prompt();
                         }
                         '\u{0008}' => {
                             if buffer.pop().is_some() { vga_buffer::backspace(); }
@@ -74,9 +105,9 @@ pub async fn shell_task() {
                     },
                     DecodedKey::RawKey(KeyCode::Return) => {
                         println!();
-                        execute_command(&buffer).await; // ТЕПЕРЬ AWAIT
+                        run_line(&buffer).await;
                         buffer.clear();
-                        print!("> ");
+                        prompt();
                     }
                     _ => {}
                 }
@@ -85,6 +116,96 @@ pub async fn shell_task() {
     }
 }
 
+/// `cmd &` запускает команду фоновой задачей, остальное выполняется сразу.
+async fn run_line(line: &str) {
+    let line = line.trim();
+    match line.strip_suffix('&') {
+        Some(cmd) => {
+            let cmd = String::from(cmd.trim());
+            let finished = FINISHED.0.clone();
+            let handle = crate::task::spawn(cmd.clone(), async move {
+                execute_command(&cmd).await;
+                let _ = finished.send(cmd);
+            });
+            println!("[{}] started", handle.id().as_u64());
+            let mut jobs = JOBS.lock();
+            jobs.retain(|job| !job.is_finished());
+            jobs.push(handle);
+        }
+        None => execute_command(line).await,
+    }
+}
+
+// Несброшенные блоки кэша иначе пропадут вместе с питанием
+async fn sync_before_power_off() {
+    if let Err(e) = crate::block::cache::sync().await {
+        println!("sync: {}", e);
+    }
+}
+
+// Печатает файл кусками, не загружая его в память целиком
+fn print_file(fd: crate::process::Fd) -> crate::fs::FsResult<()> {
+    let mut buf = [0u8; 512];
+    // Начало многобайтного символа, разрезанного границей куска
+    let mut carry = 0;
+    loop {
+        let n = crate::process::read(fd, &mut buf[carry..])?;
+        let len = carry + n;
+        let valid = match core::str::from_utf8(&buf[..len]) {
+            Err(e) if n > 0 && e.error_len().is_none() => e.valid_up_to(),
+            _ => len,
+        };
+        print!("{}", String::from_utf8_lossy(&buf[..valid]));
+        if n == 0 {
+            return Ok(());
+        }
+        buf.copy_within(valid..len, 0);
+        carry = len - valid;
+    }
+}
+
+// `tail`: N — последние N байт, +N — начиная с байта N
+fn tail_seek(fd: crate::process::Fd, count: &str) -> crate::fs::FsResult<()> {
+    use crate::fs::{FsError, SeekFrom};
+    let (from_start, count) = match count.strip_prefix('+') {
+        Some(offset) => (true, offset),
+        None => (false, count),
+    };
+    let count = count.parse::<u64>().map_err(|_| FsError::InvalidArgument)?;
+    if from_start {
+        crate::process::seek(fd, SeekFrom::Start(count))?;
+    } else {
+        let size = crate::process::seek(fd, SeekFrom::End(0))?;
+        crate::process::seek(fd, SeekFrom::Current(-(count.min(size) as i64)))?;
+    }
+    Ok(())
+}
+
+fn report(command: &str, path: &str, result: crate::fs::FsResult<()>) {
+    if let Err(e) = result {
+        println!("{}: {}: {}", command, path, e);
+    }
+}
+
+// `echo text`, `echo text > file` (перезапись) и `echo text >> file` (дописывание)
+fn echo(args: &str) {
+    use crate::fs::OpenFlags;
+    let (text, path, flags) = match args.find('>') {
+        None => return println!("{}", args),
+        Some(i) if args[i + 1..].starts_with('>') => (&args[..i], &args[i + 2..], OpenFlags::APPEND),
+        Some(i) => (&args[..i], &args[i + 1..], OpenFlags::TRUNCATE),
+    };
+    let path = path.trim();
+    let mut line = String::from(text.trim());
+    line.push('\n');
+    let result = crate::process::open(path, OpenFlags::WRITE | OpenFlags::CREATE | flags).and_then(|fd| {
+        let written = crate::process::write(fd, line.as_bytes());
+        let _ = crate::process::close(fd);
+        written.map(|_| ())
+    });
+    report("echo", path, result);
+}
+
 async fn execute_command(input: &str) {
     let input = input.trim();
     if input.is_empty() { return; }
@@ -94,22 +215,95 @@ async fn execute_command(input: &str) {
     let args = parts.next().unwrap_or("");
 
     match command {
-        "help" => println!("Commands: ls, cat <file>, help, clear, uptime, sum <n>, sleep <ms>, info, panic, free"),
+        "help" => println!("Commands: ls [dir], cat <file>, tail <file> [bytes|+offset], stat <path>, touch <file>, mkdir <dir>, rm <path>, mv <from> <to>, cp <from> <to>, echo <text> [>|>> file], mounts, mount <dev> <path>, disks, lsblk, lspci [-v], blkread <dev> <lba>, sync, help, clear, uptime, clock, irqstat, sum <n>, sleep <ms>, ps, proc, spawn <ms> [code], wait <pid>, kill <pid>, tasks, cancel <id>, nice <tid> <prio>, slice <prio> <ms>, acpi [madt|fadt|hpet|mcfg], smp, info, shutdown, reboot, panic, free"),
         "clear" => vga_buffer::clear_screen(),
-        "ls" => crate::fs::list_files(),
+        "ls" => crate::fs::list_dir(if args.trim().is_empty() { "/" } else { args.trim() }),
         "cat" => {
-            if let Some(file) = crate::fs::get_file(args.trim()) {
-                println!("{}", file.content);
-            } else {
-                println!("File not found: {}", args);
+            let path = args.trim();
+            let result = crate::process::open(path, crate::fs::OpenFlags::READ).and_then(|fd| {
+                let result = print_file(fd);
+                let _ = crate::process::close(fd);
+                result
+            });
+            match result {
+                Ok(()) => println!(),
+                Err(e) => println!("cat: {}: {}", path, e),
+            }
+        }
+        "tail" => {
+            let mut words = args.split_whitespace();
+            match (words.next(), words.next().unwrap_or("512")) {
+                (Some(path), count) => {
+                    let result = crate::process::open(path, crate::fs::OpenFlags::READ).and_then(|fd| {
+                        let result = tail_seek(fd, count).and_then(|()| print_file(fd));
+                        let _ = crate::process::close(fd);
+                        result
+                    });
+                    match result {
+                        Ok(()) => println!(),
+                        Err(e) => println!("tail: {}: {}", path, e),
+                    }
+                }
+                (None, _) => println!("Usage: tail <file> [bytes|+offset]"),
+            }
+        }
+        "stat" => crate::fs::print_stat(args.trim()),
+        "touch" => report("touch", args.trim(), crate::fs::touch(args.trim())),
+        "mkdir" => report("mkdir", args.trim(), crate::fs::mkdir(args.trim())),
+        "rm" => report("rm", args.trim(), crate::fs::unlink(args.trim())),
+        "mv" | "cp" => {
+            let mut paths = args.split_whitespace();
+            match (paths.next(), paths.next()) {
+                (Some(from), Some(to)) => {
+                    let result = if command == "mv" { crate::fs::rename(from, to) } else { crate::fs::copy(from, to) };
+                    report(command, from, result);
+                }
+                _ => println!("Usage: {} <from> <to>", command),
+            }
+        }
+        "echo" => echo(args),
+        "mounts" => crate::fs::list_mounts(),
+        "mount" => {
+            let mut words = args.split_whitespace();
+            match (words.next(), words.next()) {
+                (None, _) => crate::fs::list_mounts(),
+                (Some(device), Some(path)) => match crate::fs::mount_device(device, path) {
+                    Ok(kind) => println!("Mounted {} ({}) on {}", device, kind, path),
+                    Err(e) => println!("mount: {}: {}", device, e),
+                },
+                _ => println!("Usage: mount <dev> <path>"),
+            }
+        }
+        "disks" => crate::block::list_devices(),
+        "lsblk" => crate::block::partition::list_tree(),
+        "lspci" => crate::pci::list_devices(args.trim() == "-v"),
+        "blkread" => {
+            let mut words = args.split_whitespace();
+            match (words.next(), words.next().and_then(|lba| lba.parse::<u64>().ok())) {
+                (Some(name), Some(lba)) => crate::block::dump_block(name, lba).await,
+                _ => println!("Usage: blkread <dev> <lba>"),
             }
         }
         "uptime" => println!("Ticks: {}", crate::interrupts::TICKS.load(core::sync::atomic::Ordering::Relaxed)),
+        "clock" => crate::time::list_clocks(),
+        "irqstat" => crate::irq::list_irqs(),
+        "smp" => crate::smp::list_cpus(),
         "sum" => {
             if let Ok(n) = args.parse::<u64>() {
-                let mut total: u64 = 0;
-                for i in 1..=n { total += i; }
-                println!("Sum: {}", total);
+                // Считаем в отдельном процессе, чтобы не занимать executor
+                let pid = crate::process::spawn("sum", move || {
+                    let mut total: u64 = 0;
+                    for i in 1..=n {
+                        total += i;
+                        // Здесь `kill` может завершить поток
+                        if i % (1 << 20) == 0 { crate::thread::yield_now(); }
+                    }
+                    println!("Sum: {}", total);
+                    0
+                });
+                if let Ok(code) = crate::process::wait_async(pid).await {
+                    if code != 0 { println!("sum: exit code {}", code); }
+                }
             }
         },
         "sleep" => {
@@ -117,6 +311,90 @@ async fn execute_command(input: &str) {
                 sleep(ms).await;
             }
         },
+        "ps" => crate::thread::list_threads(),
+        "tasks" => crate::task::list_tasks(),
+        "cancel" => {
+            match args.trim().parse::<u64>() {
+                Ok(id) => {
+                    let id = TaskId::from_u64(id);
+                    // Свои фоновые задачи отменяем через их JoinHandle, прочие — по id
+                    let job = {
+                        let mut jobs = JOBS.lock();
+                        jobs.iter().position(|job| job.id() == id).map(|i| jobs.swap_remove(i))
+                    };
+                    let result = match job {
+                        Some(job) => {
+                            job.abort();
+                            Ok(())
+                        }
+                        None => crate::task::abort(id),
+                    };
+                    if let Err(e) = result {
+                        println!("cancel: {}", e);
+                    }
+                }
+                Err(_) => println!("Usage: cancel <task id>"),
+            }
+        },
+        "proc" => crate::process::list_processes(),
+        "spawn" => {
+            let mut words = args.split_whitespace();
+            let ms = words.next().and_then(|s| s.parse::<u64>().ok());
+            let code = words.next().map_or(Some(0), |s| s.parse::<i32>().ok());
+            match (ms, code) {
+                (Some(ms), Some(code)) => {
+                    // Процесс для проверки `proc`, `kill` и `wait`: спит в своём потоке и выходит с кодом
+                    let pid = crate::process::spawn("sleeper", move || {
+                        crate::thread::sleep(ms);
+                        crate::process::exit(code)
+                    });
+                    println!("[pid {}] started", pid.as_u64());
+                }
+                _ => println!("Usage: spawn <ms> [exit code]"),
+            }
+        },
+        "wait" => {
+            match args.trim().parse::<u64>() {
+                Ok(pid) => match crate::process::wait_async(crate::process::Pid::from_u64(pid)).await {
+                    Ok(code) => println!("Process {} exited with code {}", pid, code),
+                    Err(e) => println!("wait: {}", e),
+                },
+                Err(_) => println!("Usage: wait <pid>"),
+            }
+        },
+        "kill" => {
+            match args.trim().parse::<u64>() {
+                Ok(pid) => {
+                    if let Err(e) = crate::process::kill(crate::process::Pid::from_u64(pid)) {
+                        println!("kill: {}", e);
+                    }
+                }
+                Err(_) => println!("Usage: kill <pid>"),
+            }
+        },
+        "nice" => {
+            let mut it = args.split_whitespace();
+            let tid = it.next().and_then(|s| s.parse::<u64>().ok());
+            let prio = it.next().and_then(|s| s.parse::<crate::thread::Priority>().ok());
+            match (tid, prio) {
+                (Some(tid), Some(prio)) => {
+                    if !crate::thread::set_priority(crate::thread::ThreadId::from_u64(tid), prio) {
+                        println!("No such thread: {}", tid);
+                    }
+                }
+                _ => println!("Usage: nice <tid> <idle|low|normal|high>"),
+            }
+        },
+        "slice" => {
+            let mut it = args.split_whitespace();
+            let prio = it.next().and_then(|s| s.parse::<crate::thread::Priority>().ok());
+            let ticks = it.next().and_then(|s| s.parse::<u64>().ok());
+            match (prio, ticks) {
+                (Some(prio), Some(ticks)) => crate::thread::set_time_slice(prio, ticks),
+                _ => println!("Usage: slice <idle|low|normal|high> <ms>"),
+            }
+        },
+        "acpi" => crate::acpi::dump(args.trim()),
         "info" => {
             println!("  ______             ____  _____ ");
             println!(" /_  __/___ ___     / __ \\/ ___/ ");
@@ -124,10 +402,29 @@ async fn execute_command(input: &str) {
             println!(" / / / / / / / /  / /_/ /___/ /  ");
             println!("/_/ /_/ /_/ /_/   \\____//____/   ");
 
-            println!("CPU: x86_64 | Mode: Rust Async | Files: {}", crate::fs::FILES.len());
+            println!("CPU: x86_64 | Mode: Rust Async | Mounts: {}", crate::fs::mount_count());
         },
         "free" => {
-            println!("Heap Usage: Dynamic Allocation Enabled (1024 KB reserved)");
+            let (used, free) = crate::allocator::heap_usage();
+            println!("Heap Usage: {} KB used, {} KB free ({} KB reserved)",
+                used / 1024, free / 1024, crate::allocator::HEAP_SIZE / 1024);
+        },
+        "sync" => match crate::block::cache::sync().await {
+            Ok(blocks) => {
+                println!("Wrote {} blocks", blocks);
+                crate::block::cache::print_stats();
+            }
+            Err(e) => println!("sync: {}", e),
+        },
+        "shutdown" => {
+            println!("Shutting down...");
+            sync_before_power_off().await;
+            crate::power::shutdown();
+        },
+        "reboot" => {
+            println!("Rebooting...");
+            sync_before_power_off().await;
+            crate::power::reboot();
         },
         "panic" => {
             panic!("User requested system crash!");
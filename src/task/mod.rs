@@ -1,42 +1,242 @@
-use core::{future::Future, pin::Pin, task::{Context, Poll}};
-use alloc::boxed::Box;
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use spin::Mutex;
 use crate::interrupts::TICKS;
-use core::sync::atomic::Ordering;
 
-pub mod simple_executor;
+pub mod executor;
 pub mod keyboard;
+pub mod sync;
+
+pub use executor::{spawn, spawn_system};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_u64(id: u64) -> Self { TaskId(id) }
+
+    pub fn as_u64(self) -> u64 { self.0 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Aborted,
+}
+
+// Общая часть задачи и её JoinHandle, не зависящая от типа результата
+struct TaskHeader {
+    name: String,
+    // Задачи ядра (оболочка, softirq, ...): без них не вернуть ни ввод, ни отложенную работу
+    system: bool,
+    aborted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl TaskHeader {
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+struct JoinState<T> {
+    result: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+}
+
+lazy_static! {
+    static ref TASKS: Mutex<BTreeMap<TaskId, Arc<TaskHeader>>> = Mutex::new(BTreeMap::new());
+    static ref SLEEPERS: Mutex<BTreeMap<u64, Vec<Waker>>> = Mutex::new(BTreeMap::new());
+}
 
 pub struct Task {
-    future: Pin<Box<dyn Future<Output = ()>>>,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
 }
 
 impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
-        Task {
-            future: Box::pin(future),
-        }
+    /// Оборачивает future в задачу и возвращает её вместе с JoinHandle.
+    /// Системную задачу нельзя отменить через `abort`.
+    pub fn new<F>(name: impl Into<String>, system: bool, future: F) -> (Task, JoinHandle<F::Output>)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = TaskId::new();
+        let header = Arc::new(TaskHeader {
+            name: name.into(),
+            system,
+            aborted: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        let join = Arc::new(Mutex::new(JoinState { result: None, waker: None }));
+        TASKS.lock().insert(id, header.clone());
+        let task = Task {
+            future: Box::pin(TaskFuture {
+                id,
+                future: Box::pin(future),
+                header: header.clone(),
+                join: join.clone(),
+            }),
+        };
+        (task, JoinHandle { id, header, join })
     }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
 }
 
+struct TaskFuture<T> {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = T> + Send>>,
+    header: Arc<TaskHeader>,
+    join: Arc<Mutex<JoinState<T>>>,
+}
+
+impl<T> TaskFuture<T> {
+    fn complete(&self, result: Result<T, JoinError>) {
+        TASKS.lock().remove(&self.id);
+        let waker = {
+            let mut join = self.join.lock();
+            join.result = Some(result);
+            join.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for TaskFuture<T> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.header.waker.register(cx.waker());
+        if self.header.aborted.load(Ordering::Acquire) {
+            self.complete(Err(JoinError::Aborted));
+            return Poll::Ready(());
+        }
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(value) => {
+                self.complete(Ok(value));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Дескриптор запущенной задачи: сам является future с её результатом.
+/// Если его отбросить, задача продолжит работать в фоне.
+pub struct JoinHandle<T> {
+    id: TaskId,
+    header: Arc<TaskHeader>,
+    join: Arc<Mutex<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn abort(&self) {
+        self.header.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join.lock().result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut join = self.join.lock();
+        match join.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                join.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Отменяет задачу по id; она завершится при следующем опросе executor'ом.
+pub fn abort(id: TaskId) -> Result<(), &'static str> {
+    match TASKS.lock().get(&id) {
+        Some(header) if header.system => Err("cannot cancel a system task"),
+        Some(header) => {
+            header.abort();
+            Ok(())
+        }
+        None => Err("no such task"),
+    }
+}
+
+pub fn list_tasks() {
+    use crate::println;
+    let tasks: Vec<(TaskId, String, bool, bool)> = TASKS.lock().iter()
+        .map(|(id, h)| (*id, h.name.clone(), h.system, h.aborted.load(Ordering::Relaxed)))
+        .collect();
+    println!("  ID  NAME");
+    for (id, name, system, aborted) in tasks {
+        let note = if system { " (system)" } else if aborted { " (aborting)" } else { "" };
+        println!("{:>4}  {}{}", id.as_u64(), name, note);
+    }
+}
+
 pub fn sleep(ms: u64) -> SleepFuture {
     let target_tick = TICKS.load(Ordering::Relaxed) + ms;
-    SleepFuture { target_tick }
+    SleepFuture { target_tick, waker: None }
 }
 
 pub struct SleepFuture {
     target_tick: u64,
+    // Уже зарегистрированный waker: повторный опрос не плодит копии в SLEEPERS
+    waker: Option<Waker>,
 }
 
 impl Future for SleepFuture {
     type Output = ();
-    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         if TICKS.load(Ordering::Relaxed) >= self.target_tick {
             Poll::Ready(())
         } else {
+            if self.waker.as_ref().is_some_and(|waker| waker.will_wake(cx.waker())) {
+                return Poll::Pending;
+            }
+            self.waker = Some(cx.waker().clone());
+            SLEEPERS.lock().entry(self.target_tick).or_default().push(cx.waker().clone());
+            // Спящих будит BSP; в tickless-простое он мог завести таймер на более поздний срок
+            executor::kick(0);
             Poll::Pending
         }
     }
-}
\ No newline at end of file
+}
+
+/// Тик, на котором истекает ближайший `sleep`.
+pub(crate) fn next_sleeper() -> Option<u64> {
+    SLEEPERS.lock().keys().next().copied()
+}
+
+/// Будит задачи, чей `sleep` истёк. Executor BSP вызывает это на каждой итерации,
+/// а `hlt` просыпается на каждом тике таймера.
+pub(crate) fn wake_sleepers() {
+    let now = TICKS.load(Ordering::Relaxed);
+    let expired = {
+        let mut sleepers = SLEEPERS.lock();
+        let later = sleepers.split_off(&(now + 1));
+        core::mem::replace(&mut *sleepers, later)
+    };
+    for waker in expired.into_values().flatten() {
+        waker.wake();
+    }
+}
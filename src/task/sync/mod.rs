@@ -0,0 +1,50 @@
+//! Примитивы синхронизации для задач executor'а. Вместо ожидания в цикле
+//! они сохраняют `Waker` и будят задачу, когда можно продолжить.
+
+pub mod mpsc;
+pub mod oneshot;
+mod mutex;
+mod semaphore;
+mod notify;
+mod select;
+
+pub use mutex::Mutex;
+pub use semaphore::{Semaphore, SemaphorePermit};
+pub use notify::Notify;
+pub use select::{select, Either};
+
+use alloc::collections::VecDeque;
+use core::task::Waker;
+
+// Очередь ожидающих задач без дубликатов одного и того же waker'а
+struct WaitQueue {
+    wakers: VecDeque<Waker>,
+}
+
+impl WaitQueue {
+    const fn new() -> Self {
+        WaitQueue { wakers: VecDeque::new() }
+    }
+
+    fn register(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+            self.wakers.push_back(waker.clone());
+        }
+    }
+
+    fn remove(&mut self, waker: &Waker) {
+        self.wakers.retain(|w| !w.will_wake(waker));
+    }
+
+    fn wake_one(&mut self) {
+        if let Some(waker) = self.wakers.pop_front() {
+            waker.wake();
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Каналы "много отправителей — один получатель", ограниченные и неограниченные.
+
+use super::WaitQueue;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use futures_util::stream::Stream;
+use spin::Mutex;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+struct Chan<T> {
+    queue: VecDeque<T>,
+    capacity: Option<usize>,
+    senders: usize,
+    receiver_alive: bool,
+    recv_waker: Option<Waker>,
+    send_waiters: WaitQueue,
+}
+
+impl<T> Chan<T> {
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|cap| self.queue.len() >= cap)
+    }
+
+    fn push(&mut self, value: T) {
+        self.queue.push_back(value);
+        if let Some(waker) = self.recv_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+type Shared<T> = Arc<Mutex<Chan<T>>>;
+
+fn new_chan<T>(capacity: Option<usize>) -> Shared<T> {
+    Arc::new(Mutex::new(Chan {
+        queue: VecDeque::new(),
+        capacity,
+        senders: 1,
+        receiver_alive: true,
+        recv_waker: None,
+        send_waiters: WaitQueue::new(),
+    }))
+}
+
+/// Канал на `capacity` сообщений: `send` ждёт, пока в очереди не освободится место.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc capacity must be non-zero");
+    let chan = new_chan(Some(capacity));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let chan = new_chan(None);
+    (UnboundedSender { chan: chan.clone() }, Receiver { chan })
+}
+
+fn clone_sender<T>(chan: &Shared<T>) -> Shared<T> {
+    chan.lock().senders += 1;
+    chan.clone()
+}
+
+fn drop_sender<T>(chan: &Shared<T>) {
+    let mut chan = chan.lock();
+    chan.senders -= 1;
+    if chan.senders == 0 {
+        if let Some(waker) = chan.recv_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Sender<T> {
+    chan: Shared<T>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture { sender: self, value: Some(value) }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut chan = self.chan.lock();
+        if !chan.receiver_alive {
+            Err(TrySendError::Closed(value))
+        } else if chan.is_full() {
+            Err(TrySendError::Full(value))
+        } else {
+            chan.push(value);
+            Ok(())
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender { chan: clone_sender(&self.chan) }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.chan);
+    }
+}
+
+pub struct SendFuture<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+}
+
+// Значение никогда не закрепляется (pin), мы только перемещаем его в очередь
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let value = self.value.take().expect("SendFuture polled after completion");
+        match self.sender.try_send(value) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(TrySendError::Closed(value)) => Poll::Ready(Err(SendError(value))),
+            Err(TrySendError::Full(value)) => {
+                self.sender.chan.lock().send_waiters.register(cx.waker());
+                // Место могло освободиться, пока мы регистрировались
+                match self.sender.try_send(value) {
+                    Ok(()) => Poll::Ready(Ok(())),
+                    Err(TrySendError::Closed(value)) => Poll::Ready(Err(SendError(value))),
+                    Err(TrySendError::Full(value)) => {
+                        self.value = Some(value);
+                        Poll::Pending
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct UnboundedSender<T> {
+    chan: Shared<T>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Не ждёт и не блокирует, поэтому годится и вне задач (но не в прерываниях: выделяет память).
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut chan = self.chan.lock();
+        if !chan.receiver_alive {
+            return Err(SendError(value));
+        }
+        chan.push(value);
+        Ok(())
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        UnboundedSender { chan: clone_sender(&self.chan) }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.chan);
+    }
+}
+
+pub struct Receiver<T> {
+    chan: Shared<T>,
+}
+
+impl<T> Receiver<T> {
+    /// `None`, когда все отправители отброшены и очередь пуста.
+    pub fn recv(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut chan = self.chan.lock();
+        match chan.queue.pop_front() {
+            Some(value) => {
+                chan.send_waiters.wake_one();
+                Ok(value)
+            }
+            None if chan.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Закрывает канал для отправителей; уже отправленное можно дочитать.
+    pub fn close(&mut self) {
+        let mut chan = self.chan.lock();
+        chan.receiver_alive = false;
+        chan.send_waiters.wake_all();
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut chan = self.chan.lock();
+        if let Some(value) = chan.queue.pop_front() {
+            chan.send_waiters.wake_one();
+            return Poll::Ready(Some(value));
+        }
+        if chan.senders == 0 {
+            return Poll::Ready(None);
+        }
+        chan.recv_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
+
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Option<T>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.receiver.poll_recv(cx)
+    }
+}
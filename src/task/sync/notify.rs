@@ -0,0 +1,96 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use spin::Mutex;
+
+/// Уведомление без данных. `notify_one` без ожидающих сохраняется до
+/// следующего `notified()`.
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    waiters: VecDeque<Arc<Mutex<Waiter>>>,
+}
+
+struct Waiter {
+    notified: bool,
+    waker: Option<Waker>,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify { state: Mutex::new(State { permit: false, waiters: VecDeque::new() }) }
+    }
+
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { notify: self, waiter: None }
+    }
+
+    pub fn notify_one(&self) {
+        let mut state = self.state.lock();
+        match state.waiters.pop_front() {
+            Some(waiter) => wake(&waiter),
+            None => state.permit = true,
+        }
+    }
+}
+
+fn wake(waiter: &Mutex<Waiter>) {
+    let waker = {
+        let mut waiter = waiter.lock();
+        waiter.notified = true;
+        waiter.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Arc<Mutex<Waiter>>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(waiter) = &self.waiter {
+            let mut waiter = waiter.lock();
+            if waiter.notified {
+                drop(waiter);
+                self.waiter = None;
+                return Poll::Ready(());
+            }
+            waiter.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let mut state = self.notify.state.lock();
+        if state.permit {
+            state.permit = false;
+            return Poll::Ready(());
+        }
+        let waiter = Arc::new(Mutex::new(Waiter { notified: false, waker: Some(cx.waker().clone()) }));
+        state.waiters.push_back(waiter.clone());
+        drop(state);
+        self.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        let mut state = self.notify.state.lock();
+        state.waiters.retain(|w| !Arc::ptr_eq(w, &waiter));
+        let notified = waiter.lock().notified;
+        drop(state);
+        // Уведомление для одного ожидающего не должно потеряться вместе с нами
+        if notified {
+            self.notify.notify_one();
+        }
+    }
+}
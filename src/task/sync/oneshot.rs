@@ -0,0 +1,85 @@
+//! Одноразовый канал: ровно одно значение от отправителя к получателю.
+
+use alloc::sync::Arc;
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct Inner<T> {
+    value: Option<T>,
+    sender_alive: bool,
+    receiver_alive: bool,
+    waker: Option<Waker>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(Inner {
+        value: None,
+        sender_alive: true,
+        receiver_alive: true,
+        waker: None,
+    }));
+    (Sender { inner: inner.clone() }, Receiver { inner })
+}
+
+pub struct Sender<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Возвращает значение обратно, если получатель уже отброшен.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut inner = self.inner.lock();
+        if !inner.receiver_alive {
+            return Err(value);
+        }
+        inner.value = Some(value);
+        Ok(())
+        // drop(self) разбудит получателя
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.inner.lock().receiver_alive
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut inner = self.inner.lock();
+            inner.sender_alive = false;
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Future со значением; `Err(RecvError)`, если отправитель отброшен, ничего не отправив.
+pub struct Receiver<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.inner.lock();
+        if let Some(value) = inner.value.take() {
+            Poll::Ready(Ok(value))
+        } else if !inner.sender_alive {
+            Poll::Ready(Err(RecvError))
+        } else {
+            inner.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.lock().receiver_alive = false;
+    }
+}
@@ -0,0 +1,81 @@
+use super::WaitQueue;
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use spin::Mutex;
+
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore { state: Mutex::new(State { permits, waiters: WaitQueue::new() }) }
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire { semaphore: self, waker: None }
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.state.lock();
+        state.permits += n;
+        for _ in 0..n {
+            state.waiters.wake_one();
+        }
+    }
+}
+
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    // Waker, оставленный в очереди ожидающих; None, если мы не ждём
+    waker: Option<Waker>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let mut state = semaphore.state.lock();
+        if state.permits > 0 {
+            state.permits -= 1;
+            if let Some(waker) = self.waker.take() {
+                state.waiters.remove(&waker);
+            }
+            Poll::Ready(SemaphorePermit { semaphore })
+        } else {
+            state.waiters.register(cx.waker());
+            self.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let waker = match self.waker.take() {
+            Some(waker) => waker,
+            None => return,
+        };
+        let mut state = self.semaphore.state.lock();
+        state.waiters.remove(&waker);
+        // Нас могли разбудить под освободившееся разрешение: передаём пробуждение дальше
+        if state.permits > 0 {
+            state.waiters.wake_one();
+        }
+    }
+}
+
+/// Разрешение возвращается семафору при drop.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(1);
+    }
+}
@@ -0,0 +1,56 @@
+use core::arch::global_asm;
+
+// Сохраняем callee-saved регистры и RFLAGS на стеке текущего потока,
+// переключаем rsp и восстанавливаем то же самое со стека нового потока.
+global_asm!(
+    ".global tm_switch_context",
+    "tm_switch_context:",
+    "    push rbp",
+    "    push rbx",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    pushfq",
+    "    mov [rdi], rsp",
+    "    mov rsp, rsi",
+    "    popfq",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbx",
+    "    pop rbp",
+    "    ret",
+    "",
+    ".global tm_thread_trampoline",
+    "tm_thread_trampoline:",
+    "    mov rdi, r12",
+    "    call r13",
+    "    ud2",
+);
+
+extern "C" {
+    fn tm_switch_context(old_rsp: *mut u64, new_rsp: u64);
+    fn tm_thread_trampoline();
+}
+
+pub unsafe fn switch(old_rsp: *mut u64, new_rsp: u64) {
+    tm_switch_context(old_rsp, new_rsp);
+}
+
+/// Готовит стек нового потока так, чтобы первый `switch` на него
+/// "вернулся" в трамплин, который вызовет `entry(arg)`.
+pub unsafe fn init_stack(stack_top: u64, entry: extern "C" fn(usize) -> !, arg: usize) -> u64 {
+    let top = stack_top & !0xF;
+    let frame = (top - 8 * 8) as *mut u64;
+    frame.add(0).write(0x2); // rflags: IF=0, прерывания включит сам поток
+    frame.add(1).write(0); // r15
+    frame.add(2).write(0); // r14
+    frame.add(3).write(entry as usize as u64); // r13
+    frame.add(4).write(arg as u64); // r12
+    frame.add(5).write(0); // rbx
+    frame.add(6).write(0); // rbp
+    frame.add(7).write(tm_thread_trampoline as unsafe extern "C" fn() as usize as u64);
+    frame as u64
+}
@@ -0,0 +1,314 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::interrupts::TICKS;
+
+mod context;
+pub mod scheduler;
+
+use scheduler::Scheduler;
+
+pub const STACK_SIZE: usize = 4096 * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_u64(id: u64) -> Self { ThreadId(id) }
+
+    pub fn as_u64(self) -> u64 { self.0 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Runnable,
+    Running,
+    Sleeping(u64),
+    Finished,
+}
+
+impl ThreadState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThreadState::Runnable => "runnable",
+            ThreadState::Running => "running",
+            ThreadState::Sleeping(_) => "sleeping",
+            ThreadState::Finished => "finished",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Priority {
+    Idle = 0,
+    Low = 1,
+    Normal = 2,
+    High = 3,
+}
+
+impl Priority {
+    pub const COUNT: usize = 4;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Idle => "idle",
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Priority, ()> {
+        match s {
+            "idle" => Ok(Priority::Idle),
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    state: ThreadState,
+    priority: Priority,
+    cpu_ticks: u64,
+    // Поток попросили завершиться; он выйдет сам в безопасной точке
+    kill_pending: bool,
+    rsp: u64,
+    // None у загрузочного потока: он работает на стеке от bootloader
+    _stack: Option<Box<[u8]>>,
+}
+
+impl Thread {
+    fn new(name: &'static str, priority: Priority, stack: Option<Box<[u8]>>, rsp: u64) -> Box<Thread> {
+        Box::new(Thread {
+            id: ThreadId::new(),
+            name,
+            state: ThreadState::Runnable,
+            priority,
+            cpu_ticks: 0,
+            kill_pending: false,
+            rsp,
+            _stack: stack,
+        })
+    }
+}
+
+lazy_static! {
+    static ref SCHEDULER: Mutex<Scheduler> = {
+        let mut boot = Thread::new("kernel", Priority::Normal, None, 0);
+        boot.state = ThreadState::Running;
+        Mutex::new(Scheduler::new(boot))
+    };
+}
+
+pub fn init() {
+    lazy_static::initialize(&SCHEDULER);
+    // Всегда есть кого запустить, даже если все остальные потоки спят
+    spawn_with_priority("idle", Priority::Idle, || loop { x86_64::instructions::hlt(); });
+}
+
+pub fn spawn<F>(name: &'static str, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_priority(name, Priority::Normal, f)
+}
+
+pub fn spawn_with_priority<F>(name: &'static str, priority: Priority, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    reap();
+    let entry: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+    let stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let stack_top = stack.as_ptr() as u64 + STACK_SIZE as u64;
+    let rsp = unsafe { context::init_stack(stack_top, thread_start, Box::into_raw(entry) as usize) };
+    let thread = Thread::new(name, priority, Some(stack), rsp);
+    let id = thread.id;
+    interrupts::without_interrupts(|| SCHEDULER.lock().add(thread));
+    wake_bsp();
+    id
+}
+
+// Потоки выполняются только на BSP; если он простаивает в tickless-режиме, будим
+fn wake_bsp() {
+    if crate::smp::cpu_index() != 0 {
+        crate::smp::wake_cpu(0);
+    }
+}
+
+/// Освобождает стеки завершившихся потоков. Только с включёнными прерываниями и вне
+/// замка планировщика: вытесненный поток мог остаться с замком кучи, и освобождение
+/// памяти при выключенных прерываниях ждало бы его вечно. Иначе уборка откладывается
+/// до следующего вызова.
+pub fn reap() {
+    if !interrupts::are_enabled() {
+        return;
+    }
+    while let Some(thread) = interrupts::without_interrupts(|| SCHEDULER.lock().take_finished()) {
+        drop(thread);
+    }
+}
+
+extern "C" fn thread_start(entry: usize) -> ! {
+    let f = unsafe { Box::from_raw(entry as *mut Box<dyn FnOnce() + Send>) };
+    interrupts::enable();
+    f();
+    exit();
+}
+
+// Потоки переключаются только на BSP; на AP работает лишь executor, и текущего потока там нет
+fn on_bsp() -> bool {
+    crate::smp::cpu_index() == 0
+}
+
+pub fn exit() -> ! {
+    assert!(on_bsp(), "thread::exit called on an AP");
+    interrupts::disable();
+    SCHEDULER.lock().current().state = ThreadState::Finished;
+    schedule();
+    unreachable!("finished thread was scheduled again");
+}
+
+/// Просит поток завершиться. Насильно его не снимаем: он мог держать замки.
+/// Поток выходит сам в ближайшей безопасной точке (`yield_now` или `sleep`);
+/// спящий ради этого будится. Поток, который никогда не уступает процессор,
+/// закончится только вместе со своей функцией.
+pub fn kill(id: ThreadId) -> bool {
+    if Some(id) == current_id() {
+        exit();
+    }
+    let found = interrupts::without_interrupts(|| {
+        match SCHEDULER.lock().get_mut(id) {
+            Some(thread) if thread.state != ThreadState::Finished => {
+                thread.kill_pending = true;
+                if let ThreadState::Sleeping(_) = thread.state {
+                    thread.state = ThreadState::Runnable;
+                }
+                true
+            }
+            _ => false,
+        }
+    });
+    if found {
+        wake_bsp();
+    }
+    found
+}
+
+// Безопасная точка: поток не внутри чужого кода и не держит замков планировщика
+fn exit_if_killed() {
+    if interrupts::without_interrupts(|| SCHEDULER.lock().current().kill_pending) {
+        exit();
+    }
+}
+
+/// Поток, выполняющийся сейчас; на AP потоков нет — `None`.
+pub fn current_id() -> Option<ThreadId> {
+    if !on_bsp() {
+        return None;
+    }
+    Some(interrupts::without_interrupts(|| SCHEDULER.lock().current().id))
+}
+
+/// Отдаёт процессор другим потокам; на AP ничего не делает.
+pub fn yield_now() {
+    if !on_bsp() {
+        return;
+    }
+    interrupts::without_interrupts(schedule);
+    exit_if_killed();
+}
+
+/// Усыпляет текущий поток (не задачу executor'а) на `ms` тиков.
+pub fn sleep(ms: u64) {
+    assert!(on_bsp(), "thread::sleep called on an AP");
+    let until = TICKS.load(Ordering::Relaxed) + ms;
+    interrupts::without_interrupts(|| {
+        SCHEDULER.lock().current().state = ThreadState::Sleeping(until);
+        schedule();
+    });
+    exit_if_killed();
+}
+
+pub fn set_priority(id: ThreadId, priority: Priority) -> bool {
+    interrupts::without_interrupts(|| {
+        SCHEDULER.lock().get_mut(id).map(|t| t.priority = priority).is_some()
+    })
+}
+
+pub fn set_time_slice(priority: Priority, ticks: u64) {
+    interrupts::without_interrupts(|| SCHEDULER.lock().set_time_slice(priority, ticks));
+}
+
+/// Ближайший тик, когда планировщику нужно снова получить управление:
+/// сейчас, если кроме текущего есть готовые потоки, иначе — когда проснётся спящий.
+pub fn next_deadline() -> Option<u64> {
+    let now = TICKS.load(Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current().id;
+        scheduler.threads()
+            .filter(|t| t.id != current && t.priority != Priority::Idle)
+            .filter_map(|t| match t.state {
+                ThreadState::Runnable => Some(now),
+                ThreadState::Sleeping(until) => Some(until),
+                _ => None,
+            })
+            .min()
+    })
+}
+
+/// Вызывается из обработчика таймера (прерывания уже выключены, EOI отправлен).
+pub(crate) fn on_tick() {
+    let now = TICKS.load(Ordering::Relaxed);
+    let expired = match SCHEDULER.try_lock() {
+        Some(mut scheduler) => scheduler.tick(now),
+        None => false,
+    };
+    if expired {
+        schedule();
+    }
+}
+
+// Должна вызываться с выключенными прерываниями.
+fn schedule() {
+    let switch = match SCHEDULER.try_lock() {
+        Some(mut scheduler) => scheduler.switch_next(),
+        None => None,
+    };
+    if let Some((old_rsp, new_rsp)) = switch {
+        unsafe { context::switch(old_rsp, new_rsp) };
+    }
+}
+
+pub fn list_threads() {
+    use crate::println;
+    let threads: Vec<(ThreadId, &'static str, ThreadState, Priority, u64)> =
+        interrupts::without_interrupts(|| {
+            SCHEDULER.lock().threads()
+                .map(|t| (t.id, t.name, t.state, t.priority, t.cpu_ticks))
+                .collect()
+        });
+    println!(" TID  NAME         STATE      PRIO    CPU(ms)");
+    for (id, name, state, priority, cpu) in threads {
+        println!("{:>4}  {:<12} {:<10} {:<7} {}", id.as_u64(), name, state.name(), priority.name(), cpu);
+    }
+}
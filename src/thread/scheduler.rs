@@ -0,0 +1,104 @@
+use super::{Priority, Thread, ThreadId, ThreadState};
+use alloc::{boxed::Box, vec::Vec};
+
+pub const DEFAULT_TIME_SLICES: [u64; Priority::COUNT] = [5, 10, 20, 40];
+
+pub struct Scheduler {
+    // Потоки в куче по отдельности: context::switch сохраняет rsp через указатель
+    // внутрь Thread, и он не должен сдвинуться, если другой CPU добавит поток
+    #[allow(clippy::vec_box)]
+    threads: Vec<Box<Thread>>,
+    current: usize,
+    slice_left: u64,
+    time_slices: [u64; Priority::COUNT],
+}
+
+impl Scheduler {
+    pub fn new(boot_thread: Box<Thread>) -> Self {
+        Scheduler {
+            threads: alloc::vec![boot_thread],
+            current: 0,
+            slice_left: DEFAULT_TIME_SLICES[Priority::Normal as usize],
+            time_slices: DEFAULT_TIME_SLICES,
+        }
+    }
+
+    pub fn current(&mut self) -> &mut Thread {
+        &mut self.threads[self.current]
+    }
+
+    pub fn get_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|t| t.id == id).map(|t| &mut **t)
+    }
+
+    pub fn threads(&self) -> impl Iterator<Item = &Thread> {
+        self.threads.iter().map(|t| &**t)
+    }
+
+    pub fn add(&mut self, thread: Box<Thread>) {
+        self.threads.push(thread);
+    }
+
+    pub fn set_time_slice(&mut self, priority: Priority, ticks: u64) {
+        self.time_slices[priority as usize] = ticks.max(1);
+    }
+
+    /// Вынимает из списка завершившийся поток (кроме текущего, он ещё на своём стеке).
+    /// Сам поток со стеком освобождает вызывающий, уже отпустив замок.
+    pub fn take_finished(&mut self) -> Option<Box<Thread>> {
+        let index = self.threads.iter().enumerate()
+            .position(|(i, t)| t.state == ThreadState::Finished && i != self.current)?;
+        if index < self.current {
+            self.current -= 1;
+        }
+        Some(self.threads.remove(index))
+    }
+
+    /// Учитывает тик: CPU-время текущего потока, пробуждение спящих.
+    /// Возвращает true, если квант текущего потока исчерпан.
+    pub fn tick(&mut self, now: u64) -> bool {
+        self.threads[self.current].cpu_ticks += 1;
+        for thread in self.threads.iter_mut() {
+            if let ThreadState::Sleeping(until) = thread.state {
+                if now >= until {
+                    thread.state = ThreadState::Runnable;
+                }
+            }
+        }
+        self.slice_left = self.slice_left.saturating_sub(1);
+        self.slice_left == 0 || self.higher_priority_waiting()
+    }
+
+    fn higher_priority_waiting(&self) -> bool {
+        let current = self.threads[self.current].priority;
+        self.threads.iter().any(|t| t.state == ThreadState::Runnable && t.priority > current)
+    }
+
+    /// Выбирает поток с наивысшим приоритетом, внутри уровня — round-robin
+    /// начиная с потока после текущего. Возвращает (куда сохранить rsp текущего, rsp следующего).
+    pub fn switch_next(&mut self) -> Option<(*mut u64, u64)> {
+        let len = self.threads.len();
+        let current_runs = self.threads[self.current].state == ThreadState::Running;
+        let mut best: Option<usize> = None;
+        for i in (1..=len).map(|i| (self.current + i) % len) {
+            let t = &self.threads[i];
+            let eligible = t.state == ThreadState::Runnable || (i == self.current && current_runs);
+            if eligible && best.is_none_or(|b| t.priority > self.threads[b].priority) {
+                best = Some(i);
+            }
+        }
+        let next = best?;
+        self.slice_left = self.time_slices[self.threads[next].priority as usize];
+        if next == self.current {
+            return None;
+        }
+        let prev = self.current;
+        if current_runs {
+            self.threads[prev].state = ThreadState::Runnable;
+        }
+        self.threads[next].state = ThreadState::Running;
+        self.current = next;
+        let old_rsp = &mut self.threads[prev].rsp as *mut u64;
+        Some((old_rsp, self.threads[next].rsp))
+    }
+}
@@ -0,0 +1,33 @@
+use core::sync::atomic::Ordering;
+use x86_64::instructions::interrupts;
+use super::ClockSource;
+use crate::{apic, interrupts::TICKS};
+
+/// Тики таймера local APIC плюс прошедшая часть текущего периода.
+/// Частота — откалиброванная по PIT, так что точность не выше, чем у него.
+pub struct ApicTimerClock;
+
+impl ClockSource for ApicTimerClock {
+    fn name(&self) -> &'static str {
+        "apic"
+    }
+
+    fn read(&self) -> u64 {
+        interrupts::without_interrupts(|| {
+            let ticks = TICKS.load(Ordering::Relaxed);
+            ticks * apic::timer_counts_per_ms() as u64 + apic::timer_elapsed_in_period() as u64
+        })
+    }
+
+    fn frequency(&self) -> u64 {
+        apic::timer_counts_per_ms() as u64 * 1000
+    }
+
+    fn rating(&self) -> u32 {
+        150
+    }
+
+    fn is_continuous(&self) -> bool {
+        false
+    }
+}
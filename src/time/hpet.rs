@@ -0,0 +1,68 @@
+use core::ptr;
+use x86_64::{PhysAddr, VirtAddr};
+use super::ClockSource;
+use crate::{acpi::{self, GenericAddress}, memory};
+
+const CAPABILITIES: u64 = 0x000;
+const CONFIG: u64 = 0x010;
+const MAIN_COUNTER: u64 = 0x0F0;
+
+const CONFIG_ENABLE: u64 = 1;
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+// Период больше 100 нс спецификация запрещает
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Главный счётчик HPET.
+pub struct HpetClock {
+    base: VirtAddr,
+    frequency: u64,
+}
+
+impl HpetClock {
+    /// Находит HPET через ACPI и запускает главный счётчик.
+    pub fn init() -> Option<HpetClock> {
+        let info = acpi::hpet()?;
+        // 32-битный счётчик переполняется за минуты, а следить за ним некому
+        if !info.counter_64bit || info.address.space != GenericAddress::SYSTEM_MEMORY {
+            return None;
+        }
+        let base = memory::map_mmio(PhysAddr::new(info.address.address), 1024).ok()?;
+        let mut hpet = HpetClock { base, frequency: 0 };
+        let period = unsafe { hpet.read_reg(CAPABILITIES) } >> 32;
+        if period == 0 || period > MAX_PERIOD_FS {
+            return None;
+        }
+        hpet.frequency = FEMTOS_PER_SEC / period;
+        unsafe {
+            let config = hpet.read_reg(CONFIG);
+            hpet.write_reg(CONFIG, config | CONFIG_ENABLE);
+        }
+        Some(hpet)
+    }
+
+    unsafe fn read_reg(&self, reg: u64) -> u64 {
+        ptr::read_volatile((self.base.as_u64() + reg) as *const u64)
+    }
+
+    unsafe fn write_reg(&self, reg: u64, value: u64) {
+        ptr::write_volatile((self.base.as_u64() + reg) as *mut u64, value);
+    }
+}
+
+impl ClockSource for HpetClock {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn read(&self) -> u64 {
+        unsafe { self.read_reg(MAIN_COUNTER) }
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn rating(&self) -> u32 {
+        250
+    }
+}
@@ -0,0 +1,159 @@
+use alloc::{boxed::Box, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use crate::{apic, cmdline, interrupts::TICKS, println};
+
+mod apic_timer;
+mod hpet;
+mod pit;
+mod tsc;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_TICK: u64 = 1_000_000;
+// Дальше этого tickless-простой не заглядывает, даже если будить некого
+const MAX_IDLE_MS: u64 = 1000;
+
+/// Монотонный аппаратный счётчик с известной частотой.
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+    fn read(&self) -> u64;
+    /// Частота счётчика в Гц.
+    fn frequency(&self) -> u64;
+    /// Чем выше, тем точнее источник; по умолчанию выбирается лучший.
+    fn rating(&self) -> u32;
+    /// Идёт ли счётчик сам по себе, без периодического тика (нужно для tickless).
+    fn is_continuous(&self) -> bool {
+        true
+    }
+}
+
+struct Clock {
+    source: &'static dyn ClockSource,
+    base_counts: u64,
+    base_ns: u64,
+}
+
+static SOURCES: OnceCell<Vec<&'static dyn ClockSource>> = OnceCell::uninit();
+static CLOCK: OnceCell<Clock> = OnceCell::uninit();
+static LAST_NS: AtomicU64 = AtomicU64::new(0);
+static TICKLESS: AtomicBool = AtomicBool::new(false);
+// Периодический тик остановлен, таймер APIC заведён одним выстрелом
+static ONESHOT_ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Собирает источники времени и выбирает лучший (или заданный `clocksource=<name>`).
+/// Вызывается после `apic::init`: от него зависит, кто даёт тики.
+pub fn init() -> Result<&'static str, &'static str> {
+    let mut sources: Vec<&'static dyn ClockSource> = Vec::new();
+    if apic::is_enabled() {
+        sources.push(&apic_timer::ApicTimerClock);
+    } else {
+        sources.push(&pit::PitClock);
+    }
+    if let Some(hpet) = hpet::HpetClock::init() {
+        sources.push(Box::leak(Box::new(hpet)));
+    }
+    // TSC калибруем по лучшему из уже найденных
+    if let Some(tsc) = tsc::TscClock::calibrate(best(&sources)) {
+        sources.push(Box::leak(Box::new(tsc)));
+    }
+
+    let source = cmdline::value("clocksource")
+        .and_then(|name| sources.iter().copied().find(|s| s.name() == name))
+        .unwrap_or_else(|| best(&sources));
+    interrupts::without_interrupts(|| {
+        let clock = Clock {
+            source,
+            base_counts: source.read(),
+            base_ns: TICKS.load(Ordering::Relaxed) * NANOS_PER_TICK,
+        };
+        let _ = CLOCK.try_init_once(|| clock);
+    });
+    let _ = SOURCES.try_init_once(|| sources);
+
+    let nohz = cmdline::value("nohz") != Some("off");
+    TICKLESS.store(nohz && apic::is_enabled() && source.is_continuous(), Ordering::Relaxed);
+    Ok(source.name())
+}
+
+fn best(sources: &[&'static dyn ClockSource]) -> &'static dyn ClockSource {
+    sources.iter().copied().max_by_key(|s| s.rating()).unwrap()
+}
+
+fn to_nanos(counts: u64, frequency: u64) -> u64 {
+    (counts as u128 * NANOS_PER_SEC as u128 / frequency as u128) as u64
+}
+
+/// Наносекунды с загрузки. До `init` — с точностью до тика.
+pub fn now() -> u64 {
+    let ns = match CLOCK.get() {
+        Some(clock) => {
+            let counts = clock.source.read().wrapping_sub(clock.base_counts);
+            clock.base_ns + to_nanos(counts, clock.source.frequency())
+        }
+        None => TICKS.load(Ordering::Relaxed) * NANOS_PER_TICK,
+    };
+    // Тиковые источники на границе тика могут откатиться назад на долю тика
+    ns.max(LAST_NS.fetch_max(ns, Ordering::Relaxed))
+}
+
+/// Активное ожидание; прерывания должны быть включены, если источник тиковый.
+pub fn delay_us(us: u64) {
+    let until = now() + us * 1000;
+    while now() < until {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn is_tickless() -> bool {
+    TICKLESS.load(Ordering::Relaxed)
+}
+
+/// Вызывается из обработчика таймера. Возвращает новое значение `TICKS`.
+pub(crate) fn on_timer_interrupt() -> u64 {
+    if ONESHOT_ARMED.swap(false, Ordering::Relaxed) {
+        leave_oneshot()
+    } else {
+        TICKS.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+// Возвращает периодический тик и догоняет TICKS по источнику времени
+fn leave_oneshot() -> u64 {
+    apic::start_periodic_timer();
+    let ms = now() / NANOS_PER_TICK;
+    TICKS.fetch_max(ms, Ordering::Relaxed).max(ms)
+}
+
+/// Простой до следующего прерывания; вызывать с выключенными прерываниями.
+/// В tickless-режиме периодический тик на это время останавливается,
+/// а таймер заводится одним выстрелом на `deadline` (в тиках), если он есть.
+pub fn idle(deadline: Option<u64>) {
+    let ticks = TICKS.load(Ordering::Relaxed);
+    let sleep_ms = deadline.map_or(MAX_IDLE_MS, |d| d.saturating_sub(ticks)).min(MAX_IDLE_MS);
+    if !is_tickless() || sleep_ms <= 1 {
+        interrupts::enable_and_hlt();
+        return;
+    }
+    ONESHOT_ARMED.store(true, Ordering::Relaxed);
+    apic::start_oneshot_timer(sleep_ms);
+    interrupts::enable_and_hlt();
+    // Разбудило другое прерывание (например, клавиатура) раньше срока
+    interrupts::disable();
+    if ONESHOT_ARMED.swap(false, Ordering::Relaxed) {
+        leave_oneshot();
+    }
+    interrupts::enable();
+}
+
+pub fn list_clocks() {
+    let current = CLOCK.get().map(|c| c.source.name());
+    println!(" NAME      FREQ(Hz)      RATING");
+    for source in SOURCES.get().map_or(&[][..], |s| &s[..]) {
+        let mark = if Some(source.name()) == current { "*" } else { " " };
+        println!("{}{:<9} {:<13} {}", mark, source.name(), source.frequency(), source.rating());
+    }
+    let ns = now();
+    println!("Now: {}.{:09} s, tickless: {}", ns / NANOS_PER_SEC, ns % NANOS_PER_SEC,
+        if is_tickless() { "on" } else { "off" });
+}
@@ -0,0 +1,45 @@
+use core::sync::atomic::Ordering;
+use x86_64::instructions::{interrupts, port::Port};
+use super::ClockSource;
+use crate::interrupts::{PIT_DIVISOR, PIT_FREQUENCY, TICKS};
+
+/// Тики PIT плюс прошедшая часть текущего периода из счётчика канала 0.
+/// Годится, только пока прерывания таймера даёт сам PIT.
+pub struct PitClock;
+
+fn read_counter() -> u16 {
+    let mut cmd: Port<u8> = Port::new(0x43);
+    let mut data: Port<u8> = Port::new(0x40);
+    unsafe {
+        cmd.write(0x00); // Защёлкнуть счётчик канала 0
+        let lo = data.read() as u16;
+        let hi = data.read() as u16;
+        (hi << 8) | lo
+    }
+}
+
+impl ClockSource for PitClock {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn read(&self) -> u64 {
+        interrupts::without_interrupts(|| {
+            let ticks = TICKS.load(Ordering::Relaxed);
+            let elapsed = PIT_DIVISOR.saturating_sub(read_counter());
+            ticks * PIT_DIVISOR as u64 + elapsed as u64
+        })
+    }
+
+    fn frequency(&self) -> u64 {
+        PIT_FREQUENCY
+    }
+
+    fn rating(&self) -> u32 {
+        100
+    }
+
+    fn is_continuous(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,61 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use x86_64::instructions::interrupts;
+use super::ClockSource;
+
+const CALIBRATION_MS: u64 = 10;
+
+/// Счётчик тактов процессора; частота измеряется по другому источнику.
+pub struct TscClock {
+    frequency: u64,
+    invariant: bool,
+}
+
+impl TscClock {
+    pub fn calibrate(reference: &dyn ClockSource) -> Option<TscClock> {
+        if __cpuid(1).edx & (1 << 4) == 0 {
+            return None;
+        }
+        // Пара отсчётов без прерываний между ними; ждём при этом с включёнными,
+        // иначе тиковые источники стоят на месте
+        let sample = || interrupts::without_interrupts(|| (reference.read(), unsafe { _rdtsc() }));
+        let wait = reference.frequency() * CALIBRATION_MS / 1000;
+        let (ref_start, tsc_start) = sample();
+        let (ref_end, tsc_end) = loop {
+            let (ref_now, tsc_now) = sample();
+            if ref_now.wrapping_sub(ref_start) >= wait {
+                break (ref_now, tsc_now);
+            }
+            core::hint::spin_loop();
+        };
+        let (tsc, counts) = (tsc_end - tsc_start, ref_end.wrapping_sub(ref_start));
+        if counts == 0 {
+            return None;
+        }
+        let frequency = (tsc as u128 * reference.frequency() as u128 / counts as u128) as u64;
+        Some(TscClock { frequency, invariant: is_invariant() })
+    }
+}
+
+// Invariant TSC идёт с постоянной частотой независимо от P/C-состояний
+fn is_invariant() -> bool {
+    __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+impl ClockSource for TscClock {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn read(&self) -> u64 {
+        unsafe { _rdtsc() }
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    // Без invariant TSC частота может плавать — тогда лучше HPET
+    fn rating(&self) -> u32 {
+        if self.invariant { 300 } else { 50 }
+    }
+}
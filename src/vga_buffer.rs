@@ -1,6 +1,6 @@
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::lock::IrqSafeMutex;
 use volatile::Volatile;
 
 #[allow(dead_code)]
@@ -153,7 +153,7 @@ impl fmt::Write for Writer {
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    pub static ref WRITER: IrqSafeMutex<Writer> = IrqSafeMutex::new("vga_writer", Writer {
         column_position: 0,
         color_code: ColorCode::new(Color::LightBlue, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
@@ -189,35 +189,34 @@ pub fn set_color(foreground: Color, background: Color) {
     WRITER.lock().set_color(foreground, background);
 }
 
+/// Рисует часы в углу экрана; вызывается из отложенной работы, не из прерывания.
 pub fn print_timer(seconds: u64) {
-    // try_lock() возвращает Some(guard), если замок свободен, и None, если занят.
-    if let Some(mut writer) = WRITER.try_lock() {
-        let old_color = writer.color_code;
-        writer.set_color(Color::Black, Color::Cyan);
+    let mut writer = WRITER.lock();
+    let old_color = writer.color_code;
+    writer.set_color(Color::Black, Color::Cyan);
 
-        let hours = seconds / 3600;
-        let mins = (seconds % 3600) / 60;
-        let secs = seconds % 60;
+    let hours = seconds / 3600;
+    let mins = (seconds % 3600) / 60;
+    let secs = seconds % 60;
 
-        let col_start = 65;
-        let row = 0;
+    let col_start = 65;
+    let row = 0;
 
-        let prefix = "TIME: ";
-        for (i, &byte) in prefix.as_bytes().iter().enumerate() {
-            writer.write_byte_at(col_start + i, row, byte);
-        }
+    let prefix = "TIME: ";
+    for (i, &byte) in prefix.as_bytes().iter().enumerate() {
+        writer.write_byte_at(col_start + i, row, byte);
+    }
 
-        writer.write_byte_at(col_start + 6, row, (hours / 10) as u8 + b'0');
-        writer.write_byte_at(col_start + 7, row, (hours % 10) as u8 + b'0');
-        writer.write_byte_at(col_start + 8, row, b':');
-        writer.write_byte_at(col_start + 9, row, (mins / 10) as u8 + b'0');
-        writer.write_byte_at(col_start + 10, row, (mins % 10) as u8 + b'0');
-        writer.write_byte_at(col_start + 11, row, b':');
-        writer.write_byte_at(col_start + 12, row, (secs / 10) as u8 + b'0');
-        writer.write_byte_at(col_start + 13, row, (secs % 10) as u8 + b'0');
+    writer.write_byte_at(col_start + 6, row, (hours / 10) as u8 + b'0');
+    writer.write_byte_at(col_start + 7, row, (hours % 10) as u8 + b'0');
+    writer.write_byte_at(col_start + 8, row, b':');
+    writer.write_byte_at(col_start + 9, row, (mins / 10) as u8 + b'0');
+    writer.write_byte_at(col_start + 10, row, (mins % 10) as u8 + b'0');
+    writer.write_byte_at(col_start + 11, row, b':');
+    writer.write_byte_at(col_start + 12, row, (secs / 10) as u8 + b'0');
+    writer.write_byte_at(col_start + 13, row, (secs % 10) as u8 + b'0');
 
-        writer.color_code = old_color;
-    }
+    writer.color_code = old_color;
 }
 
 pub fn draw_header() {
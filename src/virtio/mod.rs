@@ -0,0 +1,296 @@
+//! Транспорт virtio поверх PCI: legacy (регистры в портах BAR0) и современный
+//! virtio 1.0 (области, описанные vendor capability, в памяти BAR).
+
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+use crate::{memory, pci::{Bar, PciDevice}};
+
+pub mod queue;
+
+pub use queue::{Descriptor, VirtQueue, DESC_F_NEXT, DESC_F_WRITE};
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 128;
+
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// Вектор MSI-X "не использовать".
+pub const NO_VECTOR: u16 = 0xFFFF;
+
+const ISR_QUEUE: u8 = 1;
+const ISR_CONFIG: u8 = 2;
+
+// Legacy-регистры относительно порта BAR0
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+const LEGACY_CONFIG_VECTOR: u16 = 0x14;
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+// Конфигурация устройства сдвигается на 4 байта, когда включён MSI-X
+const LEGACY_DEVICE_CONFIG: u16 = 0x14;
+const LEGACY_DEVICE_CONFIG_MSIX: u16 = 0x18;
+
+// Типы vendor capability virtio 1.0
+const CAP_VENDOR: u8 = 0x09;
+const CFG_COMMON: u8 = 1;
+const CFG_NOTIFY: u8 = 2;
+const CFG_ISR: u8 = 3;
+const CFG_DEVICE: u8 = 4;
+
+// Поля common configuration
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0C;
+const COMMON_CONFIG_VECTOR: u64 = 0x10;
+const COMMON_STATUS: u64 = 0x14;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_VECTOR: u64 = 0x1A;
+const COMMON_QUEUE_ENABLE: u64 = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+// Современные устройства сами выбирают размер очереди только сверху
+const MAX_QUEUE_SIZE: u16 = 128;
+
+pub enum Transport {
+    Legacy {
+        io: u16,
+        msix: bool,
+    },
+    Modern {
+        common: VirtAddr,
+        notify: VirtAddr,
+        notify_multiplier: u32,
+        isr: VirtAddr,
+        device: VirtAddr,
+    },
+}
+
+unsafe fn read<T>(addr: VirtAddr) -> T {
+    core::ptr::read_volatile(addr.as_ptr())
+}
+
+unsafe fn write<T>(addr: VirtAddr, value: T) {
+    core::ptr::write_volatile(addr.as_mut_ptr(), value)
+}
+
+impl Transport {
+    /// Современный транспорт, если у функции есть его capability, иначе legacy через BAR0.
+    pub fn new(dev: &PciDevice) -> Result<Transport, &'static str> {
+        match Self::modern(dev) {
+            Some(transport) => Ok(transport),
+            None => match dev.bars[0] {
+                Some(Bar::Io { port, .. }) => Ok(Transport::Legacy { io: port, msix: false }),
+                _ => Err("no usable virtio transport"),
+            },
+        }
+    }
+
+    fn modern(dev: &PciDevice) -> Option<Transport> {
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+        for cap in dev.capabilities_of(CAP_VENDOR) {
+            let a = dev.address;
+            let cfg_type = a.read_u8(cap + 3);
+            let bar = a.read_u8(cap + 4) as usize;
+            let offset = a.read_u32(cap + 8) as u64;
+            let length = a.read_u32(cap + 12) as u64;
+            let base = match dev.bars.get(bar).copied().flatten() {
+                Some(Bar::Memory { address, .. }) => address,
+                _ => continue,
+            };
+            let map = || memory::map_mmio(PhysAddr::new(base + offset), length).ok();
+            match cfg_type {
+                CFG_COMMON if common.is_none() => common = map(),
+                CFG_NOTIFY if notify.is_none() => {
+                    notify = map();
+                    notify_multiplier = a.read_u32(cap + 16);
+                }
+                CFG_ISR if isr.is_none() => isr = map(),
+                CFG_DEVICE if device.is_none() => device = map(),
+                _ => {}
+            }
+        }
+        Some(Transport::Modern { common: common?, notify: notify?, notify_multiplier, isr: isr?, device: device? })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    pub fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe { Port::new(io + LEGACY_STATUS).read() },
+            Transport::Modern { common, .. } => unsafe { read(common + COMMON_STATUS) },
+        }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe { Port::new(io + LEGACY_STATUS).write(status) },
+            Transport::Modern { common, .. } => unsafe { write(common + COMMON_STATUS, status) },
+        }
+    }
+
+    pub fn add_status(&self, bits: u8) {
+        self.set_status(self.status() | bits);
+    }
+
+    /// Сброс, затем ACKNOWLEDGE и DRIVER: устройство найдено и драйвер его знает.
+    pub fn begin_init(&self) {
+        self.set_status(0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+        self.add_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+    }
+
+    /// Принимает пересечение `wanted` с возможностями устройства; для современного
+    /// транспорта VERSION_1 обязателен. Возвращает принятые биты.
+    pub fn negotiate(&self, wanted: u64) -> Result<u64, &'static str> {
+        match *self {
+            Transport::Legacy { io, .. } => {
+                let offered = unsafe { Port::<u32>::new(io + LEGACY_DEVICE_FEATURES).read() } as u64;
+                let accepted = offered & wanted & 0xFFFF_FFFF;
+                unsafe { Port::<u32>::new(io + LEGACY_DRIVER_FEATURES).write(accepted as u32) };
+                Ok(accepted)
+            }
+            Transport::Modern { common, .. } => unsafe {
+                let mut offered = 0u64;
+                for half in 0..2u32 {
+                    write(common + COMMON_DEVICE_FEATURE_SELECT, half);
+                    offered |= (read::<u32>(common + COMMON_DEVICE_FEATURE) as u64) << (32 * half);
+                }
+                let accepted = offered & (wanted | F_VERSION_1);
+                if accepted & F_VERSION_1 == 0 {
+                    return Err("device does not offer VIRTIO_F_VERSION_1");
+                }
+                for half in 0..2u32 {
+                    write(common + COMMON_DRIVER_FEATURE_SELECT, half);
+                    write(common + COMMON_DRIVER_FEATURE, (accepted >> (32 * half)) as u32);
+                }
+                self.add_status(STATUS_FEATURES_OK);
+                if self.status() & STATUS_FEATURES_OK == 0 {
+                    return Err("device rejected the feature set");
+                }
+                Ok(accepted)
+            },
+        }
+    }
+
+    /// Размер очереди, который стоит выделить (0 — очереди нет).
+    pub fn queue_size(&self, index: u16) -> u16 {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe {
+                Port::<u16>::new(io + LEGACY_QUEUE_SELECT).write(index);
+                // В legacy размер задаёт устройство
+                Port::<u16>::new(io + LEGACY_QUEUE_SIZE).read()
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write(common + COMMON_QUEUE_SELECT, index);
+                read::<u16>(common + COMMON_QUEUE_SIZE).min(MAX_QUEUE_SIZE)
+            },
+        }
+    }
+
+    /// Сообщает устройству адреса колец и включает очередь.
+    /// Вектор MSI-X проверяется обратным чтением: устройство может его не принять.
+    pub fn setup_queue(&self, index: u16, queue: &VirtQueue, vector: u16) -> Result<(), &'static str> {
+        match *self {
+            Transport::Legacy { io, msix } => unsafe {
+                Port::<u16>::new(io + LEGACY_QUEUE_SELECT).write(index);
+                if msix {
+                    let mut port = Port::<u16>::new(io + LEGACY_QUEUE_VECTOR);
+                    port.write(vector);
+                    if port.read() != vector {
+                        return Err("device rejected the MSI-X vector");
+                    }
+                }
+                Port::<u32>::new(io + LEGACY_QUEUE_PFN).write((queue.desc_address().as_u64() >> 12) as u32);
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write(common + COMMON_QUEUE_SELECT, index);
+                write(common + COMMON_QUEUE_SIZE, queue.size());
+                if vector != NO_VECTOR {
+                    write(common + COMMON_QUEUE_VECTOR, vector);
+                    if read::<u16>(common + COMMON_QUEUE_VECTOR) != vector {
+                        return Err("device rejected the MSI-X vector");
+                    }
+                }
+                write(common + COMMON_QUEUE_DESC, queue.desc_address().as_u64());
+                write(common + COMMON_QUEUE_DRIVER, queue.avail_address().as_u64());
+                write(common + COMMON_QUEUE_DEVICE, queue.used_address().as_u64());
+                write(common + COMMON_QUEUE_ENABLE, 1u16);
+            },
+        }
+        Ok(())
+    }
+
+    /// Включён ли MSI-X в PCI: от этого в legacy зависит раскладка регистров.
+    pub fn set_msix(&mut self, enabled: bool) {
+        if let Transport::Legacy { msix, .. } = self {
+            *msix = enabled;
+        }
+    }
+
+    /// Прерывания об изменении конфигурации не нужны.
+    pub fn disable_config_vector(&self) {
+        match *self {
+            Transport::Legacy { io, msix: true } => unsafe { Port::<u16>::new(io + LEGACY_CONFIG_VECTOR).write(NO_VECTOR) },
+            Transport::Legacy { .. } => {}
+            Transport::Modern { common, .. } => unsafe { write(common + COMMON_CONFIG_VECTOR, NO_VECTOR) },
+        }
+    }
+
+    pub fn notify(&self, index: u16) {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe { Port::<u16>::new(io + LEGACY_QUEUE_NOTIFY).write(index) },
+            Transport::Modern { common, notify, notify_multiplier, .. } => unsafe {
+                write(common + COMMON_QUEUE_SELECT, index);
+                let offset = read::<u16>(common + COMMON_QUEUE_NOTIFY_OFF) as u64 * notify_multiplier as u64;
+                write(notify + offset, index);
+            },
+        }
+    }
+
+    /// Читает и тем самым сбрасывает ISR; true, если прерывание от этого устройства.
+    pub fn ack_interrupt(&self) -> bool {
+        let isr: u8 = match *self {
+            Transport::Legacy { io, .. } => unsafe { Port::new(io + LEGACY_ISR).read() },
+            Transport::Modern { isr, .. } => unsafe { read(isr) },
+        };
+        isr & (ISR_QUEUE | ISR_CONFIG) != 0
+    }
+
+    /// 32-битное слово конфигурации устройства по смещению `offset`.
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        match *self {
+            Transport::Legacy { io, msix } => {
+                let base = if msix { LEGACY_DEVICE_CONFIG_MSIX } else { LEGACY_DEVICE_CONFIG };
+                unsafe { Port::<u32>::new(io + base + offset).read() }
+            }
+            Transport::Modern { device, .. } => unsafe { read(device + offset as u64) },
+        }
+    }
+
+    pub fn read_config_u64(&self, offset: u16) -> u64 {
+        self.read_config_u32(offset) as u64 | (self.read_config_u32(offset + 4) as u64) << 32
+    }
+
+    pub fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+}
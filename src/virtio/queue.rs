@@ -0,0 +1,100 @@
+//! Split virtqueue: таблица дескрипторов, available- и used-кольца в одном
+//! физически смежном DMA-буфере в раскладке legacy (used выровнено на страницу).
+
+use core::sync::atomic::{fence, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::memory;
+
+pub const DESC_F_NEXT: u16 = 1;
+/// Буфер пишет устройство.
+pub const DESC_F_WRITE: u16 = 2;
+
+const PAGE_SIZE: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+pub struct VirtQueue {
+    size: u16,
+    phys: PhysAddr,
+    base: VirtAddr,
+    avail_offset: usize,
+    used_offset: usize,
+    last_used: u16,
+}
+
+// Кольца лежат в памяти, которой владеет только очередь
+unsafe impl Send for VirtQueue {}
+
+fn align_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+impl VirtQueue {
+    pub fn new(size: u16) -> Option<VirtQueue> {
+        let size_usize = size as usize;
+        let avail_offset = 16 * size_usize;
+        let used_offset = align_up(avail_offset + 6 + 2 * size_usize, PAGE_SIZE);
+        let total = used_offset + align_up(6 + 8 * size_usize, PAGE_SIZE);
+        let phys = memory::allocate_dma(total / PAGE_SIZE)?;
+        Some(VirtQueue { size, phys, base: memory::phys_to_virt(phys), avail_offset, used_offset, last_used: 0 })
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_address(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn avail_address(&self) -> PhysAddr {
+        self.phys + self.avail_offset as u64
+    }
+
+    pub fn used_address(&self) -> PhysAddr {
+        self.phys + self.used_offset as u64
+    }
+
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        (self.base + offset as u64).as_mut_ptr()
+    }
+
+    pub fn set_descriptor(&mut self, index: u16, desc: Descriptor) {
+        assert!(index < self.size);
+        unsafe { self.ptr::<Descriptor>(16 * index as usize).write_volatile(desc) };
+    }
+
+    /// Отдаёт устройству цепочку, начинающуюся с дескриптора `head`.
+    pub fn push_avail(&mut self, head: u16) {
+        let idx_ptr = self.ptr::<u16>(self.avail_offset + 2);
+        unsafe {
+            let idx = idx_ptr.read_volatile();
+            let slot = self.avail_offset + 4 + 2 * (idx % self.size) as usize;
+            self.ptr::<u16>(slot).write_volatile(head);
+            // Запись в кольцо должна стать видна раньше нового индекса
+            fence(Ordering::SeqCst);
+            idx_ptr.write_volatile(idx.wrapping_add(1));
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    /// Следующая завершённая цепочка: (головной дескриптор, сколько байт записало устройство).
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let idx = unsafe { self.ptr::<u16>(self.used_offset + 2).read_volatile() };
+        if idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let slot = self.used_offset + 4 + 8 * (self.last_used % self.size) as usize;
+        let (id, len) = unsafe { (self.ptr::<u32>(slot).read_volatile(), self.ptr::<u32>(slot + 4).read_volatile()) };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((id as u16, len))
+    }
+}